@@ -1,17 +1,28 @@
 use anyhow::Result;
 use core::message::{MediaKind, MediaPacket};
-use core::transport::{IncomingBroadcast, Message, OutgoingBroadcast};
+use core::metrics::{GaugeGuard, METRICS};
+use core::resync::Resync;
+use core::stats::StatsCollector;
+use core::transport::{IncomingBroadcast, InitData, JoinResp, Message, OutgoingBroadcast};
+use core::{ChannelMessage, ManagerHandle};
+use std::time::{Duration, Instant};
+use tokio::time;
 
 pub struct Channel {
     name: String,
     incoming: IncomingBroadcast,
     outgoing: OutgoingBroadcast,
+    manager_handle: ManagerHandle,
+    idle_timeout: Option<Duration>,
+    idle_since: Option<Instant>,
     metadata: Option<MediaPacket>,
     video_seq_header: Option<MediaPacket>,
     audio_seq_header: Option<MediaPacket>,
     gop: Option<Vec<MediaPacket>>,
     closing: bool,
+    resync: Resync,
     full_gop: bool,
+    stats: StatsCollector,
 }
 
 impl Channel {
@@ -19,52 +30,131 @@ impl Channel {
         name: String,
         incoming: IncomingBroadcast,
         outgoing: OutgoingBroadcast,
+        manager_handle: ManagerHandle,
+        idle_timeout: Option<Duration>,
         full_gop: bool,
     ) -> Self {
         Self {
             name,
             incoming,
             outgoing,
+            manager_handle,
+            idle_timeout,
+            idle_since: None,
             metadata: None,
             video_seq_header: None,
             audio_seq_header: None,
             gop: None,
             closing: false,
+            resync: Resync::default(),
             full_gop,
+            stats: StatsCollector::new(),
         }
     }
 
     pub async fn run(mut self) {
+        let _channel = GaugeGuard::new(METRICS.channels.clone());
+        let mut interval = time::interval(Duration::from_secs(1));
         while !self.closing {
-            if let Some(message) = self.incoming.recv().await {
-                self.handle_message(message).await;
+            tokio::select! {
+                message = self.incoming.recv() => match message {
+                    Some(message) => self.handle_message(message).await,
+                    None => break,
+                },
+                _ = interval.tick() => {
+                    self.check_idle();
+                    self.report_stats();
+                }
+            }
+        }
+    }
+
+    fn report_stats(&mut self) {
+        _ = self.manager_handle.send(ChannelMessage::Stats((
+            self.name.clone(),
+            self.stats.stats(),
+        )));
+    }
+
+    fn check_idle(&mut self) {
+        let idle_timeout = match self.idle_timeout {
+            Some(idle_timeout) => idle_timeout,
+            None => return,
+        };
+        if self.outgoing.receiver_count() != 0 {
+            self.idle_since = None;
+            return;
+        }
+        let idle_since = *self.idle_since.get_or_insert_with(Instant::now);
+        if idle_since.elapsed() >= idle_timeout {
+            log::info!(
+                "channel {} has no player for {:?}, release it",
+                self.name,
+                idle_timeout
+            );
+            self.release();
+        }
+    }
+
+    //stop taking messages and hand the channel back to the manager,
+    //dropping the incoming receiver also stops the upstream reader
+    fn release(&mut self) {
+        self.incoming.close();
+        _ = self
+            .manager_handle
+            .send(ChannelMessage::Release(self.name.clone()));
+        //joins raced with the release go back to the manager, which pulls the channel again
+        while let Ok(message) = self.incoming.try_recv() {
+            if let Message::Join(responder) = message {
+                _ = self
+                    .manager_handle
+                    .send(ChannelMessage::Join((self.name.clone(), responder)));
             }
         }
+        self.closing = true;
     }
 
     async fn handle_message(&mut self, message: Message) {
         match message {
-            Message::Packet(_) => unreachable!(),
-            Message::InitData(responder) => {
-                let response = (
-                    self.metadata.clone(),
-                    self.video_seq_header.clone(),
-                    self.audio_seq_header.clone(),
-                    self.gop.clone(),
-                );
-                if responder.send(response).is_err() {
-                    log::error!("Failed to send init data");
+            Message::Packet(_) | Message::Publish(_) => unreachable!(),
+            Message::Join(responder) => {
+                let resp = JoinResp {
+                    init_data: InitData {
+                        metadata: self.metadata.clone(),
+                        video_seq_header: self.video_seq_header.clone(),
+                        audio_seq_header: self.audio_seq_header.clone(),
+                        gop: self.gop.clone(),
+                    },
+                    watcher: self.outgoing.subscribe(),
+                };
+                if responder.send(resp).is_err() {
+                    log::error!("Failed to send join response");
                 }
             }
             Message::PacketFromOrigin(packet) => {
+                if !self.resync.accept(
+                    &packet,
+                    &self.metadata,
+                    &self.video_seq_header,
+                    &self.audio_seq_header,
+                ) {
+                    return;
+                }
+                //the replayed gop is not counted twice
+                self.stats.update(&packet);
                 if let Err(e) = self.set_cache(&packet) {
                     log::error!("Failed to set channel cache {}", e);
                 }
                 self.broadcast_packet(packet);
             }
-            Message::Disconnect => {
-                self.closing = true;
+            Message::Reconnected => {
+                log::info!(
+                    "channel {} upstream reconnected, wait for keyframe",
+                    self.name
+                );
+                self.resync.start();
             }
+            Message::Disconnect => self.release(),
         }
     }
 
@@ -1,8 +1,9 @@
 use anyhow::{bail, Result};
 use bytes::Bytes;
 use core::message::{Kind, MediaPacket, MessageInitPayload, MessageInitPayloadKind, ProtoMessage};
-use core::transport::JoinResp;
-use core::{ChannelMessage, ManagerHandle, Message};
+use core::metrics::{GaugeGuard, METRICS, XLIVE};
+use core::transport::{JoinResp, RelayGuard};
+use core::{ChannelMessage, ManagerHandle};
 use futures::{SinkExt, StreamExt};
 use tokio::net::TcpStream;
 use tokio::sync::oneshot;
@@ -18,6 +19,10 @@ pub struct Connection {
     state: State,
     frame: Framed<TcpStream, LengthDelimitedCodec>,
     manager_handle: ManagerHandle,
+    //counts the connection as a publisher or player while it is alive
+    gauge: Option<GaugeGuard>,
+    //every player of this protocol is a cache or edge
+    relay: Option<RelayGuard>,
 }
 
 impl Connection {
@@ -28,6 +33,8 @@ impl Connection {
             state: State::Init,
             id,
             manager_handle,
+            gauge: None,
+            relay: None,
         }
     }
 
@@ -38,6 +45,10 @@ impl Connection {
     }
 
     async fn send(&mut self, packet: MediaPacket) -> Result<()> {
+        METRICS
+            .bytes_sent
+            .with_label_values(&[XLIVE])
+            .inc_by(packet.payload.len() as u64);
         let proto_message: ProtoMessage = packet.into();
         self.frame.send(proto_message.into()).await?;
         Ok(())
@@ -75,57 +86,39 @@ impl Connection {
                             return Ok(());
                         }
 
+                        self.relay = Some(RelayGuard::new(
+                            init_message.app_name.clone(),
+                            self.manager_handle.clone(),
+                        ));
                         self.state = State::Player(init_message.app_name);
+                        self.gauge =
+                            Some(GaugeGuard::new(METRICS.players.with_label_values(&[XLIVE])));
                         //respone join ok
                         log::info!("send proto message ok");
                         self.frame.send(ProtoMessage::new_proto_ok().into()).await?;
 
-                        if let Ok(join_resp) = response.await {
-                            let (session_sender, mut session_receiver, need_init_data) =
-                                match join_resp {
-                                    JoinResp::Local(session_sender, session_receiver) => {
-                                        (session_sender, session_receiver, true)
-                                    }
-                                    JoinResp::Origin(session_sender, session_receiver) => {
-                                        (session_sender, session_receiver, false)
-                                    }
-                                };
-
-                            if need_init_data {
-                                let (request, response) = oneshot::channel();
-                                if session_sender.send(Message::InitData(request)).is_err() {
-                                    self.disconnected("session_sender send failed").await?;
-                                    return Ok(());
-                                };
-                                if let Ok((meta, video, audio, gop)) = response.await {
-                                    if let Some(m) = meta {
-                                        self.send(m).await?;
-                                    }
-                                    if let Some(v) = video {
-                                        self.send(v).await?;
-                                    }
-                                    if let Some(a) = audio {
-                                        self.send(a).await?;
-                                    }
-                                    if let Some(a) = gop {
-                                        for p in a {
-                                            self.send(p).await?;
-                                        }
-                                    }
-                                }
+                        if let Ok(JoinResp {
+                            init_data,
+                            watcher: mut session_receiver,
+                        }) = response.await
+                        {
+                            for packet in init_data.into_packets() {
+                                self.send(packet).await?;
                             }
                             loop {
                                 use tokio::sync::broadcast::error::RecvError;
                                 match session_receiver.recv().await {
-                                    Ok(data) => {
-                                        let proto_message: ProtoMessage = data.into();
-                                        self.frame.send(proto_message.into()).await?;
-                                    }
+                                    Ok(data) => self.send(data).await?,
                                     Err(RecvError::Closed) => {
                                         self.disconnected("session_receiver is closed").await?;
                                         break;
                                     }
-                                    Err(_) => {}
+                                    Err(RecvError::Lagged(n)) => {
+                                        METRICS
+                                            .lagged_packets
+                                            .with_label_values(&[XLIVE])
+                                            .inc_by(n);
+                                    }
                                 }
                             }
                         } else {
@@ -144,9 +137,14 @@ impl Connection {
 
 impl Drop for Connection {
     fn drop(&mut self) {
-        println!(
-            "connecton {:?},state:{:?} disconnected",
-            self.id, self.state
+        let app_name = match &self.state {
+            State::Init => return,
+            State::Player(app_name) => app_name,
+        };
+        log::info!(
+            "connecton {:?},app name({:?}),state:player disconnected",
+            self.id,
+            app_name
         );
     }
 }
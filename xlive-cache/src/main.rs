@@ -1,22 +1,34 @@
 use anyhow::Result;
 use chrono::Local;
+use core::register::Role;
+use core::register_client::RegisterClientConfig;
 use core::ManagerHandle;
-use core::Upstream;
+use core::{RegisterClient, Upstream};
 use std::io::Write;
+use std::time::Duration;
 use structopt::StructOpt;
 use tokio::net::{TcpListener, TcpStream};
 #[cfg(feature = "monitor")]
 use xlive_cache::monitor::Service;
 use xlive_cache::{conn::Connection, manager::Manager};
 
+//announced to the register, which fills in the ip
+const LISTEN_ADDR: &str = "0.0.0.0:9888";
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "xlive-cache")]
 struct Opt {
+    /// comma separated register addresses, lookups fail over to the next one
     #[structopt(short = "r", long = "register", default_value = "127.0.0.1:9336")]
     register: String,
 
+    /// comma separated origin addresses, the next one is used when the current one fails
     #[structopt(short = "o", long = "origin", default_value = "127.0.0.1:9878")]
     origin: String,
+
+    /// seconds to keep pulling a channel after its last player left, 0 keeps it forever
+    #[structopt(long = "idle-timeout", default_value = "30")]
+    idle_timeout: u64,
 }
 
 #[tokio::main]
@@ -40,9 +52,17 @@ async fn main() -> Result<()> {
 
     let mut upstream: Option<Upstream> = None;
     if !opt.register.is_empty() {
-        upstream = Some(Upstream::Register(opt.register));
+        let mut config = RegisterClientConfig::new(&opt.register);
+        config.role = Some(Role::Cache);
+        //edges pulling from this cache report it as their upstream
+        config.advertise = LISTEN_ADDR.to_owned();
+        #[cfg(feature = "monitor")]
+        {
+            config.monitor_addr = xlive_cache::monitor::MONITOR_ADDR.to_owned();
+        }
+        upstream = Some(Upstream::Register(RegisterClient::connect(config).await?));
     } else if !opt.origin.is_empty() {
-        upstream = Some(Upstream::Addr(opt.origin));
+        upstream = Some(Upstream::from_addrs(&opt.origin));
     }
 
     if upstream.is_none() {
@@ -50,7 +70,11 @@ async fn main() -> Result<()> {
         std::process::exit(-1);
     }
 
-    let manager = Manager::new(true, upstream.unwrap());
+    let idle_timeout = match opt.idle_timeout {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+    let manager = Manager::new(true, upstream.unwrap(), idle_timeout);
     let manager_handle = manager.handle();
     tokio::spawn(manager.run());
 
@@ -62,7 +86,7 @@ async fn main() -> Result<()> {
         });
     }
 
-    let listener = TcpListener::bind("0.0.0.0:9888").await?;
+    let listener = TcpListener::bind(LISTEN_ADDR).await?;
     log::info!(
         "xilve cache service is running,Listening for connections on {}",
         listener.local_addr()?
@@ -1,31 +1,45 @@
 use crate::channel::Channel;
 use anyhow::{bail, Result};
-use bytes::Bytes;
-use core::message::{Kind, MediaPacket, ProtoMessage};
-use core::register::{Register, RegisterKind, RegisterResp, RegisterRespKind};
+use core::stats::ChannelStats;
 use core::transport::{
-    ChannelMessage, ChannelReceiver, Handle, ManagerHandle, OutgoingBroadcast, Trigger,
+    ChannelInfo, ChannelMessage, ChannelReceiver, Handle, JoinResp, ManagerHandle,
+    OutgoingBroadcast, Relays, Responder, Trigger,
 };
-use core::transport::{JoinResp, Responder};
+use core::upstream::{pull, relay, UpstreamFrame};
 use core::Upstream;
 use core::{AppName, Event, Message};
-use futures::{SinkExt, StreamExt};
+use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
-use tokio::net::{TcpStream, UdpSocket};
 use tokio::sync::{broadcast, mpsc, RwLock};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+type Pulled = (AppName, Result<UpstreamFrame>);
+
 pub struct Manager {
     handle: ManagerHandle,
     incoming: ChannelReceiver,
     channels: Arc<RwLock<HashMap<AppName, (Handle, OutgoingBroadcast)>>>,
     triggers: Arc<RwLock<HashMap<Event, Vec<Trigger>>>>,
+    //latest stats reported by every channel
+    stats: HashMap<AppName, ChannelStats>,
+    //address every channel is pulled from
+    upstreams: HashMap<AppName, String>,
+    //upstream reconnects of every channel
+    reconnects: HashMap<AppName, u64>,
+    //caches and edges pulling every channel, the other subscribers are local
+    relays: Relays,
+    //players waiting for an upstream pull in flight, keyed by channel name
+    pulling: HashMap<AppName, Vec<Responder<JoinResp>>>,
+    pulled: mpsc::UnboundedSender<Pulled>,
+    pulled_incoming: mpsc::UnboundedReceiver<Pulled>,
     full_gop: bool,
     upstream: Upstream,
+    idle_timeout: Option<Duration>,
 }
 
 impl Manager {
-    pub fn new(full_gop: bool, upstream: Upstream) -> Self {
+    pub fn new(full_gop: bool, upstream: Upstream, idle_timeout: Option<Duration>) -> Self {
         let (handle, incoming) = mpsc::unbounded_channel();
+        let (pulled, pulled_incoming) = mpsc::unbounded_channel();
         let channels = Arc::new(RwLock::new(HashMap::new()));
         let triggers = Arc::new(RwLock::new(HashMap::new()));
 
@@ -34,8 +48,16 @@ impl Manager {
             incoming,
             channels,
             triggers,
+            stats: HashMap::new(),
+            upstreams: HashMap::new(),
+            reconnects: HashMap::new(),
+            relays: Relays::default(),
+            pulling: HashMap::new(),
+            pulled,
+            pulled_incoming,
             full_gop,
             upstream,
+            idle_timeout,
         }
     }
 
@@ -48,138 +70,35 @@ impl Manager {
             ChannelMessage::Create(_) => unreachable!(),
             ChannelMessage::Join((name, responder)) => {
                 let sessions = self.channels.read().await;
-                if let Some((handle, watcher)) = sessions.get(&name) {
-                    if responder
-                        .send(JoinResp::Local(handle.clone(), watcher.subscribe()))
-                        .is_err()
-                    {
-                        bail!("Failed to send response");
+                if let Some((handle, _)) = sessions.get(&name) {
+                    if handle.send(Message::Join(responder)).is_err() {
+                        bail!("Failed to join channel {}", name);
                     }
+                } else if let Some(waiting) = self.pulling.get_mut(&name) {
+                    waiting.push(responder);
                 } else {
                     //本地没找到，去源站拉，并且作为一个 publisher，cache 缓存seq_header 和gop
-                    drop(sessions);
-                    let addr = match &self.upstream {
-                        Upstream::Register(addr) => {
-                            let buf: Bytes = Register {
-                                kind: RegisterKind::Get,
-                                channel_name: name.clone(),
-                            }
-                            .try_into()
-                            .unwrap();
-
-                            let udp = UdpSocket::bind("0.0.0.0:0").await.unwrap();
-                            udp.connect(addr).await.unwrap();
-                            _ = udp.send(&buf).await;
-                            let mut buf = vec![0u8; 100];
-                            let n = udp.recv(&mut buf).await?;
-                            let register_resp: RegisterResp = (&buf[..n]).try_into()?;
-                            match register_resp.kind {
-                                RegisterRespKind::OK => {
-                                    log::info!(
-                                        "got origin add from register {:?}",
-                                        register_resp.payload
-                                    );
-                                    register_resp.payload
-                                }
-                                RegisterRespKind::NOFOUND => bail!("publisher in not found"),
-                            }
-                        }
-                        Upstream::Addr(a) => a.clone(),
-                    };
-
-                    log::info!("TcpStream::connect {}", &addr);
-                    let stream = TcpStream::connect(&addr).await?;
-                    let mut frame = Framed::new(stream, LengthDelimitedCodec::new());
-                    frame
-                        .send(ProtoMessage::new_proto_init(false, &name).into())
-                        .await?;
-
-                    if let Some(Ok(data)) = frame.next().await {
-                        let proto_msg = ProtoMessage::try_from(data.freeze())?;
-                        match proto_msg.kind {
-                            Kind::Errors => {
-                                log::error!("join chanle err");
-                                bail!("join chanle err");
-                            }
-                            Kind::Init | Kind::Media => unreachable!(),
-                            Kind::Ok => {}
-                        }
-
-                        let (handle, incoming) = mpsc::unbounded_channel();
-                        let (outgoing, _watcher) = broadcast::channel(64);
-                        let mut sessions = self.channels.write().await;
-                        sessions.insert(name.clone(), (handle.clone(), outgoing.clone()));
-
-                        let triggers = self.triggers.read().await;
-                        if let Some(event_triggers) = triggers.get("create_session") {
-                            for trigger in event_triggers {
-                                trigger.send((name.clone(), outgoing.subscribe()))?;
-                            }
-                        }
-
-                        let full_gop = self.full_gop;
-                        let name_copy = name.clone();
-
-                        let watcher = outgoing.subscribe();
-                        let handle_cp = handle.clone();
-                        tokio::spawn(async move {
-                            _ = Channel::new(name_copy, incoming, outgoing, full_gop)
-                                .run()
-                                .await;
-                        });
-                        let manager_handle = self.handle.clone();
-                        let name_copy = name.clone();
-                        tokio::spawn(async move {
-                            loop {
-                                match frame.next().await {
-                                    Some(Ok(data)) => {
-                                        let proto_msg = ProtoMessage::try_from(data.freeze())?;
-                                        match &proto_msg.kind {
-                                            Kind::Errors => {
-                                                _ = handle_cp.send(Message::Disconnect);
-                                                _ = manager_handle
-                                                    .send(ChannelMessage::Release(name_copy));
-                                                log::error!("xlive cache origin connection closed");
-                                                bail!("ProtoMessage chanle err");
-                                            }
-                                            Kind::Init | Kind::Ok => unreachable!(),
-                                            Kind::Media => {
-                                                let media_packet =
-                                                    MediaPacket::try_from(proto_msg.payload)?;
-                                                match handle_cp
-                                                    .send(Message::PacketFromOrigin(media_packet))
-                                                {
-                                                    Ok(_) => {}
-                                                    Err(_e) => {
-                                                        break;
-                                                    }
-                                                }
-                                            }
-                                        };
-                                    }
-                                    Some(Err(e)) => {
-                                        log::error!("{:?}", e);
-                                        _ = handle_cp.send(Message::Disconnect);
-                                        break;
-                                    }
-                                    None => {}
-                                }
-                            }
-                            Ok::<(), anyhow::Error>(())
-                        });
-
-                        if responder
-                            .send(JoinResp::Origin(handle.clone(), watcher))
-                            .is_err()
-                        {
-                            bail!("Failed to send response");
-                        }
-                    }
+                    self.pulling.insert(name.clone(), vec![responder]);
+                    let upstream = self.upstream.clone();
+                    let pulled = self.pulled.clone();
+                    tokio::spawn(async move {
+                        let result = pull(&upstream, &name, 0).await;
+                        _ = pulled.send((name, result));
+                    });
                 }
             }
             ChannelMessage::Release(name) => {
+                //a channel closes its handle before it asks for release,
+                //so a late release never removes a newer channel of the same name
                 let mut sessions = self.channels.write().await;
-                sessions.remove(&name);
+                if let Some((handle, _)) = sessions.get(&name) {
+                    if handle.is_closed() {
+                        sessions.remove(&name);
+                        self.stats.remove(&name);
+                        self.upstreams.remove(&name);
+                        self.reconnects.remove(&name);
+                    }
+                }
             }
             ChannelMessage::RegisterTrigger(event, trigger) => {
                 log::debug!("Registering trigger for {}", event);
@@ -190,18 +109,94 @@ impl Manager {
                 let sessions = self.channels.read().await;
                 let mut info = HashMap::new();
                 for (k, v) in sessions.iter() {
-                    info.insert(k.to_owned(), v.1.receiver_count());
+                    let channel_info = ChannelInfo {
+                        subscribers: v.1.receiver_count(),
+                        relays: self.relays.get(k),
+                        upstream: self.upstreams.get(k).cloned(),
+                        reconnects: self.reconnects.get(k).copied().unwrap_or_default(),
+                        stats: self.stats.get(k).cloned(),
+                        ..Default::default()
+                    };
+                    info.insert(k.to_owned(), channel_info);
                 }
                 _ = responder.send(info);
             }
+            ChannelMessage::Switched(_) => unreachable!(),
+            ChannelMessage::Stats((name, stats)) => {
+                self.stats.insert(name, stats);
+            }
+            ChannelMessage::Upstream((name, addr)) => {
+                *self.reconnects.entry(name.clone()).or_default() += 1;
+                self.upstreams.insert(name, addr);
+            }
+            ChannelMessage::Relay((name, started)) => self.relays.update(name, started),
+        }
+
+        Ok(())
+    }
+
+    async fn process_pulled(&mut self, name: AppName, frame: Result<UpstreamFrame>) -> Result<()> {
+        let waiting = self.pulling.remove(&name).unwrap_or_default();
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => bail!("pull {} from upstream err {}", name, e),
+        };
+        if let Ok(addr) = frame.get_ref().peer_addr() {
+            self.upstreams.insert(name.clone(), addr.to_string());
+        }
+
+        let (handle, incoming) = mpsc::unbounded_channel();
+        let (outgoing, _watcher) = broadcast::channel(64);
+        let mut sessions = self.channels.write().await;
+        sessions.insert(name.clone(), (handle.clone(), outgoing.clone()));
+
+        let triggers = self.triggers.read().await;
+        if let Some(event_triggers) = triggers.get("create_session") {
+            for trigger in event_triggers {
+                trigger.send((name.clone(), outgoing.subscribe()))?;
+            }
+        }
+
+        //the joins are queued before any packet from origin,
+        //so the players get an empty snapshot and the whole stream
+        for responder in waiting {
+            if handle.send(Message::Join(responder)).is_err() {
+                bail!("Failed to join channel {}", name);
+            }
         }
 
+        let full_gop = self.full_gop;
+        let name_copy = name.clone();
+        let manager_handle = self.handle.clone();
+        let idle_timeout = self.idle_timeout;
+        tokio::spawn(async move {
+            _ = Channel::new(
+                name_copy,
+                incoming,
+                outgoing,
+                manager_handle,
+                idle_timeout,
+                full_gop,
+            )
+            .run()
+            .await;
+        });
+        let upstream = self.upstream.clone();
+        let manager_handle = self.handle.clone();
+        tokio::spawn(relay(name, frame, upstream, handle, manager_handle));
         Ok(())
     }
 
     pub async fn run(mut self) {
-        while let Some(message) = self.incoming.recv().await {
-            if let Err(err) = self.process_message(message).await {
+        loop {
+            let result = tokio::select! {
+                Some(message) = self.incoming.recv() => self.process_message(message).await,
+                Some((name, frame)) = self.pulled_incoming.recv() => {
+                    self.process_pulled(name, frame).await
+                }
+                else => break,
+            };
+            if let Err(err) = result {
                 log::error!("{}", err);
             };
         }
@@ -0,0 +1,4 @@
+//the workspace crate named `core` shadows the one `#[tokio::test]` expands to
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Runtime::new().unwrap().block_on(future)
+}
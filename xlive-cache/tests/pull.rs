@@ -0,0 +1,289 @@
+mod common;
+
+use bytes::Bytes;
+use common::block_on;
+use core::message::{MediaKind, MediaPacket, ProtoMessage};
+use core::transport::{ChannelMessage, JoinResp, ManagerHandle, Watcher};
+use core::upstream::{pull, UpstreamFrame};
+use core::Upstream;
+use futures::{SinkExt, StreamExt};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use xlive_cache::conn::Connection;
+use xlive_cache::manager::Manager;
+
+fn video(timestamp: u32, is_key_frame: bool) -> MediaPacket {
+    MediaPacket {
+        kind: MediaKind::Video,
+        is_seq_header: false,
+        is_key_frame,
+        timestamp,
+        payload: Bytes::from(timestamp.to_be_bytes().to_vec()),
+    }
+}
+
+//an origin that accepts every pull and hands the connection to the test
+async fn origin(addr: &str) -> mpsc::UnboundedReceiver<UpstreamFrame> {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let (sender, pulls) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let mut frame = Framed::new(stream, LengthDelimitedCodec::new());
+            //the init message of the pull
+            _ = frame.next().await;
+            if frame
+                .send(ProtoMessage::new_proto_ok().into())
+                .await
+                .is_err()
+            {
+                continue;
+            }
+            if sender.send(frame).is_err() {
+                break;
+            }
+        }
+    });
+    pulls
+}
+
+async fn send(frame: &mut UpstreamFrame, packet: MediaPacket) {
+    assert!(frame.send(ProtoMessage::from(packet).into()).await.is_ok());
+}
+
+async fn join(manager: &ManagerHandle, name: &str) -> Option<JoinResp> {
+    let (request, response) = oneshot::channel();
+    assert!(manager
+        .send(ChannelMessage::Join((name.to_owned(), request)))
+        .is_ok());
+    response.await.ok()
+}
+
+async fn recv(watcher: &mut Watcher) -> u32 {
+    time::timeout(Duration::from_secs(3), watcher.recv())
+        .await
+        .unwrap()
+        .unwrap()
+        .timestamp
+}
+
+#[test]
+fn concurrent_pulls_share_one_upstream() {
+    block_on(async {
+        let mut pulls = origin("127.0.0.1:29606").await;
+        let manager = Manager::new(true, Upstream::from_addrs("127.0.0.1:29606"), None);
+        let handle = manager.handle();
+        tokio::spawn(manager.run());
+
+        let (first, second) = tokio::join!(join(&handle, "live"), join(&handle, "live"));
+        let mut frame = pulls.recv().await.unwrap();
+        for i in 0..3 {
+            send(&mut frame, video(i * 40, i == 0)).await;
+        }
+        for mut resp in [first.unwrap(), second.unwrap()] {
+            for i in 0..3 {
+                assert_eq!(recv(&mut resp.watcher).await, i * 40);
+            }
+        }
+        //a later join is served from the cache
+        let third = join(&handle, "live").await.unwrap();
+        assert_eq!(third.init_data.gop.unwrap().len(), 3);
+        assert!(time::timeout(Duration::from_millis(200), pulls.recv())
+            .await
+            .is_err());
+    });
+}
+
+#[test]
+fn failed_pull_drops_the_waiting_players() {
+    block_on(async {
+        let manager = Manager::new(true, Upstream::from_addrs("127.0.0.1:29616"), None);
+        let handle = manager.handle();
+        tokio::spawn(manager.run());
+
+        let (first, second) = tokio::join!(join(&handle, "live"), join(&handle, "live"));
+        assert!(first.is_none() && second.is_none());
+    });
+}
+
+async fn channels(manager: &ManagerHandle) -> usize {
+    let (request, response) = oneshot::channel();
+    assert!(manager.send(ChannelMessage::Snapshot(request)).is_ok());
+    response.await.unwrap().len()
+}
+
+#[test]
+fn idle_channel_releases_its_upstream() {
+    block_on(async {
+        let mut pulls = origin("127.0.0.1:29626").await;
+        let idle_timeout = Some(Duration::from_millis(200));
+        let manager = Manager::new(true, Upstream::from_addrs("127.0.0.1:29626"), idle_timeout);
+        let handle = manager.handle();
+        tokio::spawn(manager.run());
+
+        let mut resp = join(&handle, "live").await.unwrap();
+        let mut frame = pulls.recv().await.unwrap();
+        send(&mut frame, video(0, true)).await;
+        assert_eq!(recv(&mut resp.watcher).await, 0);
+        //a player keeps the channel
+        time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(channels(&handle).await, 1);
+
+        drop(resp);
+        let closed = time::timeout(Duration::from_secs(3), frame.next()).await;
+        assert!(matches!(closed, Ok(None)));
+        assert_eq!(channels(&handle).await, 0);
+
+        //the next player pulls it again
+        let _resp = join(&handle, "live").await.unwrap();
+        assert!(pulls.recv().await.is_some());
+    });
+}
+
+#[test]
+fn reconnect_resumes_on_the_next_live_keyframe() {
+    block_on(async {
+        let mut pulls = origin("127.0.0.1:29636").await;
+        let manager = Manager::new(true, Upstream::from_addrs("127.0.0.1:29636"), None);
+        let handle = manager.handle();
+        tokio::spawn(manager.run());
+
+        let mut resp = join(&handle, "live").await.unwrap();
+        let mut frame = pulls.recv().await.unwrap();
+        for i in 0..3 {
+            send(&mut frame, video(i * 40, i == 0)).await;
+        }
+        for i in 0..3 {
+            assert_eq!(recv(&mut resp.watcher).await, i * 40);
+        }
+        drop(frame);
+
+        //the origin replays its cached gop before the live stream
+        let mut frame = time::timeout(Duration::from_secs(3), pulls.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        for i in 0..3 {
+            send(&mut frame, video(i * 40, i == 0)).await;
+        }
+        send(&mut frame, video(120, false)).await;
+        send(&mut frame, video(160, true)).await;
+        send(&mut frame, video(200, false)).await;
+        assert_eq!(recv(&mut resp.watcher).await, 160);
+        assert_eq!(recv(&mut resp.watcher).await, 200);
+
+        let (request, response) = oneshot::channel();
+        assert!(handle.send(ChannelMessage::Snapshot(request)).is_ok());
+        assert_eq!(response.await.unwrap()["live"].reconnects, 1);
+    });
+}
+
+#[test]
+fn replayed_gop_is_not_counted_in_the_stats() {
+    block_on(async {
+        let mut pulls = origin("127.0.0.1:29676").await;
+        let manager = Manager::new(true, Upstream::from_addrs("127.0.0.1:29676"), None);
+        let handle = manager.handle();
+        tokio::spawn(manager.run());
+
+        let mut resp = join(&handle, "live").await.unwrap();
+        let mut frame = pulls.recv().await.unwrap();
+        for i in 0..3 {
+            send(&mut frame, video(i * 40, i != 1)).await;
+        }
+        for i in 0..3 {
+            assert_eq!(recv(&mut resp.watcher).await, i * 40);
+        }
+        drop(frame);
+
+        let mut frame = time::timeout(Duration::from_secs(3), pulls.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        send(&mut frame, video(80, true)).await;
+        send(&mut frame, video(120, false)).await;
+        //the channel reports its stats every second
+        time::sleep(Duration::from_millis(1500)).await;
+        let (request, response) = oneshot::channel();
+        assert!(handle.send(ChannelMessage::Snapshot(request)).is_ok());
+        let stats = response.await.unwrap()["live"].stats.clone().unwrap();
+        assert_eq!(stats.gop_ms, Some(80));
+    });
+}
+
+#[test]
+fn reconnect_resumes_audio_only_channels() {
+    block_on(async {
+        let mut pulls = origin("127.0.0.1:29646").await;
+        let manager = Manager::new(true, Upstream::from_addrs("127.0.0.1:29646"), None);
+        let handle = manager.handle();
+        tokio::spawn(manager.run());
+
+        let audio = |timestamp: u32| MediaPacket {
+            kind: MediaKind::Audio,
+            ..video(timestamp, false)
+        };
+        let mut resp = join(&handle, "live").await.unwrap();
+        let mut frame = pulls.recv().await.unwrap();
+        send(&mut frame, audio(0)).await;
+        send(&mut frame, audio(20)).await;
+        assert_eq!(recv(&mut resp.watcher).await, 0);
+        assert_eq!(recv(&mut resp.watcher).await, 20);
+        drop(frame);
+
+        let mut frame = time::timeout(Duration::from_secs(3), pulls.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        //no gop to replay, the next origin may start again from 0
+        send(&mut frame, audio(0)).await;
+        send(&mut frame, audio(20)).await;
+        assert_eq!(recv(&mut resp.watcher).await, 0);
+        assert_eq!(recv(&mut resp.watcher).await, 20);
+    });
+}
+
+#[test]
+fn relays_are_counted_apart_from_local_subscribers() {
+    block_on(async {
+        let mut pulls = origin("127.0.0.1:29656").await;
+        let manager = Manager::new(true, Upstream::from_addrs("127.0.0.1:29656"), None);
+        let handle = manager.handle();
+        tokio::spawn(manager.run());
+
+        //the cache itself, serving an edge
+        let listener = TcpListener::bind("127.0.0.1:29666").await.unwrap();
+        let manager_handle = handle.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let manager_handle = manager_handle.clone();
+                tokio::spawn(async move { Connection::new(stream, 0, manager_handle).run().await });
+            }
+        });
+
+        let _local = join(&handle, "live").await.unwrap();
+        let mut frame = pulls.recv().await.unwrap();
+        let edge = Upstream::from_addrs("127.0.0.1:29666");
+        let relay = pull(&edge, "live", 0).await.unwrap();
+        let info = |handle: ManagerHandle| async move {
+            let (request, response) = oneshot::channel();
+            assert!(handle.send(ChannelMessage::Snapshot(request)).is_ok());
+            let info = response.await.unwrap().remove("live").unwrap();
+            (info.subscribers, info.relays)
+        };
+        assert_eq!(info(handle.clone()).await, (2, 1));
+
+        //the cache notices the edge left on its next write
+        drop(relay);
+        for i in 0..100 {
+            send(&mut frame, video(i * 40, i == 0)).await;
+            if info(handle.clone()).await == (1, 0) {
+                return;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("relay still counted");
+    });
+}
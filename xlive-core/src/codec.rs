@@ -0,0 +1,339 @@
+use serde::Serialize;
+
+const FLV_CODEC_AVC: u8 = 7;
+const FLV_CODEC_HEVC: u8 = 12;
+const FLV_SOUND_AAC: u8 = 10;
+const HEVC_NAL_SPS: u8 = 33;
+
+/// What a video seq header tells about the stream.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct VideoCodec {
+    pub codec: String,
+    pub profile: String,
+    pub level: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// What an audio seq header tells about the stream.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AudioCodec {
+    pub codec: String,
+    pub profile: String,
+    pub sample_rate: u32,
+    pub channels: u8,
+}
+
+/// Parse the decoder config record of a flv video seq header, h264 or h265.
+pub fn parse_video_seq_header(payload: &[u8]) -> Option<VideoCodec> {
+    //frame type and codec id, packet type and composition time
+    let record = payload.get(5..)?;
+    match payload.first()? & 0x0f {
+        FLV_CODEC_AVC => parse_avc_record(record),
+        FLV_CODEC_HEVC => parse_hevc_record(record),
+        _ => None,
+    }
+}
+
+/// Parse the AudioSpecificConfig of a flv aac seq header.
+pub fn parse_audio_seq_header(payload: &[u8]) -> Option<AudioCodec> {
+    if payload.first()? >> 4 != FLV_SOUND_AAC {
+        return None;
+    }
+    let mut reader = BitReader::new(payload.get(2..)?);
+    let mut object_type = reader.read(5)?;
+    if object_type == 31 {
+        object_type = 32 + reader.read(6)?;
+    }
+    let sample_rate = match reader.read(4)? {
+        0x0f => reader.read(24)?,
+        index => *SAMPLE_RATES.get(index as usize)?,
+    };
+    let channels = reader.read(4)? as u8;
+    let profile = match object_type {
+        1 => "Main",
+        2 => "LC",
+        3 => "SSR",
+        4 => "LTP",
+        5 => "HE-AAC",
+        29 => "HE-AACv2",
+        _ => "",
+    };
+    Some(AudioCodec {
+        codec: "aac".to_owned(),
+        profile: if profile.is_empty() {
+            object_type.to_string()
+        } else {
+            profile.to_owned()
+        },
+        sample_rate,
+        channels,
+    })
+}
+
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+//AVCDecoderConfigurationRecord, the resolution is in the first sps
+fn parse_avc_record(record: &[u8]) -> Option<VideoCodec> {
+    if record.len() < 8 || record[5] & 0x1f == 0 {
+        return None;
+    }
+    let sps_len = u16::from_be_bytes([record[6], record[7]]) as usize;
+    let sps = record.get(8..8 + sps_len)?;
+    let (profile_idc, level_idc, width, height) = parse_avc_sps(sps)?;
+    let profile = match profile_idc {
+        66 => "Baseline",
+        77 => "Main",
+        88 => "Extended",
+        100 => "High",
+        110 => "High 10",
+        122 => "High 4:2:2",
+        244 => "High 4:4:4",
+        _ => "",
+    };
+    Some(VideoCodec {
+        codec: "h264".to_owned(),
+        profile: if profile.is_empty() {
+            profile_idc.to_string()
+        } else {
+            profile.to_owned()
+        },
+        level: format!("{}.{}", level_idc / 10, level_idc % 10),
+        width,
+        height,
+    })
+}
+
+fn parse_avc_sps(nal: &[u8]) -> Option<(u32, u32, u32, u32)> {
+    let rbsp = unescape(nal.get(1..)?);
+    let mut reader = BitReader::new(&rbsp);
+    let profile_idc = reader.read(8)?;
+    reader.skip(8)?;
+    let level_idc = reader.read(8)?;
+    reader.read_ue()?;
+    let mut chroma_format_idc = 1;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = reader.read_ue()?;
+        if chroma_format_idc == 3 {
+            reader.skip(1)?;
+        }
+        //bit depths and qpprime_y_zero_transform_bypass_flag
+        reader.read_ue()?;
+        reader.read_ue()?;
+        reader.skip(1)?;
+        if reader.read(1)? == 1 {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if reader.read(1)? == 1 {
+                    skip_scaling_list(&mut reader, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+    reader.read_ue()?;
+    match reader.read_ue()? {
+        0 => {
+            reader.read_ue()?;
+        }
+        1 => {
+            reader.skip(1)?;
+            reader.read_se()?;
+            reader.read_se()?;
+            for _ in 0..reader.read_ue()? {
+                reader.read_se()?;
+            }
+        }
+        _ => {}
+    }
+    reader.read_ue()?;
+    reader.skip(1)?;
+    let width_in_mbs = reader.read_ue()? + 1;
+    let height_in_map_units = reader.read_ue()? + 1;
+    let frame_mbs_only = reader.read(1)?;
+    if frame_mbs_only == 0 {
+        reader.skip(1)?;
+    }
+    reader.skip(1)?;
+    let mut crop = [0; 4];
+    if reader.read(1)? == 1 {
+        for c in crop.iter_mut() {
+            *c = reader.read_ue()?;
+        }
+    }
+    let (sub_width, sub_height) = match chroma_format_idc {
+        0 | 3 => (1, 1),
+        2 => (2, 1),
+        _ => (2, 2),
+    };
+    let crop_x = sub_width;
+    let crop_y = sub_height * (2 - frame_mbs_only);
+    let width = (width_in_mbs * 16).checked_sub((crop[0] + crop[1]) * crop_x)?;
+    let height = ((2 - frame_mbs_only) * height_in_map_units * 16)
+        .checked_sub((crop[2] + crop[3]) * crop_y)?;
+    Some((profile_idc, level_idc, width, height))
+}
+
+fn skip_scaling_list(reader: &mut BitReader, size: u32) -> Option<()> {
+    let mut last = 8i64;
+    let mut next = 8i64;
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + reader.read_se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Some(())
+}
+
+//HEVCDecoderConfigurationRecord, 22 bytes before the nal unit arrays
+fn parse_hevc_record(record: &[u8]) -> Option<VideoCodec> {
+    let mut pos = 23;
+    for _ in 0..*record.get(22)? {
+        let nal_type = record.get(pos)? & 0x3f;
+        let count = u16::from_be_bytes([*record.get(pos + 1)?, *record.get(pos + 2)?]);
+        pos += 3;
+        for _ in 0..count {
+            let len = u16::from_be_bytes([*record.get(pos)?, *record.get(pos + 1)?]) as usize;
+            let nal = record.get(pos + 2..pos + 2 + len)?;
+            if nal_type == HEVC_NAL_SPS {
+                return parse_hevc_sps(nal);
+            }
+            pos += 2 + len;
+        }
+    }
+    None
+}
+
+fn parse_hevc_sps(nal: &[u8]) -> Option<VideoCodec> {
+    let rbsp = unescape(nal.get(2..)?);
+    let mut reader = BitReader::new(&rbsp);
+    reader.skip(4)?;
+    let max_sub_layers_minus1 = reader.read(3)?;
+    reader.skip(1)?;
+    //profile_tier_level, the general profile and level
+    reader.skip(3)?;
+    let profile_idc = reader.read(5)?;
+    reader.skip(32 + 48)?;
+    let level_idc = reader.read(8)?;
+    let mut sub_layers = vec![];
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layers.push((reader.read(1)?, reader.read(1)?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        reader.skip(2 * (8 - max_sub_layers_minus1))?;
+    }
+    for (profile_present, level_present) in sub_layers {
+        reader.skip(profile_present * 88 + level_present * 8)?;
+    }
+    reader.read_ue()?;
+    let chroma_format_idc = reader.read_ue()?;
+    if chroma_format_idc == 3 {
+        reader.skip(1)?;
+    }
+    let mut width = reader.read_ue()?;
+    let mut height = reader.read_ue()?;
+    if reader.read(1)? == 1 {
+        let (sub_width, sub_height) = match chroma_format_idc {
+            0 | 3 => (1, 1),
+            2 => (2, 1),
+            _ => (2, 2),
+        };
+        let (left, right, top, bottom) = (
+            reader.read_ue()?,
+            reader.read_ue()?,
+            reader.read_ue()?,
+            reader.read_ue()?,
+        );
+        width = width.checked_sub((left + right) * sub_width)?;
+        height = height.checked_sub((top + bottom) * sub_height)?;
+    }
+    let profile = match profile_idc {
+        1 => "Main",
+        2 => "Main 10",
+        3 => "Main Still Picture",
+        4 => "Rext",
+        _ => "",
+    };
+    Some(VideoCodec {
+        codec: "h265".to_owned(),
+        profile: if profile.is_empty() {
+            profile_idc.to_string()
+        } else {
+            profile.to_owned()
+        },
+        //general_level_idc is 30 times the level
+        level: format!("{}.{}", level_idc / 30, level_idc % 30 / 3),
+        width,
+        height,
+    })
+}
+
+//drop the emulation prevention bytes of a nal unit
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    rbsp
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn read(&mut self, bits: u32) -> Option<u32> {
+        let mut value = 0u32;
+        for _ in 0..bits {
+            let byte = self.data.get(self.pos / 8)?;
+            let bit = (byte >> (7 - self.pos % 8)) & 1;
+            value = value << 1 | bit as u32;
+            self.pos += 1;
+        }
+        Some(value)
+    }
+
+    fn skip(&mut self, bits: u32) -> Option<()> {
+        self.pos += bits as usize;
+        (self.pos <= self.data.len() * 8).then_some(())
+    }
+
+    //exp-golomb codes of the parameter sets
+    fn read_ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.read(1)? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << zeros) - 1 + self.read(zeros)?)
+    }
+
+    fn read_se(&mut self) -> Option<i64> {
+        let value = self.read_ue()? as i64;
+        Some(if value % 2 == 1 {
+            (value + 1) / 2
+        } else {
+            -(value / 2)
+        })
+    }
+}
@@ -0,0 +1,54 @@
+use crate::message::{MediaKind, MediaPacket};
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+const TAG_AUDIO: u8 = 8;
+const TAG_VIDEO: u8 = 9;
+
+//read the audio and video tags of a flv file into media packets,
+//script tags are skipped because players expect metadata in the cluster's own format
+pub fn read_media_packets(data: Bytes) -> Result<Vec<MediaPacket>> {
+    if data.len() < 9 || &data[..3] != b"FLV" {
+        bail!("not a flv file");
+    }
+    let header_size = u32::from_be_bytes([data[5], data[6], data[7], data[8]]) as usize;
+    //flv header and the first previous tag size
+    let mut pos = header_size + 4;
+    let mut packets = vec![];
+    while pos + 11 <= data.len() {
+        let tag_type = data[pos] & 0x1f;
+        let size =
+            (data[pos + 1] as usize) << 16 | (data[pos + 2] as usize) << 8 | data[pos + 3] as usize;
+        let timestamp = (data[pos + 7] as u32) << 24
+            | (data[pos + 4] as u32) << 16
+            | (data[pos + 5] as u32) << 8
+            | data[pos + 6] as u32;
+        let start = pos + 11;
+        let end = start + size;
+        if end > data.len() {
+            bail!("flv tag at {} is truncated", pos);
+        }
+        let payload = data.slice(start..end);
+        match tag_type {
+            TAG_VIDEO if payload.len() >= 2 => packets.push(MediaPacket {
+                kind: MediaKind::Video,
+                // assuming h264 h265
+                is_seq_header: payload[1] == 0x00,
+                is_key_frame: payload[0] >> 4 == 1,
+                timestamp,
+                payload,
+            }),
+            TAG_AUDIO if payload.len() >= 2 => packets.push(MediaPacket {
+                kind: MediaKind::Audio,
+                // assuming aac
+                is_seq_header: payload[0] >> 4 == 10 && payload[1] == 0x00,
+                is_key_frame: false,
+                timestamp,
+                payload,
+            }),
+            _ => {}
+        }
+        pos = end + 4;
+    }
+    Ok(packets)
+}
@@ -0,0 +1,49 @@
+//helpers of the http apis of the register and the monitor
+use std::collections::HashMap;
+use subtle::ConstantTimeEq;
+
+/// whether the `Authorization` header carries `Bearer <token>`
+pub fn bearer_matches(authorization: Option<&str>, token: &str) -> bool {
+    //compared in constant time, so the time taken does not tell how much of a guess was right
+    match authorization.and_then(|value| value.strip_prefix("Bearer ")) {
+        Some(bearer) => bearer.as_bytes().ct_eq(token.as_bytes()).into(),
+        None => false,
+    }
+}
+
+/// the `key=value` pairs of a query string, decoded
+pub fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (percent_decode(key), percent_decode(value)))
+        .collect()
+}
+
+/// decodes `%XX` escapes and `+`, invalid escapes are kept as they are
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -1,18 +1,21 @@
+pub mod codec;
+pub mod flv;
+pub mod http;
 pub mod message;
+pub mod metrics;
 pub mod register;
+pub mod register_client;
+pub mod resync;
+pub mod stats;
 pub mod transport;
+pub mod upstream;
 
 pub type Event = &'static str;
 pub type AppName = String;
 pub type StreamKey = String;
 
+pub use self::register_client::RegisterClient;
 pub use self::transport::{
     trigger_channel, ChannelMessage, Handle, ManagerHandle, Message, Watcher,
 };
-
-//define the kind of upstream for cache and edge
-#[derive(Debug)]
-pub enum Upstream {
-    Register(String),
-    Addr(String),
-}
+pub use self::upstream::Upstream;
@@ -25,6 +25,8 @@ impl TryFrom<u8> for Kind {
     }
 }
 
+const BACKUP: &[u8] = b"backup";
+
 #[derive(Debug)]
 pub struct ProtoMessage {
     pub kind: Kind,
@@ -63,6 +65,18 @@ impl ProtoMessage {
             payload: Bytes::from(""), //body is empty
         }
     }
+
+    //publisher accepted as a hot backup of the current one
+    pub fn new_proto_backup() -> Self {
+        Self {
+            kind: Kind::Ok,
+            payload: Bytes::from_static(BACKUP),
+        }
+    }
+
+    pub fn is_backup(&self) -> bool {
+        matches!(self.kind, Kind::Ok) && self.payload == BACKUP
+    }
 }
 
 impl TryFrom<&[u8]> for ProtoMessage {
@@ -0,0 +1,151 @@
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    TextEncoder,
+};
+use std::sync::LazyLock;
+
+//protocols of the players, publishers and bytes
+pub const RTMP: &str = "rtmp";
+pub const HTTP_FLV: &str = "http_flv";
+//the length delimited protocol between origin, cache and edge
+pub const XLIVE: &str = "xlive";
+//packets pulled from the origin or cache
+pub const UPSTREAM: &str = "upstream";
+
+/// Metrics of an origin, cache or edge, in the default prometheus registry.
+pub struct Metrics {
+    pub channels: IntGauge,
+    pub publishers: IntGaugeVec,
+    pub players: IntGaugeVec,
+    pub bytes_received: IntCounterVec,
+    pub bytes_sent: IntCounterVec,
+    pub upstream_connections: IntGauge,
+    pub upstream_reconnects: IntCounter,
+    //packets skipped by players that fell behind the channel
+    pub lagged_packets: IntCounterVec,
+    pub register_requests: IntCounterVec,
+    pub register_request_seconds: HistogramVec,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let metrics = Metrics {
+        channels: IntGauge::new("xlive_channels", "channels on this node").unwrap(),
+        publishers: IntGaugeVec::new(
+            Opts::new("xlive_publishers", "connected publishers"),
+            &["protocol"],
+        )
+        .unwrap(),
+        players: IntGaugeVec::new(
+            Opts::new(
+                "xlive_players",
+                "connected players, caches and edges pulling from this node included",
+            ),
+            &["protocol"],
+        )
+        .unwrap(),
+        bytes_received: IntCounterVec::new(
+            Opts::new("xlive_bytes_received_total", "media bytes received"),
+            &["protocol"],
+        )
+        .unwrap(),
+        bytes_sent: IntCounterVec::new(
+            Opts::new("xlive_bytes_sent_total", "media bytes sent"),
+            &["protocol"],
+        )
+        .unwrap(),
+        upstream_connections: IntGauge::new(
+            "xlive_upstream_connections",
+            "open connections to the origin or cache channels are pulled from",
+        )
+        .unwrap(),
+        upstream_reconnects: IntCounter::new(
+            "xlive_upstream_reconnects_total",
+            "upstream connections re-established after they broke",
+        )
+        .unwrap(),
+        lagged_packets: IntCounterVec::new(
+            Opts::new(
+                "xlive_lagged_packets_total",
+                "packets dropped for players that fell behind",
+            ),
+            &["protocol"],
+        )
+        .unwrap(),
+        register_requests: IntCounterVec::new(
+            Opts::new("xlive_register_requests_total", "requests to the register"),
+            &["kind", "result"],
+        )
+        .unwrap(),
+        register_request_seconds: HistogramVec::new(
+            HistogramOpts::new(
+                "xlive_register_request_duration_seconds",
+                "time until the register answered, retries included",
+            )
+            .buckets(vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]),
+            &["kind"],
+        )
+        .unwrap(),
+    };
+    let registry = prometheus::default_registry();
+    registry
+        .register(Box::new(metrics.channels.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.publishers.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.players.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.bytes_received.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.bytes_sent.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.upstream_connections.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.upstream_reconnects.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.lagged_packets.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.register_requests.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.register_request_seconds.clone()))
+        .unwrap();
+    metrics
+});
+
+/// Keeps a gauge raised while it is alive, e.g. for the lifetime of a connection.
+pub struct GaugeGuard(IntGauge);
+
+impl GaugeGuard {
+    pub fn new(gauge: IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// The default registry in the prometheus text format, served at `/metrics`.
+pub fn encode() -> String {
+    LazyLock::force(&METRICS);
+    let mut buf = vec![];
+    //writing to a vec does not fail
+    _ = TextEncoder::new().encode(&prometheus::gather(), &mut buf);
+    String::from_utf8(buf).unwrap_or_default()
+}
+
+/// Content type of `encode`.
+pub fn content_type() -> String {
+    TextEncoder::new().format_type().to_owned()
+}
@@ -1,18 +1,109 @@
+use anyhow::bail;
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 #[repr(u8)]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 pub enum RegisterKind {
     Set = 1u8,
     Get = 2u8,
     Delete = 3u8,
+    //refresh all the channels of an origin at once
+    Heartbeat = 4u8,
+    //the origin a new publisher of the channel should go to
+    PickOrigin = 5u8,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+//how the register picks an origin for a new publisher
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub enum PickPolicy {
+    #[default]
+    LeastLoaded = 1u8,
+    //the same channel goes to the same origin while the origins do not change
+    ConsistentHash = 2u8,
+}
+
+impl FromStr for PickPolicy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "least-loaded" => Self::LeastLoaded,
+            "consistent-hash" => Self::ConsistentHash,
+            _ => bail!(
+                "unknown pick policy {}, expect least-loaded or consistent-hash",
+                s
+            ),
+        })
+    }
+}
+
+//what a node is, announced in its heartbeats so the monitor finds it
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Origin = 1u8,
+    Cache = 2u8,
+    Edge = 3u8,
+}
+
+//load of an origin sent in its heartbeats
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct Load {
+    pub channels: u32,
+    //bits per second received from the publishers
+    pub ingress_bps: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Register {
+    //echoed in the response of a get, 0 when no response is expected
+    pub id: u64,
     pub kind: RegisterKind,
     pub channel_name: String,
+    //channels of a heartbeat
+    pub channels: Vec<String>,
+    //the origin owning the channel and the tcp address it is reached at, empty on get
+    pub node_id: String,
+    pub addr: String,
+    //copied from another register of the cluster, not relayed again
+    pub relayed: bool,
+    //set on heartbeats
+    pub load: Load,
+    //set on pick origin
+    pub policy: PickPolicy,
+    //set by nodes that announce themselves
+    pub role: Option<Role>,
+    //address of the monitor endpoint of the node, an unspecified ip is the source of the message
+    pub monitor_addr: String,
+}
+
+impl Register {
+    pub fn get(id: u64, channel_name: &str) -> Self {
+        Self {
+            id,
+            kind: RegisterKind::Get,
+            channel_name: channel_name.to_owned(),
+            channels: vec![],
+            node_id: "".to_owned(),
+            addr: "".to_owned(),
+            relayed: false,
+            load: Load::default(),
+            policy: PickPolicy::default(),
+            role: None,
+            monitor_addr: "".to_owned(),
+        }
+    }
+
+    pub fn pick_origin(id: u64, channel_name: &str, policy: PickPolicy) -> Self {
+        Self {
+            kind: RegisterKind::PickOrigin,
+            policy,
+            ..Self::get(id, channel_name)
+        }
+    }
 }
 
 impl TryFrom<&[u8]> for Register {
@@ -36,12 +127,18 @@ impl TryFrom<Register> for Bytes {
 pub enum RegisterRespKind {
     OK = 1,
     NOFOUND = 2,
+    //sent unasked to an origin whose lease was taken by another one,
+    //the payload is the channel and node_id the new owner
+    REVOKED = 3,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct RegisterResp {
+    pub id: u64,
     pub kind: RegisterRespKind,
+    //tcp address of the origin
     pub payload: String,
+    pub node_id: String,
 }
 
 impl TryFrom<&[u8]> for RegisterResp {
@@ -0,0 +1,418 @@
+use crate::metrics::METRICS;
+use crate::register::{
+    Load, PickPolicy, Register, RegisterKind, RegisterResp, RegisterRespKind, Role,
+};
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time;
+
+//keep a heartbeat datagram below the usual mtu
+const MAX_DATAGRAM: usize = 1200;
+
+#[derive(Clone, Debug)]
+pub struct RegisterClientConfig {
+    //registers of the cluster, lookups go to one of them and fail over to the next
+    pub addrs: Vec<String>,
+    //how an origin introduces itself, empty for nodes that only look up
+    pub node_id: String,
+    pub advertise: String,
+    //wait for a response before the request is sent again
+    pub timeout: Duration,
+    pub retries: u32,
+    pub found_ttl: Duration,
+    pub not_found_ttl: Duration,
+    pub heartbeat_interval: Duration,
+    //origins heartbeat their load even without channels, to be picked for new publishers
+    pub report_load: bool,
+    //nodes with a role keep heartbeating to stay in the server list the monitor discovers nodes from
+    pub role: Option<Role>,
+    pub monitor_addr: String,
+}
+
+impl RegisterClientConfig {
+    //comma separated register addresses
+    pub fn new(addrs: &str) -> Self {
+        Self {
+            addrs: addrs
+                .split(',')
+                .map(|addr| addr.trim().to_owned())
+                .filter(|addr| !addr.is_empty())
+                .collect(),
+            node_id: "".to_owned(),
+            advertise: "".to_owned(),
+            timeout: Duration::from_millis(500),
+            retries: 2,
+            found_ttl: Duration::from_secs(3),
+            not_found_ttl: Duration::from_secs(1),
+            heartbeat_interval: Duration::from_secs(1),
+            report_load: false,
+            role: None,
+            monitor_addr: "".to_owned(),
+        }
+    }
+}
+
+/// The origin a channel is published on.
+#[derive(Clone, Debug)]
+pub struct Origin {
+    pub node_id: String,
+    pub addr: String,
+}
+
+enum Command {
+    //the attempt picks the register the request is sent to, the id is set on send
+    Request(Register, u32, oneshot::Sender<RegisterResp>),
+    Set(String),
+    Delete(String),
+}
+
+type LookupCache = HashMap<String, (Option<Origin>, Instant)>;
+
+/// Talks to the register over udp, shared by all the channels of a node.
+#[derive(Clone)]
+pub struct RegisterClient {
+    config: Arc<RegisterClientConfig>,
+    commands: mpsc::UnboundedSender<Command>,
+    cache: Arc<Mutex<LookupCache>>,
+    revocations: broadcast::Sender<String>,
+    //bytes received from the publishers since the last heartbeat
+    ingress: Arc<AtomicU64>,
+}
+
+impl std::fmt::Debug for RegisterClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RegisterClient")
+            .field("addrs", &self.config.addrs)
+            .finish()
+    }
+}
+
+impl RegisterClient {
+    pub async fn connect(config: RegisterClientConfig) -> Result<Self> {
+        if config.addrs.is_empty() {
+            bail!("no register address");
+        }
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        let config = Arc::new(config);
+        let (commands, incoming) = mpsc::unbounded_channel();
+        let (revocations, _) = broadcast::channel(64);
+        let ingress = Arc::new(AtomicU64::new(0));
+        let connection = Connection::new(
+            socket,
+            config.clone(),
+            incoming,
+            revocations.clone(),
+            ingress.clone(),
+        );
+        tokio::spawn(connection.run());
+        Ok(Self {
+            config,
+            commands,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            revocations,
+            ingress,
+        })
+    }
+
+    /// Origin of a channel, `None` when the register does not know it.
+    pub async fn lookup(&self, channel_name: &str) -> Result<Option<Origin>> {
+        if let Some((origin, at)) = self.cache.lock().unwrap().get(channel_name) {
+            let ttl = match origin {
+                Some(_) => self.config.found_ttl,
+                None => self.config.not_found_ttl,
+            };
+            if at.elapsed() < ttl {
+                return Ok(origin.clone());
+            }
+        }
+
+        let resp = self.request(Register::get(0, channel_name)).await?;
+        let origin = resp.into_origin();
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(channel_name.to_owned(), (origin.clone(), Instant::now()));
+        Ok(origin)
+    }
+
+    /// Origin a new publisher of the channel should go to, the current one when
+    /// the channel is published already. `None` when the register knows no origin.
+    pub async fn pick_origin(
+        &self,
+        channel_name: &str,
+        policy: PickPolicy,
+    ) -> Result<Option<Origin>> {
+        let resp = self
+            .request(Register::pick_origin(0, channel_name, policy))
+            .await?;
+        Ok(resp.into_origin())
+    }
+
+    //sent again to the next register on timeout
+    async fn request(&self, register: Register) -> Result<RegisterResp> {
+        let kind = match register.kind {
+            RegisterKind::PickOrigin => "pick_origin",
+            _ => "lookup",
+        };
+        let started = Instant::now();
+        for attempt in 0..=self.config.retries {
+            let (request, response) = oneshot::channel();
+            if self
+                .commands
+                .send(Command::Request(register.clone(), attempt, request))
+                .is_err()
+            {
+                bail!("register client is closed");
+            }
+            match time::timeout(self.config.timeout, response).await {
+                Ok(Ok(resp)) => {
+                    let result = match resp.kind {
+                        RegisterRespKind::OK => "found",
+                        _ => "not_found",
+                    };
+                    METRICS
+                        .register_requests
+                        .with_label_values(&[kind, result])
+                        .inc();
+                    METRICS
+                        .register_request_seconds
+                        .with_label_values(&[kind])
+                        .observe(started.elapsed().as_secs_f64());
+                    return Ok(resp);
+                }
+                Ok(Err(_)) => bail!("register client is closed"),
+                Err(_) => log::warn!(
+                    "register {:?} {} timeout, attempt #{}",
+                    register.kind,
+                    register.channel_name,
+                    attempt
+                ),
+            }
+        }
+        METRICS
+            .register_requests
+            .with_label_values(&[kind, "timeout"])
+            .inc();
+        bail!("registers {:?} do not respond", self.config.addrs)
+    }
+
+    /// Drop a cached lookup, when the origin it points to can not be reached.
+    pub fn forget(&self, channel_name: &str) {
+        self.cache.lock().unwrap().remove(channel_name);
+    }
+
+    /// Register a channel of this node, it is kept alive by the heartbeats.
+    pub fn set(&self, channel_name: &str) {
+        _ = self.commands.send(Command::Set(channel_name.to_owned()));
+    }
+
+    pub fn delete(&self, channel_name: &str) {
+        _ = self.commands.send(Command::Delete(channel_name.to_owned()));
+    }
+
+    /// Channels of this node whose lease another origin acquired,
+    /// they are no longer sent in the heartbeats.
+    pub fn revoked(&self) -> broadcast::Receiver<String> {
+        self.revocations.subscribe()
+    }
+
+    /// Count bytes received from publishers, reported as ingress bitrate.
+    pub fn add_ingress(&self, bytes: usize) {
+        self.ingress.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+impl RegisterResp {
+    fn into_origin(self) -> Option<Origin> {
+        match self.kind {
+            RegisterRespKind::OK => Some(Origin {
+                node_id: self.node_id,
+                addr: self.payload,
+            }),
+            RegisterRespKind::NOFOUND | RegisterRespKind::REVOKED => None,
+        }
+    }
+}
+
+//owns the socket, matches responses to requests and sends the heartbeats,
+//writes go to every register so any of them can answer after a failover
+struct Connection {
+    socket: UdpSocket,
+    config: Arc<RegisterClientConfig>,
+    incoming: mpsc::UnboundedReceiver<Command>,
+    next_id: u64,
+    //the register lookups are sent to first, the last one that answered
+    current: usize,
+    pending: HashMap<u64, (usize, oneshot::Sender<RegisterResp>)>,
+    channels: HashSet<String>,
+    revocations: broadcast::Sender<String>,
+    ingress: Arc<AtomicU64>,
+    last_heartbeat: Instant,
+}
+
+impl Connection {
+    fn new(
+        socket: UdpSocket,
+        config: Arc<RegisterClientConfig>,
+        incoming: mpsc::UnboundedReceiver<Command>,
+        revocations: broadcast::Sender<String>,
+        ingress: Arc<AtomicU64>,
+    ) -> Self {
+        Self {
+            socket,
+            config,
+            incoming,
+            next_id: 1,
+            current: 0,
+            pending: HashMap::new(),
+            channels: HashSet::new(),
+            revocations,
+            ingress,
+            last_heartbeat: Instant::now(),
+        }
+    }
+
+    async fn run(mut self) {
+        let mut heartbeat = time::interval(self.config.heartbeat_interval);
+        let mut buf = vec![0u8; 1500];
+        loop {
+            tokio::select! {
+                command = self.incoming.recv() => match command {
+                    Some(command) => self.handle_command(command).await,
+                    None => break,
+                },
+                received = self.socket.recv_from(&mut buf) => match received {
+                    Ok((n, _)) => self.handle_resp(&buf[..n]),
+                    //the register is not up yet, the request is sent again on timeout
+                    Err(e) => log::debug!("register recv err {}", e),
+                },
+                _ = heartbeat.tick() => {
+                    //requests given up by their caller
+                    self.pending.retain(|_, (_, responder)| !responder.is_closed());
+                    self.heartbeat().await;
+                }
+            }
+        }
+    }
+
+    async fn handle_command(&mut self, command: Command) {
+        let register = match command {
+            Command::Request(mut register, attempt, responder) => {
+                register.id = self.next_id;
+                self.next_id += 1;
+                let index = (self.current + attempt as usize) % self.config.addrs.len();
+                self.pending.insert(register.id, (index, responder));
+                let buf: Bytes = register.try_into().unwrap();
+                return self.send_to(&buf, index).await;
+            }
+            Command::Set(channel_name) => {
+                self.channels.insert(channel_name.clone());
+                self.register(RegisterKind::Set, channel_name, vec![])
+            }
+            Command::Delete(channel_name) => {
+                self.channels.remove(&channel_name);
+                self.register(RegisterKind::Delete, channel_name, vec![])
+            }
+        };
+        self.send(register).await;
+    }
+
+    fn handle_resp(&mut self, buf: &[u8]) {
+        let resp = match RegisterResp::try_from(buf) {
+            Ok(resp) => resp,
+            Err(e) => return log::error!("invalid register resp {}", e),
+        };
+        if let RegisterRespKind::REVOKED = resp.kind {
+            //every register of the cluster may send it
+            if self.channels.remove(&resp.payload) {
+                match resp.node_id.as_str() {
+                    "" => log::warn!("channel {} evicted from the register", resp.payload),
+                    node_id => log::warn!("lease of {} taken by {}", resp.payload, node_id),
+                }
+                _ = self.revocations.send(resp.payload);
+            }
+            return;
+        }
+        //a late response of a request that was sent again
+        if let Some((index, responder)) = self.pending.remove(&resp.id) {
+            self.current = index;
+            _ = responder.send(resp);
+        }
+    }
+
+    async fn heartbeat(&mut self) {
+        let elapsed = self.last_heartbeat.elapsed().as_secs_f64();
+        self.last_heartbeat = Instant::now();
+        let bytes = self.ingress.swap(0, Ordering::Relaxed);
+        let load = Load {
+            channels: self.channels.len() as u32,
+            ingress_bps: (bytes as f64 * 8.0 / elapsed.max(0.001)) as u64,
+        };
+
+        let mut heartbeat = self.register(RegisterKind::Heartbeat, "".to_owned(), vec![]);
+        heartbeat.load = load;
+        //the heartbeat without channels, every channel adds its own encoded size
+        let header = bincode::serialized_size(&heartbeat).unwrap() as usize;
+        let mut batches = vec![vec![]];
+        let mut size = header;
+        for channel_name in &self.channels {
+            let channel_size = bincode::serialized_size(channel_name).unwrap() as usize;
+            if size + channel_size > MAX_DATAGRAM && size > header {
+                batches.push(vec![]);
+                size = header;
+            }
+            size += channel_size;
+            batches.last_mut().unwrap().push(channel_name.clone());
+        }
+        for batch in batches {
+            if !batch.is_empty() || self.config.report_load || self.config.role.is_some() {
+                self.send(Register {
+                    channels: batch,
+                    ..heartbeat.clone()
+                })
+                .await;
+            }
+        }
+    }
+
+    fn register(
+        &self,
+        kind: RegisterKind,
+        channel_name: String,
+        channels: Vec<String>,
+    ) -> Register {
+        Register {
+            id: 0,
+            kind,
+            channel_name,
+            channels,
+            node_id: self.config.node_id.clone(),
+            addr: self.config.advertise.clone(),
+            relayed: false,
+            load: Load::default(),
+            policy: PickPolicy::default(),
+            role: self.config.role,
+            monitor_addr: self.config.monitor_addr.clone(),
+        }
+    }
+
+    async fn send(&self, register: Register) {
+        let buf: Bytes = register.try_into().unwrap();
+        for index in 0..self.config.addrs.len() {
+            self.send_to(&buf, index).await;
+        }
+    }
+
+    async fn send_to(&self, buf: &[u8], index: usize) {
+        let addr = &self.config.addrs[index];
+        if let Err(e) = self.socket.send_to(buf, addr.as_str()).await {
+            log::debug!("register {} send err {}", addr, e);
+        }
+    }
+}
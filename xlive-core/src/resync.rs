@@ -0,0 +1,77 @@
+use crate::message::{MediaKind, MediaPacket};
+
+/// Filters what an upstream sends after a reconnect, kept by the cache and
+/// edge channels for every packet they get from upstream.
+#[derive(Debug, Default)]
+pub struct Resync {
+    resyncing: bool,
+    last_timestamp: Option<u32>,
+    //the upstream replays its cached gop from this keyframe or a later one
+    last_key_frame: Option<u32>,
+}
+
+impl Resync {
+    /// The upstream reconnected, players resume on the next keyframe.
+    pub fn start(&mut self) {
+        self.resyncing = true;
+    }
+
+    /// Whether the packet is forwarded, given the headers the channel holds.
+    pub fn accept(
+        &mut self,
+        packet: &MediaPacket,
+        metadata: &Option<MediaPacket>,
+        video_seq_header: &Option<MediaPacket>,
+        audio_seq_header: &Option<MediaPacket>,
+    ) -> bool {
+        let cached = match packet.kind {
+            MediaKind::Metadata => metadata,
+            MediaKind::Video if packet.is_seq_header => video_seq_header,
+            MediaKind::Audio if packet.is_seq_header => audio_seq_header,
+            _ => {
+                if self.resyncing {
+                    //a keyframe before the last one starts a new timeline, e.g. another origin
+                    //or a restarted publisher, audio only streams have no gop to replay
+                    let restarted = match packet.kind {
+                        MediaKind::Video => {
+                            packet.is_key_frame
+                                && matches!(self.last_key_frame, Some(key) if packet.timestamp < key)
+                        }
+                        _ => video_seq_header.is_none(),
+                    };
+                    if restarted {
+                        self.last_timestamp = None;
+                    }
+                    //the upstream replays its cached gop first, which players already have
+                    if matches!(self.last_timestamp, Some(last) if packet.timestamp <= last) {
+                        return false;
+                    }
+                    let resume = match packet.kind {
+                        MediaKind::Video => packet.is_key_frame,
+                        _ => video_seq_header.is_none(),
+                    };
+                    if !resume {
+                        return false;
+                    }
+                    self.resyncing = false;
+                }
+                if matches!(packet.kind, MediaKind::Video) && packet.is_key_frame {
+                    self.last_key_frame = Some(packet.timestamp);
+                }
+                self.last_timestamp = Some(packet.timestamp);
+                return true;
+            }
+        };
+        if !self.resyncing {
+            return true;
+        }
+        //metadata and seq headers are only sent again when they changed,
+        //changed seq headers come from another encoder, with its own timeline
+        let changed = !matches!(cached, Some(cached) if cached.payload == packet.payload);
+        if changed && packet.is_seq_header {
+            self.last_timestamp = None;
+            self.last_key_frame = None;
+        }
+        changed
+    }
+}
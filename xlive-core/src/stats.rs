@@ -0,0 +1,122 @@
+use crate::codec::{self, AudioCodec, VideoCodec};
+use crate::message::{MediaKind, MediaPacket};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+//bitrate and fps are averaged over this window
+const WINDOW: Duration = Duration::from_secs(5);
+
+/// Rolling stats of the stream a channel carries.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ChannelStats {
+    pub video_bps: u64,
+    pub audio_bps: u64,
+    pub fps: f64,
+    /// ms between the last two keyframes, in stream time
+    pub gop_ms: Option<u32>,
+    /// unix time in ms
+    pub last_key_frame: Option<u64>,
+    pub video: Option<VideoCodec>,
+    pub audio: Option<AudioCodec>,
+}
+
+struct Sample {
+    at: Instant,
+    video: bool,
+    bytes: usize,
+}
+
+/// Kept by a channel task, fed with every packet it receives.
+pub struct StatsCollector {
+    started: Instant,
+    samples: VecDeque<Sample>,
+    last_key_frame_timestamp: Option<u32>,
+    stats: ChannelStats,
+}
+
+impl Default for StatsCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatsCollector {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            samples: VecDeque::new(),
+            last_key_frame_timestamp: None,
+            stats: ChannelStats::default(),
+        }
+    }
+
+    pub fn update(&mut self, packet: &MediaPacket) {
+        let video = match packet.kind {
+            MediaKind::Metadata => return,
+            MediaKind::Video => true,
+            MediaKind::Audio => false,
+        };
+        if packet.is_seq_header {
+            if video {
+                self.stats.video = codec::parse_video_seq_header(&packet.payload);
+            } else {
+                self.stats.audio = codec::parse_audio_seq_header(&packet.payload);
+            }
+            return;
+        }
+        let now = Instant::now();
+        self.samples.push_back(Sample {
+            at: now,
+            video,
+            bytes: packet.payload.len(),
+        });
+        self.trim(now);
+        if video && packet.is_key_frame {
+            if let Some(last) = self.last_key_frame_timestamp {
+                self.stats.gop_ms = Some(packet.timestamp.wrapping_sub(last));
+            }
+            self.last_key_frame_timestamp = Some(packet.timestamp);
+            self.stats.last_key_frame = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_millis() as u64);
+        }
+    }
+
+    /// Stats of the last window, a stalled stream goes down to zero.
+    pub fn stats(&mut self) -> ChannelStats {
+        let now = Instant::now();
+        self.trim(now);
+        //a channel younger than the window is averaged over its age
+        let secs = now
+            .duration_since(self.started)
+            .min(WINDOW)
+            .as_secs_f64()
+            .max(1.0);
+        let (mut video_bytes, mut audio_bytes, mut frames) = (0, 0, 0);
+        for sample in &self.samples {
+            if sample.video {
+                video_bytes += sample.bytes;
+                frames += 1;
+            } else {
+                audio_bytes += sample.bytes;
+            }
+        }
+        ChannelStats {
+            video_bps: (video_bytes as f64 * 8.0 / secs) as u64,
+            audio_bps: (audio_bytes as f64 * 8.0 / secs) as u64,
+            fps: (frames as f64 / secs * 10.0).round() / 10.0,
+            ..self.stats.clone()
+        }
+    }
+
+    fn trim(&mut self, now: Instant) {
+        while let Some(sample) = self.samples.front() {
+            if now.duration_since(sample.at) < WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+}
@@ -1,22 +1,153 @@
 use std::collections::HashMap;
 
 use crate::message::MediaPacket;
+use crate::stats::ChannelStats;
 use crate::{AppName, Event, StreamKey};
+use serde::Serialize;
 use tokio::sync::{broadcast, mpsc, oneshot};
 
 pub type Responder<P> = oneshot::Sender<P>;
 
-pub enum JoinResp {
-    Local(Handle, Watcher),
-    Origin(Handle, Watcher),
+/// Cached packets a new subscriber needs before it can decode the live stream.
+#[derive(Clone, Default)]
+pub struct InitData {
+    pub metadata: Option<MediaPacket>,
+    pub video_seq_header: Option<MediaPacket>,
+    pub audio_seq_header: Option<MediaPacket>,
+    pub gop: Option<Vec<MediaPacket>>,
+}
+
+impl InitData {
+    /// Packets in the order they have to be sent to a player.
+    pub fn into_packets(self) -> Vec<MediaPacket> {
+        let mut packets = vec![];
+        packets.extend(self.metadata);
+        packets.extend(self.video_seq_header);
+        packets.extend(self.audio_seq_header);
+        if let Some(gop) = self.gop {
+            packets.extend(gop);
+        }
+        packets
+    }
+}
+
+/// Init data and subscription are taken by the channel task in the same step,
+/// so the cached gop and the live packets join without gaps or duplicates.
+pub struct JoinResp {
+    pub init_data: InitData,
+    pub watcher: Watcher,
+}
+
+/// A publisher accepted by the channel, a backup only goes live when the current publisher leaves.
+pub struct Publishing {
+    pub handle: Handle,
+    pub backup: bool,
+    /// Gets the reason when the channel drops the publisher, e.g. kicked by a newer one.
+    pub dropped: oneshot::Receiver<String>,
+}
+
+/// The error is the reason reported to the rejected publisher.
+pub type PublishResp = Result<Publishing, String>;
+
+/// Why a channel moved to another publisher.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SwitchReason {
+    Kick,
+    Disconnect,
+    Stall,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SwitchEvent {
+    /// unix time in seconds
+    pub at: u64,
+    pub from: u64,
+    pub to: u64,
+    pub reason: SwitchReason,
+}
+
+/// What the monitor api reports for a channel.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ChannelInfo {
+    pub subscribers: usize,
+    /// end users, the rtmp and http-flv players of an edge
+    pub viewers: usize,
+    /// caches and edges pulling the channel from this node
+    pub relays: usize,
+    /// address the channel is pulled from, or pushed to by a publishing edge
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+    /// times the upstream connection of the channel was re-established
+    pub reconnects: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub switches: Vec<SwitchEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<ChannelStats>,
 }
 
 pub enum ChannelMessage {
-    Create((AppName, StreamKey, Responder<Handle>)),
+    Create((AppName, StreamKey, Responder<PublishResp>)),
     Release(AppName),
     Join((AppName, Responder<JoinResp>)),
     RegisterTrigger(Event, Trigger),
-    Snapshot(Responder<HashMap<AppName, usize>>),
+    Snapshot(Responder<HashMap<AppName, ChannelInfo>>),
+    //sent by a channel when it moved to another publisher
+    Switched((AppName, SwitchEvent)),
+    //sent by a channel every second
+    Stats((AppName, ChannelStats)),
+    //sent by the upstream relay of a channel when it reconnected
+    Upstream((AppName, String)),
+    //sent by a connection serving the channel to a cache or edge, true when it starts
+    Relay((AppName, bool)),
+}
+
+/// Counts a connection as a relay of the channel, a cache or edge pulling it, while it is alive.
+pub struct RelayGuard {
+    name: AppName,
+    manager_handle: ManagerHandle,
+}
+
+impl RelayGuard {
+    pub fn new(name: AppName, manager_handle: ManagerHandle) -> Self {
+        _ = manager_handle.send(ChannelMessage::Relay((name.clone(), true)));
+        Self {
+            name,
+            manager_handle,
+        }
+    }
+}
+
+impl Drop for RelayGuard {
+    fn drop(&mut self) {
+        let name = std::mem::take(&mut self.name);
+        _ = self
+            .manager_handle
+            .send(ChannelMessage::Relay((name, false)));
+    }
+}
+
+/// Relays of every channel, kept by a manager from the `Relay` messages.
+#[derive(Default)]
+pub struct Relays(HashMap<AppName, usize>);
+
+impl Relays {
+    //a relay outlives a released channel until its connection is closed
+    pub fn update(&mut self, name: AppName, started: bool) {
+        let count = self.0.entry(name.clone()).or_default();
+        if started {
+            *count += 1;
+        } else {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.0.remove(&name);
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> usize {
+        self.0.get(name).copied().unwrap_or_default()
+    }
 }
 
 pub type ManagerHandle = mpsc::UnboundedSender<ChannelMessage>;
@@ -32,14 +163,11 @@ pub fn trigger_channel() -> (Trigger, TriggerHandle) {
 pub enum Message {
     Packet(MediaPacket),
     PacketFromOrigin(MediaPacket),
-    InitData(
-        Responder<(
-            Option<MediaPacket>,
-            Option<MediaPacket>,
-            Option<MediaPacket>,
-            Option<Vec<MediaPacket>>,
-        )>,
-    ),
+    Join(Responder<JoinResp>),
+    //attach a publisher, answered with the handle it sends packets to
+    Publish(Responder<PublishResp>),
+    //the upstream connection was re-established, the stream may be discontinuous
+    Reconnected,
     Disconnect,
 }
 
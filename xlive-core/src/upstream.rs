@@ -0,0 +1,181 @@
+use crate::message::{Kind, MediaPacket, ProtoMessage};
+use crate::metrics::{GaugeGuard, METRICS, UPSTREAM};
+use crate::transport::{ChannelMessage, Handle, ManagerHandle, Message};
+use crate::{AppName, RegisterClient};
+use anyhow::{bail, Result};
+use futures::{SinkExt, StreamExt};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::time;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_millis(200);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(5);
+pub const PULL_TIMEOUT: Duration = Duration::from_secs(5);
+//players are dropped when no upstream can be reached again in this time
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A connection to the origin or cache a channel is pulled from or pushed to.
+pub type UpstreamFrame = Framed<TcpStream, LengthDelimitedCodec>;
+
+//define the kind of upstream for cache and edge,
+//a list of addresses is tried in order when the upstream connection breaks
+#[derive(Clone, Debug)]
+pub enum Upstream {
+    Register(RegisterClient),
+    Addr(Vec<String>),
+}
+
+impl Upstream {
+    pub fn from_addrs(addrs: &str) -> Self {
+        Self::Addr(addrs.split(',').map(|a| a.trim().to_owned()).collect())
+    }
+}
+
+//forward packets from the upstream to the channel, and reconnect to the
+//same or the next upstream when the connection breaks, players stay attached
+pub async fn relay(
+    name: AppName,
+    mut frame: UpstreamFrame,
+    upstream: Upstream,
+    handle: Handle,
+    manager_handle: ManagerHandle,
+) {
+    loop {
+        match forward(&mut frame, &handle).await {
+            Ok(()) => return,
+            Err(e) => log::error!("channel {} upstream err {}", name, e),
+        }
+        frame = match reconnect(&name, &upstream, &handle).await {
+            Some(frame) => frame,
+            None => {
+                if handle.send(Message::Disconnect).is_ok() {
+                    log::error!("channel {} upstream lost, disconnect players", name);
+                }
+                return;
+            }
+        };
+        if let Ok(addr) = frame.get_ref().peer_addr() {
+            _ = manager_handle.send(ChannelMessage::Upstream((name.clone(), addr.to_string())));
+        }
+        if handle.send(Message::Reconnected).is_err() {
+            return;
+        }
+    }
+}
+
+//returns Ok when the channel is released, Err when the upstream connection breaks
+async fn forward(frame: &mut UpstreamFrame, handle: &Handle) -> Result<()> {
+    let _connected = GaugeGuard::new(METRICS.upstream_connections.clone());
+    loop {
+        let data = tokio::select! {
+            next = frame.next() => match next {
+                Some(data) => data?,
+                None => bail!("upstream closed"),
+            },
+            //the channel is released, close the upstream connection
+            _ = handle.closed() => return Ok(()),
+        };
+        let proto_msg = ProtoMessage::try_from(data.freeze())?;
+        match proto_msg.kind {
+            Kind::Errors => bail!("ProtoMessage chanle err {:?}", proto_msg.payload),
+            //a misbehaving or newer upstream, reconnect
+            Kind::Init | Kind::Ok => bail!("unexpected {:?} from upstream", proto_msg.kind),
+            Kind::Media => {
+                METRICS
+                    .bytes_received
+                    .with_label_values(&[UPSTREAM])
+                    .inc_by(proto_msg.payload.len() as u64);
+                let media_packet = MediaPacket::try_from(proto_msg.payload)?;
+                if handle
+                    .send(Message::PacketFromOrigin(media_packet))
+                    .is_err()
+                {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+async fn reconnect(name: &str, upstream: &Upstream, handle: &Handle) -> Option<UpstreamFrame> {
+    let started = Instant::now();
+    let mut backoff = RECONNECT_BACKOFF_MIN;
+    let mut attempt = 1;
+    while started.elapsed() < RECONNECT_TIMEOUT {
+        tokio::select! {
+            _ = time::sleep(backoff) => {}
+            _ = handle.closed() => return None,
+        }
+        match time::timeout(PULL_TIMEOUT, pull(upstream, name, attempt)).await {
+            Ok(Ok(frame)) => {
+                log::info!("channel {} upstream reconnected", name);
+                METRICS.upstream_reconnects.inc();
+                return Some(frame);
+            }
+            Ok(Err(e)) => log::warn!("channel {} reconnect #{} err {}", name, attempt, e),
+            Err(_) => log::warn!("channel {} reconnect #{} timeout", name, attempt),
+        }
+        attempt += 1;
+        backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+    }
+    None
+}
+
+//resolve the upstream of a channel and join it as a player,
+//runs outside of the manager loop so a slow upstream does not block other messages,
+//`attempt` picks the next configured upstream address on reconnect
+pub async fn pull(upstream: &Upstream, name: &str, attempt: usize) -> Result<UpstreamFrame> {
+    let addr = match upstream {
+        Upstream::Register(client) => match client.lookup(name).await? {
+            Some(origin) => {
+                log::info!(
+                    "got origin {} add from register {:?}",
+                    origin.node_id,
+                    origin.addr
+                );
+                origin.addr
+            }
+            None => bail!("publisher in not found"),
+        },
+        Upstream::Addr(addrs) => addrs[attempt % addrs.len()].clone(),
+    };
+
+    log::info!("TcpStream::connect {}", &addr);
+    let stream = match TcpStream::connect(&addr).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            //the cached origin may be gone
+            if let Upstream::Register(client) = upstream {
+                client.forget(name);
+            }
+            return Err(e.into());
+        }
+    };
+    let mut frame = Framed::new(stream, LengthDelimitedCodec::new());
+    frame
+        .send(ProtoMessage::new_proto_init(false, name).into())
+        .await?;
+
+    match frame.next().await {
+        Some(Ok(data)) => {
+            let proto_msg = ProtoMessage::try_from(data.freeze())?;
+            match proto_msg.kind {
+                Kind::Errors => {
+                    log::error!("join chanle err");
+                    bail!("join chanle err");
+                }
+                Kind::Init | Kind::Media => {
+                    bail!(
+                        "unexpected {:?} from upstream {} on join",
+                        proto_msg.kind,
+                        addr
+                    )
+                }
+                Kind::Ok => {}
+            }
+        }
+        _ => bail!("upstream {} closed before join", addr),
+    }
+    Ok(frame)
+}
@@ -0,0 +1,9 @@
+//the crate is named `core` itself, which `#[tokio::test]` expands to,
+//and it has no multi threaded runtime
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use common::block_on;
+use core::register::{Register, RegisterKind};
+use core::register_client::{RegisterClient, RegisterClientConfig};
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time;
+
+#[test]
+fn heartbeats_fit_in_a_datagram() {
+    block_on(async {
+        //a register that never answers, only the heartbeats are looked at
+        let socket = UdpSocket::bind("127.0.0.1:29686").await.unwrap();
+        let mut config = RegisterClientConfig::new("127.0.0.1:29686");
+        //a long header leaves less room for the channels
+        config.node_id = "n".repeat(200);
+        config.advertise = "a".repeat(200);
+        config.monitor_addr = "m".repeat(200);
+        config.heartbeat_interval = Duration::from_millis(100);
+        let client = RegisterClient::connect(config).await.unwrap();
+        let channels: HashSet<String> = (0..100).map(|i| format!("live/room-{:03}", i)).collect();
+        for channel_name in &channels {
+            client.set(channel_name);
+        }
+
+        let mut heartbeat = HashSet::new();
+        let mut buf = vec![0; 65536];
+        while heartbeat != channels {
+            let (len, _) = time::timeout(Duration::from_secs(3), socket.recv_from(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            let register = Register::try_from(&buf[..len]).unwrap();
+            if let RegisterKind::Heartbeat = register.kind {
+                assert!(len <= 1200, "heartbeat of {} bytes", len);
+                heartbeat.extend(register.channels);
+            }
+        }
+    });
+}
@@ -0,0 +1,21 @@
+use core::http::{bearer_matches, parse_query, percent_decode};
+
+#[test]
+fn bearer_token() {
+    assert!(bearer_matches(Some("Bearer secret"), "secret"));
+    assert!(!bearer_matches(Some("Bearer other"), "secret"));
+    assert!(!bearer_matches(Some("Bearer secrets"), "secret"));
+    assert!(!bearer_matches(Some("secret"), "secret"));
+    assert!(!bearer_matches(None, "secret"));
+}
+
+#[test]
+fn query_is_decoded() {
+    let query = parse_query("channel=live%2Froom&node=edge+a&empty=&flag");
+    assert_eq!(query.len(), 3);
+    assert_eq!(query["channel"], "live/room");
+    assert_eq!(query["node"], "edge a");
+    assert_eq!(query["empty"], "");
+    assert_eq!(percent_decode("100%"), "100%");
+    assert_eq!(percent_decode("%zz%e4%b8%ad"), "%zz中");
+}
@@ -0,0 +1,103 @@
+use bytes::Bytes;
+use core::message::{MediaKind, MediaPacket};
+use core::resync::Resync;
+
+fn packet(kind: MediaKind, timestamp: u32, is_key_frame: bool, is_seq_header: bool) -> MediaPacket {
+    MediaPacket {
+        kind,
+        is_seq_header,
+        is_key_frame,
+        timestamp,
+        payload: Bytes::from_static(b"payload"),
+    }
+}
+
+fn video(timestamp: u32, is_key_frame: bool) -> MediaPacket {
+    packet(MediaKind::Video, timestamp, is_key_frame, false)
+}
+
+fn audio(timestamp: u32) -> MediaPacket {
+    packet(MediaKind::Audio, timestamp, false, false)
+}
+
+#[test]
+fn replayed_gop_is_dropped_after_reconnect() {
+    let header = Some(packet(MediaKind::Video, 0, true, true));
+    let mut resync = Resync::default();
+    let accept =
+        |resync: &mut Resync, packet: MediaPacket| resync.accept(&packet, &None, &header, &None);
+    assert!(accept(&mut resync, video(0, true)));
+    assert!(accept(&mut resync, video(40, false)));
+    assert!(accept(&mut resync, video(80, false)));
+
+    resync.start();
+    //unchanged headers are not sent again
+    assert!(!accept(
+        &mut resync,
+        packet(MediaKind::Video, 0, true, true)
+    ));
+    //the gop cached by the upstream
+    assert!(!accept(&mut resync, video(0, true)));
+    assert!(!accept(&mut resync, video(40, false)));
+    assert!(!accept(&mut resync, video(80, true)));
+    //live again, but not on a keyframe yet
+    assert!(!accept(&mut resync, video(120, false)));
+    assert!(!accept(&mut resync, audio(130)));
+    assert!(accept(&mut resync, video(160, true)));
+    assert!(accept(&mut resync, audio(150)));
+    assert!(accept(&mut resync, video(200, false)));
+}
+
+#[test]
+fn audio_only_resumes_after_reconnect() {
+    let header = Some(packet(MediaKind::Audio, 0, false, true));
+    let mut resync = Resync::default();
+    let accept =
+        |resync: &mut Resync, packet: MediaPacket| resync.accept(&packet, &None, &None, &header);
+    assert!(accept(&mut resync, audio(0)));
+    assert!(accept(&mut resync, audio(20)));
+
+    resync.start();
+    assert!(!accept(
+        &mut resync,
+        packet(MediaKind::Audio, 0, false, true)
+    ));
+    //no gop is replayed, another origin may start again from 0
+    assert!(accept(&mut resync, audio(0)));
+    assert!(accept(&mut resync, audio(20)));
+}
+
+#[test]
+fn failover_resumes_on_a_restarted_timeline() {
+    let header = Some(packet(MediaKind::Video, 0, true, true));
+    let mut resync = Resync::default();
+    let accept =
+        |resync: &mut Resync, packet: MediaPacket| resync.accept(&packet, &None, &header, &None);
+    for i in 0..100 {
+        assert!(accept(&mut resync, video(3_600_000 + i * 40, i % 10 == 0)));
+    }
+
+    //another origin, its timestamps start again from 0
+    resync.start();
+    assert!(!accept(&mut resync, video(40, false)));
+    assert!(accept(&mut resync, video(80, true)));
+    assert!(accept(&mut resync, audio(90)));
+    assert!(accept(&mut resync, video(120, false)));
+}
+
+#[test]
+fn changed_headers_are_sent_after_reconnect() {
+    let header = Some(packet(MediaKind::Video, 0, true, true));
+    let mut resync = Resync::default();
+    let changed = MediaPacket {
+        payload: Bytes::from_static(b"changed"),
+        ..packet(MediaKind::Video, 0, true, true)
+    };
+    assert!(resync.accept(&video(0, true), &None, &header, &None));
+    assert!(resync.accept(&video(40, false), &None, &header, &None));
+
+    //a new encoder, with its own timeline
+    resync.start();
+    assert!(resync.accept(&changed, &None, &header, &None));
+    assert!(resync.accept(&video(0, true), &None, &header, &None));
+}
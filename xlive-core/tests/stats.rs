@@ -0,0 +1,113 @@
+use bytes::Bytes;
+use core::codec::{parse_audio_seq_header, parse_video_seq_header, AudioCodec, VideoCodec};
+use core::message::{MediaKind, MediaPacket};
+use core::stats::StatsCollector;
+
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+//flv video tag of an AVCDecoderConfigurationRecord with one sps and one pps
+fn avc_seq_header(sps: &str) -> Vec<u8> {
+    let sps = hex(sps);
+    let mut payload = vec![0x17, 0, 0, 0, 0, 1, sps[1], sps[2], sps[3], 0xff, 0xe1];
+    payload.extend((sps.len() as u16).to_be_bytes());
+    payload.extend(&sps);
+    payload.extend([1, 0, 4, 0x68, 0xce, 0x3c, 0x80]);
+    payload
+}
+
+fn video(timestamp: u32, is_key_frame: bool, is_seq_header: bool, payload: Vec<u8>) -> MediaPacket {
+    MediaPacket {
+        kind: MediaKind::Video,
+        is_seq_header,
+        is_key_frame,
+        timestamp,
+        payload: Bytes::from(payload),
+    }
+}
+
+#[test]
+fn parse_avc_decoder_config() {
+    assert_eq!(
+        parse_video_seq_header(&avc_seq_header("6742c01eda0280f640")),
+        Some(VideoCodec {
+            codec: "h264".to_owned(),
+            profile: "Baseline".to_owned(),
+            level: "3.0".to_owned(),
+            width: 640,
+            height: 480,
+        })
+    );
+    //1920x1088 cropped to 1080
+    assert_eq!(
+        parse_video_seq_header(&avc_seq_header("67640028acca501e0089f950")),
+        Some(VideoCodec {
+            codec: "h264".to_owned(),
+            profile: "High".to_owned(),
+            level: "4.0".to_owned(),
+            width: 1920,
+            height: 1080,
+        })
+    );
+    assert_eq!(parse_video_seq_header(&[0x17, 0, 0, 0, 0]), None);
+}
+
+#[test]
+fn parse_hevc_decoder_config() {
+    let sps = hex("42010101600000030090000003000003007ba003c0801107cbe0");
+    let mut payload = vec![0x1c, 0, 0, 0, 0];
+    payload.extend([1, 1, 0x60, 0, 0, 0, 0x90, 0, 0, 0, 0, 0, 123]);
+    payload.extend([0xf0, 0, 0xfc, 0xfd, 0xf8, 0xf8, 0, 0, 0x0f]);
+    //a vps array, then the sps array
+    payload.extend([2, 0x20, 0, 1, 0, 2, 0x40, 0x01, 0x21, 0, 1]);
+    payload.extend((sps.len() as u16).to_be_bytes());
+    payload.extend(&sps);
+    assert_eq!(
+        parse_video_seq_header(&payload),
+        Some(VideoCodec {
+            codec: "h265".to_owned(),
+            profile: "Main".to_owned(),
+            level: "4.1".to_owned(),
+            width: 1920,
+            height: 1080,
+        })
+    );
+}
+
+#[test]
+fn parse_aac_audio_specific_config() {
+    assert_eq!(
+        parse_audio_seq_header(&[0xaf, 0, 0x12, 0x10]),
+        Some(AudioCodec {
+            codec: "aac".to_owned(),
+            profile: "LC".to_owned(),
+            sample_rate: 44100,
+            channels: 2,
+        })
+    );
+    //mp3 has no seq header to parse
+    assert_eq!(parse_audio_seq_header(&[0x2f, 0, 0x12, 0x10]), None);
+}
+
+#[test]
+fn collect_channel_stats() {
+    let mut collector = StatsCollector::new();
+    collector.update(&video(0, true, true, avc_seq_header("6742c01eda0280f640")));
+    for i in 0..60u32 {
+        //a keyframe every second at 30 fps
+        collector.update(&video(i * 33, i % 30 == 0, false, vec![0; 1000]));
+    }
+    let stats = collector.stats();
+    assert_eq!(stats.video.unwrap().width, 640);
+    assert!(stats.audio.is_none());
+    assert_eq!(stats.gop_ms, Some(990));
+    assert!(stats.last_key_frame.is_some());
+    //a young channel is averaged over one second
+    assert_eq!(stats.fps, 60.0);
+    assert_eq!(stats.video_bps, 60 * 1000 * 8);
+    assert_eq!(stats.audio_bps, 0);
+}
@@ -1,20 +1,34 @@
 use anyhow::{bail, Result};
-use core::message::{MediaKind, MediaPacket, ProtoMessage};
-use core::transport::{IncomingBroadcast, Message, OutgoingBroadcast};
-use futures::SinkExt;
-use tokio::net::TcpStream;
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use bytes::BytesMut;
+use core::message::{Kind, MediaKind, MediaPacket, ProtoMessage};
+use core::metrics::{GaugeGuard, METRICS, XLIVE};
+use core::resync::Resync;
+use core::stats::StatsCollector;
+use core::transport::{IncomingBroadcast, InitData, JoinResp, Message, OutgoingBroadcast};
+use core::upstream::UpstreamFrame;
+use core::{ChannelMessage, ManagerHandle};
+use futures::{SinkExt, StreamExt};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tokio::time;
 pub struct Channel {
     name: String,
     incoming: IncomingBroadcast,
     outgoing: OutgoingBroadcast,
+    manager_handle: ManagerHandle,
+    idle_timeout: Option<Duration>,
+    idle_since: Option<Instant>,
     metadata: Option<MediaPacket>,
     video_seq_header: Option<MediaPacket>,
     audio_seq_header: Option<MediaPacket>,
     gop: Option<Vec<MediaPacket>>,
     closing: bool,
+    resync: Resync,
     full_gop: bool,
-    frame: Option<Framed<TcpStream, LengthDelimitedCodec>>,
+    stats: StatsCollector,
+    frame: Option<UpstreamFrame>,
+    //tells the publisher why the origin dropped it
+    dropper: Option<oneshot::Sender<String>>,
 }
 
 impl Channel {
@@ -22,49 +36,134 @@ impl Channel {
         name: String,
         incoming: IncomingBroadcast,
         outgoing: OutgoingBroadcast,
+        manager_handle: ManagerHandle,
+        idle_timeout: Option<Duration>,
         full_gop: bool,
     ) -> Self {
         Self {
             name,
             incoming,
             outgoing,
+            manager_handle,
+            idle_timeout,
+            idle_since: None,
             metadata: None,
             video_seq_header: None,
             audio_seq_header: None,
             gop: None,
             closing: false,
+            resync: Resync::default(),
             full_gop,
+            stats: StatsCollector::new(),
             frame: None,
+            dropper: None,
         }
     }
 
-    pub async fn run(mut self, from_cluster: bool, origin_addr: String) -> Result<()> {
-        if !from_cluster {
-            let stream = TcpStream::connect(origin_addr).await?;
-            self.frame = Some(Framed::new(stream, LengthDelimitedCodec::new()));
-            self.frame
-                .as_mut()
-                .unwrap()
-                .send(ProtoMessage::new_proto_init(true, &self.name).into())
-                .await?;
-        }
-
+    //a published channel pushes its packets to the origin over `frame`
+    pub async fn run(
+        mut self,
+        frame: Option<UpstreamFrame>,
+        dropper: Option<oneshot::Sender<String>>,
+    ) -> Result<()> {
+        self.frame = frame;
+        self.dropper = dropper;
+        let _channel = GaugeGuard::new(METRICS.channels.clone());
+        let mut interval = time::interval(Duration::from_secs(1));
         while !self.closing {
-            if let Some(message) = self.incoming.recv().await {
-                self.handle_message(message).await?;
+            tokio::select! {
+                message = self.incoming.recv() => match message {
+                    Some(message) => self.handle_message(message).await?,
+                    None => break,
+                },
+                reply = origin_reply(&mut self.frame), if self.frame.is_some() => {
+                    self.origin_dropped(reply);
+                }
+                _ = interval.tick() => {
+                    self.check_idle();
+                    self.report_stats();
+                }
             }
         }
         Ok(())
     }
 
+    //the origin only talks to a publisher to drop it
+    fn origin_dropped(&mut self, reply: Option<std::io::Result<BytesMut>>) {
+        let reason = match reply.map(|data| ProtoMessage::try_from(data?.freeze())) {
+            Some(Ok(ProtoMessage {
+                kind: Kind::Errors,
+                payload,
+            })) => String::from_utf8_lossy(&payload).into_owned(),
+            Some(Ok(proto_msg)) => format!("unexpected {:?}", proto_msg.kind),
+            Some(Err(e)) => e.to_string(),
+            None => "connection closed".to_owned(),
+        };
+        log::warn!("channel {} dropped by origin: {}", self.name, reason);
+        if let Some(dropper) = self.dropper.take() {
+            _ = dropper.send(reason);
+        }
+        self.release();
+    }
+
+    fn report_stats(&mut self) {
+        _ = self.manager_handle.send(ChannelMessage::Stats((
+            self.name.clone(),
+            self.stats.stats(),
+        )));
+    }
+
+    fn check_idle(&mut self) {
+        let idle_timeout = match self.idle_timeout {
+            Some(idle_timeout) => idle_timeout,
+            None => return,
+        };
+        if self.outgoing.receiver_count() != 0 {
+            self.idle_since = None;
+            return;
+        }
+        let idle_since = *self.idle_since.get_or_insert_with(Instant::now);
+        if idle_since.elapsed() >= idle_timeout {
+            log::info!(
+                "channel {} has no player for {:?}, release it",
+                self.name,
+                idle_timeout
+            );
+            self.release();
+        }
+    }
+
+    //stop taking messages and hand the channel back to the manager,
+    //dropping the incoming receiver also stops the upstream reader
+    fn release(&mut self) {
+        self.incoming.close();
+        _ = self
+            .manager_handle
+            .send(ChannelMessage::Release(self.name.clone()));
+        //joins raced with the release go back to the manager, which pulls the channel again
+        while let Ok(message) = self.incoming.try_recv() {
+            if let Message::Join(responder) = message {
+                _ = self
+                    .manager_handle
+                    .send(ChannelMessage::Join((self.name.clone(), responder)));
+            }
+        }
+        self.closing = true;
+    }
+
     async fn handle_message(&mut self, message: Message) -> Result<()> {
         match message {
             Message::Packet(packet) => {
+                self.stats.update(&packet);
                 if let Err(e) = self.set_cache(&packet) {
                     bail!("Failed to set channel cache {}", e);
                 }
 
                 let media_packet = packet.clone();
+                METRICS
+                    .bytes_sent
+                    .with_label_values(&[XLIVE])
+                    .inc_by(media_packet.payload.len() as u64);
                 match self
                     .frame
                     .as_mut()
@@ -81,24 +180,43 @@ impl Channel {
             }
 
             Message::PacketFromOrigin(packet) => {
+                if !self.resync.accept(
+                    &packet,
+                    &self.metadata,
+                    &self.video_seq_header,
+                    &self.audio_seq_header,
+                ) {
+                    return Ok(());
+                }
+                //the replayed gop is not counted twice
+                self.stats.update(&packet);
                 self.set_cache(&packet)?;
                 self.broadcast_packet(packet)?;
             }
 
-            Message::InitData(responder) => {
-                let response = (
-                    self.metadata.clone(),
-                    self.video_seq_header.clone(),
-                    self.audio_seq_header.clone(),
-                    self.gop.clone(),
-                );
-                if responder.send(response).is_err() {
-                    bail!("Failed to send init datar");
+            Message::Join(responder) => {
+                let resp = JoinResp {
+                    init_data: InitData {
+                        metadata: self.metadata.clone(),
+                        video_seq_header: self.video_seq_header.clone(),
+                        audio_seq_header: self.audio_seq_header.clone(),
+                        gop: self.gop.clone(),
+                    },
+                    watcher: self.outgoing.subscribe(),
+                };
+                if responder.send(resp).is_err() {
+                    log::error!("Failed to send join response");
                 }
             }
-            Message::Disconnect => {
-                self.closing = true;
+            Message::Publish(_) => unreachable!(),
+            Message::Reconnected => {
+                log::info!(
+                    "channel {} upstream reconnected, wait for keyframe",
+                    self.name
+                );
+                self.resync.start();
             }
+            Message::Disconnect => self.release(),
         }
         Ok(())
     }
@@ -142,3 +260,10 @@ impl Drop for Channel {
         log::info!("channel {} closed", self.name);
     }
 }
+
+async fn origin_reply(frame: &mut Option<UpstreamFrame>) -> Option<std::io::Result<BytesMut>> {
+    match frame {
+        Some(frame) => frame.next().await,
+        None => std::future::pending().await,
+    }
+}
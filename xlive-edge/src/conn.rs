@@ -1,8 +1,10 @@
 use crate::packet::{Packet, PacketType};
 use crate::rtmp::{Event, Protocol};
 use anyhow::Result;
+use bytes::Bytes;
 use core::message::MediaPacket;
-use core::transport::JoinResp;
+use core::metrics::{GaugeGuard, METRICS, RTMP};
+use core::transport::{InitData, JoinResp};
 use core::{ChannelMessage, Handle, ManagerHandle, Message, Watcher};
 use futures::SinkExt;
 use std::time::Duration;
@@ -17,12 +19,10 @@ use tokio_util::codec::{BytesCodec, Framed};
 type ReturnQueue<P> = (mpsc::UnboundedSender<P>, mpsc::UnboundedReceiver<P>);
 const TIME_OUT: std::time::Duration = Duration::from_secs(5);
 
-type NeedInitData = bool; // first connection to orgin get init data from origin,but others from local
-
 enum State {
     Initializing,
-    Publishing(Handle),
-    Playing(Handle, Watcher, NeedInitData),
+    Publishing(Handle, oneshot::Receiver<String>),
+    Playing(Watcher, Option<InitData>),
     Disconnecting,
 }
 
@@ -34,6 +34,8 @@ pub struct Connection {
     proto: Protocol,
     app_name: Option<String>,
     state: State,
+    //counts the connection as a publisher or player while it is alive
+    gauge: Option<GaugeGuard>,
 }
 
 impl Connection {
@@ -46,6 +48,7 @@ impl Connection {
             proto: Protocol::new(),
             app_name: None,
             state: State::Initializing,
+            gauge: None,
         }
     }
 
@@ -58,10 +61,14 @@ impl Connection {
             }
 
             match &mut self.state {
-                State::Initializing | State::Publishing(_) => {
+                State::Initializing | State::Publishing(..) => {
                     let val = self.bytes_stream.try_next();
                     match timeout(TIME_OUT, val).await? {
                         Ok(Some(data)) => {
+                            METRICS
+                                .bytes_received
+                                .with_label_values(&[RTMP])
+                                .inc_by(data.len() as u64);
                             for event in self.proto.handle_bytes(&data)? {
                                 self.handle_event(event).await?;
                             }
@@ -69,12 +76,14 @@ impl Connection {
                         _ => self.disconnect()?,
                     }
                 }
-                State::Playing(_, watcher, _) => {
+                State::Playing(watcher, _) => {
                     use tokio::sync::broadcast::error::RecvError;
                     match watcher.recv().await {
                         Ok(packet) => self.send_back(packet)?,
                         Err(RecvError::Closed) => self.disconnect()?,
-                        Err(_) => (),
+                        Err(RecvError::Lagged(n)) => {
+                            METRICS.lagged_packets.with_label_values(&[RTMP]).inc_by(n)
+                        }
                     }
                 }
                 State::Disconnecting => {
@@ -91,7 +100,11 @@ impl Connection {
             PacketType::Video => self.proto.pack_video(packet)?,
             PacketType::Audio => self.proto.pack_audio(packet)?,
         };
-        let res = timeout(TIME_OUT, self.bytes_stream.send(bytes.into())).await?;
+        METRICS
+            .bytes_sent
+            .with_label_values(&[RTMP])
+            .inc_by(bytes.len() as u64);
+        let res = timeout(TIME_OUT, self.bytes_stream.send(Bytes::from(bytes))).await?;
         Ok(res?)
     }
 
@@ -104,13 +117,19 @@ impl Connection {
                     .expect("Failed to return data");
             }
             Event::SendPacket(packet) => {
-                if let State::Publishing(session) = &mut self.state {
-                    session
-                        .send(Message::Packet(packet.into()))
-                        .map_err(|_| anyhow::anyhow!("ChannelSendFailed"))?;
+                if let State::Publishing(session, dropped) = &mut self.state {
+                    if session.send(Message::Packet(packet.into())).is_err() {
+                        //its publish request is accepted already, the session can not answer it again
+                        let reason = dropped
+                            .try_recv()
+                            .unwrap_or_else(|_| "channel closed".to_owned());
+                        log::info!("Client {} publish dropped: {}", self.id, reason);
+                        self.state = State::Disconnecting;
+                    }
                 }
             }
             Event::AcquireChannel {
+                request_id,
                 app_name,
                 stream_key,
             } => {
@@ -119,54 +138,52 @@ impl Connection {
                 self.manager_handle
                     .send(ChannelMessage::Create((app_name, stream_key, request)))
                     .map_err(|_| anyhow::anyhow!("ChannelCreationFailed"))?;
-                let session_sender = response
+                match response
                     .await
-                    .map_err(|_| anyhow::anyhow!("ChannelCreationFailed"))?;
-                self.state = State::Publishing(session_sender);
+                    .map_err(|_| anyhow::anyhow!("ChannelCreationFailed"))?
+                {
+                    Ok(publishing) => {
+                        if publishing.backup {
+                            log::info!("Client {} publishes as backup", self.id);
+                        }
+                        let events = self.proto.accept_publish(request_id)?;
+                        self.return_data(events).await?;
+                        self.state = State::Publishing(publishing.handle, publishing.dropped);
+                        self.gauge = Some(GaugeGuard::new(
+                            METRICS.publishers.with_label_values(&[RTMP]),
+                        ));
+                    }
+                    Err(reason) => {
+                        log::info!("Client {} publish rejected: {}", self.id, reason);
+                        let events = self.proto.reject_publish(request_id, &reason)?;
+                        self.return_data(events).await?;
+                        self.disconnect()?;
+                    }
+                }
             }
-            Event::JoinChannel { app_name, .. } => {
+            Event::JoinChannel { app_name } => {
                 let (request, response) = oneshot::channel();
                 self.manager_handle
                     .send(ChannelMessage::Join((app_name, request)))
                     .map_err(|_| anyhow::anyhow!("ChannelJoinFailed"))?;
 
                 match response.await {
-                    Ok(resp) => match resp {
-                        JoinResp::Local(session_sender, session_receiver) => {
-                            self.state = State::Playing(session_sender, session_receiver, false);
-                        }
-                        JoinResp::Origin(session_sender, session_receiver) => {
-                            self.state = State::Playing(session_sender, session_receiver, true);
-                        }
-                    },
+                    Ok(JoinResp { init_data, watcher }) => {
+                        self.state = State::Playing(watcher, Some(init_data));
+                        self.gauge =
+                            Some(GaugeGuard::new(METRICS.players.with_label_values(&[RTMP])));
+                    }
                     Err(_) => self.disconnect()?,
                 }
             }
-            Event::SendInitData { .. } => {
-                if let State::Playing(session, _, from_cluster_fisrt) = &mut self.state {
-                    if *from_cluster_fisrt {
-                        return Ok(()); //第一个请求
-                    }
-                    let (request, response) = oneshot::channel();
-                    session
-                        .send(Message::InitData(request))
-                        .map_err(|_| anyhow::anyhow!("ChannelSendFailed "))?;
-                    //这边可能出现一致性错误,可能掉帧
-                    if let Ok((meta, video, audio, gop)) = response.await {
-                        meta.map(|m| self.send_back(m));
-                        video.map(|v| self.send_back(v));
-                        audio.map(|a| self.send_back(a));
-                        gop.map(|gop| {
-                            for g in gop {
-                                match self.send_back(g) {
-                                    Ok(_) => {}
-                                    Err(e) => {
-                                        log::error!("{}", e);
-                                        _ = self.disconnect();
-                                    }
-                                }
-                            }
-                        });
+            Event::SendInitData => {
+                let init_data = match &mut self.state {
+                    State::Playing(_, init_data) => init_data.take(),
+                    _ => None,
+                };
+                if let Some(init_data) = init_data {
+                    for packet in init_data.into_packets() {
+                        self.send_back(packet)?;
                     }
                 }
             }
@@ -175,6 +192,15 @@ impl Connection {
         Ok(())
     }
 
+    async fn return_data(&mut self, events: Vec<Event>) -> Result<()> {
+        for event in events {
+            if let Event::ReturnData(data) = event {
+                self.bytes_stream.send(data).await?;
+            }
+        }
+        Ok(())
+    }
+
     fn send_back(&mut self, packet: MediaPacket) -> Result<()> {
         self.return_queue
             .0
@@ -183,15 +209,11 @@ impl Connection {
     }
 
     fn disconnect(&mut self) -> Result<()> {
-        if let State::Publishing(session) = &mut self.state {
-            let app_name = self.app_name.clone().unwrap();
+        //the channel releases itself once the publisher is gone
+        if let State::Publishing(session, _) = &mut self.state {
             session
                 .send(Message::Disconnect)
                 .map_err(|_| anyhow::anyhow!("ChannelSendFailed"))?;
-
-            self.manager_handle
-                .send(ChannelMessage::Release(app_name))
-                .map_err(|_| anyhow::anyhow!("ChannelReleaseFailed"))?;
         }
         self.state = State::Disconnecting;
         Ok(())
@@ -2,12 +2,14 @@ use crate::{put_i24_be, put_i32_be, FLV_HEADER};
 use anyhow::Result;
 use bytes::{Bytes, BytesMut};
 use core::message::{MediaKind, MediaPacket};
+use core::metrics::{GaugeGuard, HTTP_FLV, METRICS};
 use core::transport::JoinResp;
-use core::transport::{ChannelMessage, ManagerHandle, Message};
+use core::transport::{ChannelMessage, ManagerHandle};
 use hyper::body::Sender;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Request, Response, Server, StatusCode};
 use std::convert::Infallible;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::oneshot;
 
 async fn http_flv(manager_handle: ManagerHandle, req: Request<Body>) -> Result<Response<Body>> {
@@ -78,13 +80,11 @@ impl Conn {
             .send(ChannelMessage::Join((app_name, request)))
             .map_err(|_| anyhow::anyhow!("ChannelJoinFailed"))?;
 
-        let (session_sender, mut session_receiver, need_init_data) = match response.await {
-            Ok(JoinResp::Local(session_sender, session_receiver)) => {
-                (session_sender, session_receiver, true)
-            }
-            Ok(JoinResp::Origin(session_sender, session_receiver)) => {
-                (session_sender, session_receiver, false)
-            }
+        let JoinResp {
+            init_data,
+            watcher: mut session_receiver,
+        } = match response.await {
+            Ok(resp) => resp,
             Err(e) => {
                 log::error!("join channel  err {}", e);
                 return Err(anyhow::anyhow!("ChannelJoinFailed"));
@@ -92,41 +92,17 @@ impl Conn {
         };
 
         tokio::spawn(async move {
-            let mut retrun_data = vec![];
-
-            if need_init_data {
-                let (request, response) = oneshot::channel();
-                match session_sender.send(Message::InitData(request)) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        log::error!("{}", e);
-                        return;
-                    }
-                }
-
-                //这边可能出现一致性错误,可能掉帧
-                match body_sender.send_data(Bytes::from(&FLV_HEADER[..])).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        log::error!("{}", e);
-                        return;
-                    }
-                }
-                if let Ok((meta, video, audio, gop)) = response.await {
-                    log::info!("send init data");
-                    meta.map(|m| retrun_data.push(Bytes::from(packet_to_bytes(&m))));
-                    audio.map(|a| retrun_data.push(Bytes::from(packet_to_bytes(&a))));
-                    video.map(|v| retrun_data.push(Bytes::from(packet_to_bytes(&v))));
-                    gop.map(|gop| {
-                        for g in gop {
-                            retrun_data.push(Bytes::from(packet_to_bytes(&g)));
-                        }
-                    });
+            let _player = GaugeGuard::new(METRICS.players.with_label_values(&[HTTP_FLV]));
+            match body_sender.send_data(Bytes::from(&FLV_HEADER[..])).await {
+                Ok(_) => {}
+                Err(e) => {
+                    log::error!("{}", e);
+                    return;
                 }
             }
-            let packets: Vec<Bytes> = retrun_data.drain(..).collect();
-            for p in packets {
-                match body_sender.send_data(p).await {
+            log::info!("send init data");
+            for p in init_data.into_packets() {
+                match send_packet(&mut body_sender, &p).await {
                     Ok(_) => {}
                     Err(e) => {
                         log::error!("{}", e);
@@ -134,11 +110,19 @@ impl Conn {
                     }
                 }
             }
-            while let Ok(packet) = session_receiver.recv().await {
-                match body_sender
-                    .send_data(Bytes::from(packet_to_bytes(&packet)))
-                    .await
-                {
+            loop {
+                let packet = match session_receiver.recv().await {
+                    Ok(packet) => packet,
+                    Err(RecvError::Lagged(n)) => {
+                        METRICS
+                            .lagged_packets
+                            .with_label_values(&[HTTP_FLV])
+                            .inc_by(n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                match send_packet(&mut body_sender, &packet).await {
                     Ok(_) => {}
                     Err(e) => {
                         log::error!("send_data err {}", e);
@@ -151,6 +135,15 @@ impl Conn {
     }
 }
 
+async fn send_packet(body_sender: &mut Sender, packet: &MediaPacket) -> hyper::Result<()> {
+    let bytes = packet_to_bytes(packet);
+    METRICS
+        .bytes_sent
+        .with_label_values(&[HTTP_FLV])
+        .inc_by(bytes.len() as u64);
+    body_sender.send_data(Bytes::from(bytes)).await
+}
+
 pub fn packet_to_bytes(packet: &MediaPacket) -> BytesMut {
     let type_id = match packet.kind {
         MediaKind::Audio => 8,
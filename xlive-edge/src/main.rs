@@ -1,8 +1,11 @@
 #![warn(unused_mut)]
-use anyhow::Result;
+use anyhow::{bail, Result};
 use chrono::Local;
-use core::Upstream;
+use core::register::{PickPolicy, Role};
+use core::register_client::RegisterClientConfig;
+use core::{RegisterClient, Upstream};
 use std::io::Write;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[cfg(feature = "http-flv")]
@@ -20,11 +23,21 @@ struct Opt {
     #[structopt(short = "o", long = "origin", default_value = "127.0.0.1:9878")]
     origin: String,
 
+    /// comma separated cache addresses, the next one is used when the current one fails
     #[structopt(short = "c", long = "cache", default_value = "")]
     cache: String,
 
     #[structopt(short = "b", long = "bind", default_value = "[::]:1935")]
     bind: String,
+
+    /// seconds to keep pulling a channel after its last player left, 0 keeps it forever
+    #[structopt(long = "idle-timeout", default_value = "30")]
+    idle_timeout: u64,
+
+    /// let the register pick the origin of new publishers: least-loaded or consistent-hash,
+    /// publishers go to --origin when not set
+    #[structopt(long = "publish-policy")]
+    publish_policy: Option<PickPolicy>,
 }
 
 #[tokio::main]
@@ -52,11 +65,17 @@ async fn main() -> Result<()> {
 
     let upstream;
     if !opt.register.is_empty() {
-        upstream = Some(Upstream::Register(opt.register));
+        let mut config = RegisterClientConfig::new(&opt.register);
+        config.role = Some(Role::Edge);
+        #[cfg(feature = "monitor")]
+        {
+            config.monitor_addr = monitor::MONITOR_ADDR.to_owned();
+        }
+        upstream = Some(Upstream::Register(RegisterClient::connect(config).await?));
     } else if !opt.cache.is_empty() {
-        upstream = Some(Upstream::Addr(opt.cache.clone()));
+        upstream = Some(Upstream::from_addrs(&opt.cache));
     } else {
-        upstream = Some(Upstream::Addr(opt.origin.clone()));
+        upstream = Some(Upstream::Addr(vec![opt.origin.clone()]));
     }
 
     if upstream.is_none() {
@@ -66,7 +85,20 @@ async fn main() -> Result<()> {
 
     let mut handles = Vec::new();
 
-    let manager = Manager::new(true, upstream.unwrap(), opt.origin);
+    let idle_timeout = match opt.idle_timeout {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+    if opt.publish_policy.is_some() && opt.register.is_empty() {
+        bail!("--publish-policy needs a register");
+    }
+    let manager = Manager::new(
+        true,
+        upstream.unwrap(),
+        opt.origin,
+        opt.publish_policy,
+        idle_timeout,
+    );
     let manager_handle = manager.handle();
     handles.push(tokio::spawn(manager.run()));
 
@@ -1,32 +1,67 @@
 use crate::channel::Channel;
 use anyhow::{bail, Result};
-use bytes::Bytes;
-use core::message::{Kind, MediaPacket, ProtoMessage};
-use core::register::{Register, RegisterKind, RegisterResp, RegisterRespKind};
+use core::message::{Kind, ProtoMessage};
+use core::register::PickPolicy;
+use core::stats::ChannelStats;
 use core::transport::{
-    ChannelMessage, ChannelReceiver, Handle, JoinResp, ManagerHandle, OutgoingBroadcast, Trigger,
+    ChannelInfo, ChannelMessage, ChannelReceiver, Handle, JoinResp, ManagerHandle,
+    OutgoingBroadcast, PublishResp, Publishing, Responder, Trigger,
 };
+use core::upstream::{pull, relay, UpstreamFrame, PULL_TIMEOUT};
 use core::{AppName, Event};
-use core::{Message, Upstream};
+use core::{Message, RegisterClient, Upstream};
 use futures::{SinkExt, StreamExt};
+use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
-use tokio::net::{TcpStream, UdpSocket};
-use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, oneshot, RwLock};
+use tokio::time;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
+type Pulled = (AppName, Result<UpstreamFrame>);
+//a local publisher accepted by the origin, the flag tells whether it is a backup
+type Pushed = (
+    AppName,
+    Responder<PublishResp>,
+    Result<(UpstreamFrame, bool)>,
+);
+
 pub struct Manager {
     handle: ManagerHandle,
     incoming: ChannelReceiver,
     channels: Arc<RwLock<HashMap<AppName, (Handle, OutgoingBroadcast)>>>,
     triggers: Arc<RwLock<HashMap<Event, Vec<Trigger>>>>,
+    //latest stats reported by every channel
+    stats: HashMap<AppName, ChannelStats>,
+    //address every channel is pulled from or pushed to
+    upstreams: HashMap<AppName, String>,
+    //upstream reconnects of every channel
+    reconnects: HashMap<AppName, u64>,
+    //players waiting for an upstream pull in flight, keyed by channel name
+    pulling: HashMap<AppName, Vec<Responder<JoinResp>>>,
+    pulled: mpsc::UnboundedSender<Pulled>,
+    pulled_incoming: mpsc::UnboundedReceiver<Pulled>,
+    pushed: mpsc::UnboundedSender<Pushed>,
+    pushed_incoming: mpsc::UnboundedReceiver<Pushed>,
     full_gop: bool,
     upstream: Upstream,
     origin_addr: String,
+    //the register picks the origin of new publishers, `origin_addr` is the fallback
+    publish_policy: Option<PickPolicy>,
+    idle_timeout: Option<Duration>,
 }
 
 impl Manager {
-    pub fn new(full_gop: bool, upstream: Upstream, origin_addr: String) -> Self {
+    pub fn new(
+        full_gop: bool,
+        upstream: Upstream,
+        origin_addr: String,
+        publish_policy: Option<PickPolicy>,
+        idle_timeout: Option<Duration>,
+    ) -> Self {
         let (handle, incoming) = mpsc::unbounded_channel();
+        let (pulled, pulled_incoming) = mpsc::unbounded_channel();
+        let (pushed, pushed_incoming) = mpsc::unbounded_channel();
         let channels = Arc::new(RwLock::new(HashMap::new()));
         let triggers = Arc::new(RwLock::new(HashMap::new()));
 
@@ -35,9 +70,19 @@ impl Manager {
             incoming,
             channels,
             triggers,
+            stats: HashMap::new(),
+            upstreams: HashMap::new(),
+            reconnects: HashMap::new(),
+            pulling: HashMap::new(),
+            pulled,
+            pulled_incoming,
+            pushed,
+            pushed_incoming,
             full_gop,
             upstream,
             origin_addr,
+            publish_policy,
+            idle_timeout,
         }
     }
 
@@ -48,158 +93,59 @@ impl Manager {
     async fn process_message(&mut self, message: ChannelMessage) -> Result<()> {
         match message {
             ChannelMessage::Create((name, _key, responder)) => {
-                let (handle, incoming) = mpsc::unbounded_channel();
-                let (outgoing, _watcher) = broadcast::channel(64);
-                let mut sessions = self.channels.write().await;
-                sessions.insert(name.clone(), (handle.clone(), outgoing.clone()));
-
-                let triggers = self.triggers.read().await;
-                if let Some(event_triggers) = triggers.get("create_session") {
-                    for trigger in event_triggers {
-                        trigger.send((name.clone(), outgoing.subscribe()))?;
-                    }
-                }
-
-                let full_gop = self.full_gop;
-                let name_copy = name.clone();
-                let origin_addr_cp = self.origin_addr.clone();
+                //the origin decides between publishers of the same channel
+                let origin_addr = self.origin_addr.clone();
+                let picker = match (&self.upstream, self.publish_policy) {
+                    (Upstream::Register(client), Some(policy)) => Some((client.clone(), policy)),
+                    _ => None,
+                };
+                let pushed = self.pushed.clone();
                 tokio::spawn(async move {
-                    _ = Channel::new(name_copy, incoming, outgoing, full_gop)
-                        .run(false, origin_addr_cp)
-                        .await
-                        .map_err(|e| log::error!("{:?}", e));
+                    let origin_addr = match picker {
+                        Some((client, policy)) => {
+                            pick_origin(&client, &name, policy, origin_addr).await
+                        }
+                        None => origin_addr,
+                    };
+                    let result = match time::timeout(PULL_TIMEOUT, push(&origin_addr, &name)).await
+                    {
+                        Ok(result) => result,
+                        Err(_) => Err(anyhow::anyhow!("origin {} timeout", origin_addr)),
+                    };
+                    _ = pushed.send((name, responder, result));
                 });
-
-                if responder.send(handle).is_err() {
-                    bail!("Failed to send response");
-                }
             }
             ChannelMessage::Join((name, responder)) => {
                 let sessions = self.channels.read().await;
-                if let Some((handle, watcher)) = sessions.get(&name) {
-                    if responder
-                        .send(JoinResp::Local(handle.clone(), watcher.subscribe()))
-                        .is_err()
-                    {
-                        bail!("Failed to send response");
+                if let Some((handle, _)) = sessions.get(&name) {
+                    if handle.send(Message::Join(responder)).is_err() {
+                        bail!("Failed to join channel {}", name);
                     }
+                } else if let Some(waiting) = self.pulling.get_mut(&name) {
+                    waiting.push(responder);
                 } else {
-                    drop(sessions);
                     log::info!("{:?}", self.upstream);
-                    let addr = match &self.upstream {
-                        Upstream::Register(addr) => {
-                            let buf: Bytes = Register {
-                                kind: RegisterKind::Get,
-                                channel_name: name.clone(),
-                            }
-                            .try_into()
-                            .unwrap();
-
-                            let udp = UdpSocket::bind("0.0.0.0:0").await.unwrap();
-                            udp.connect(addr).await.unwrap();
-                            log::debug!("send msg {:?} to {}", buf, addr);
-                            _ = udp.send(&buf).await;
-                            let mut buf = vec![0u8; 100];
-                            let n = udp.recv(&mut buf).await?;
-                            let register_resp: RegisterResp = (&buf[..n]).try_into()?;
-                            match register_resp.kind {
-                                RegisterRespKind::OK => {
-                                    log::info!(
-                                        "got origin add from register {:?}",
-                                        register_resp.payload
-                                    );
-                                    register_resp.payload
-                                }
-                                RegisterRespKind::NOFOUND => bail!("publisher in not found"),
-                            }
-                        }
-                        Upstream::Addr(a) => a.clone(),
-                    };
-                    log::info!("upstream add {}", addr);
-                    let stream = TcpStream::connect(addr).await?;
-                    let mut frame = Framed::new(stream, LengthDelimitedCodec::new());
-                    frame
-                        .send(ProtoMessage::new_proto_init(false, &name).into())
-                        .await?;
-
-                    if let Some(Ok(data)) = frame.next().await {
-                        let proto_msg = ProtoMessage::try_from(data.freeze())?;
-                        match proto_msg.kind {
-                            Kind::Errors => {
-                                log::error!("join chanle err");
-                                bail!("join chanle err");
-                            }
-                            Kind::Init | Kind::Media => unreachable!(),
-                            Kind::Ok => {}
-                        }
-
-                        let (handle, incoming) = mpsc::unbounded_channel();
-                        let (outgoing, _watcher) = broadcast::channel(64);
-                        let mut sessions = self.channels.write().await;
-                        sessions.insert(name.clone(), (handle.clone(), outgoing.clone()));
-
-                        let triggers = self.triggers.read().await;
-                        if let Some(event_triggers) = triggers.get("create_session") {
-                            for trigger in event_triggers {
-                                trigger.send((name.clone(), outgoing.subscribe()))?;
-                            }
-                        }
-
-                        let full_gop = self.full_gop;
-                        let name_copy = name.clone();
-
-                        let watcher = outgoing.subscribe();
-                        let handle_cp = handle.clone();
-                        let origin_add_cp = self.origin_addr.clone();
-                        tokio::spawn(async move {
-                            _ = Channel::new(name_copy, incoming, outgoing, full_gop)
-                                .run(true, origin_add_cp)
-                                .await
-                                .map_err(|e| log::error!("{:?}", e));
-                        });
-                        let manager_handle = self.handle.clone();
-                        let name_copy = name.clone();
-                        tokio::spawn(async move {
-                            while let Some(Ok(data)) = frame.next().await {
-                                let proto_msg = ProtoMessage::try_from(data.freeze())?;
-                                match &proto_msg.kind {
-                                    Kind::Errors => {
-                                        log::error!("join chanle err");
-                                        _ = handle_cp.send(Message::Disconnect);
-                                        _ = manager_handle.send(ChannelMessage::Release(name_copy));
-                                        bail!("ProtoMessage chanle err");
-                                    }
-                                    Kind::Init | Kind::Ok => unreachable!(),
-                                    Kind::Media => {
-                                        let media_packet =
-                                            MediaPacket::try_from(proto_msg.payload)?;
-                                        match handle_cp
-                                            .send(Message::PacketFromOrigin(media_packet))
-                                        {
-                                            Ok(_) => {}
-                                            Err(e) => {
-                                                log::error!("{}", e);
-                                                break;
-                                            }
-                                        }
-                                    }
-                                };
-                            }
-                            Ok::<(), anyhow::Error>(())
-                        });
-
-                        if responder
-                            .send(JoinResp::Origin(handle.clone(), watcher))
-                            .is_err()
-                        {
-                            bail!("Failed to send response");
-                        }
-                    }
+                    self.pulling.insert(name.clone(), vec![responder]);
+                    let upstream = self.upstream.clone();
+                    let pulled = self.pulled.clone();
+                    tokio::spawn(async move {
+                        let result = pull(&upstream, &name, 0).await;
+                        _ = pulled.send((name, result));
+                    });
                 }
             }
             ChannelMessage::Release(name) => {
+                //a channel closes its handle before it asks for release,
+                //so a late release never removes a newer channel of the same name
                 let mut sessions = self.channels.write().await;
-                sessions.remove(&name);
+                if let Some((handle, _)) = sessions.get(&name) {
+                    if handle.is_closed() {
+                        sessions.remove(&name);
+                        self.stats.remove(&name);
+                        self.upstreams.remove(&name);
+                        self.reconnects.remove(&name);
+                    }
+                }
             }
             ChannelMessage::RegisterTrigger(event, trigger) => {
                 log::debug!("Registering trigger for {}", event);
@@ -210,20 +156,221 @@ impl Manager {
                 let sessions = self.channels.read().await;
                 let mut info = HashMap::new();
                 for (k, v) in sessions.iter() {
-                    info.insert(k.to_owned(), v.1.receiver_count());
+                    let channel_info = ChannelInfo {
+                        subscribers: v.1.receiver_count(),
+                        viewers: v.1.receiver_count(),
+                        upstream: self.upstreams.get(k).cloned(),
+                        reconnects: self.reconnects.get(k).copied().unwrap_or_default(),
+                        stats: self.stats.get(k).cloned(),
+                        ..Default::default()
+                    };
+                    info.insert(k.to_owned(), channel_info);
                 }
                 _ = responder.send(info);
             }
+            ChannelMessage::Switched(_) => unreachable!(),
+            ChannelMessage::Stats((name, stats)) => {
+                self.stats.insert(name, stats);
+            }
+            ChannelMessage::Upstream((name, addr)) => {
+                *self.reconnects.entry(name.clone()).or_default() += 1;
+                self.upstreams.insert(name, addr);
+            }
+            //nothing pulls from an edge
+            ChannelMessage::Relay(_) => unreachable!(),
         }
 
         Ok(())
     }
 
+    async fn process_pushed(
+        &mut self,
+        name: AppName,
+        responder: Responder<PublishResp>,
+        result: Result<(UpstreamFrame, bool)>,
+    ) -> Result<()> {
+        let (frame, backup) = match result {
+            Ok(pushed) => pushed,
+            Err(e) => {
+                _ = responder.send(Err(e.to_string()));
+                bail!("publish {} to origin err {}", name, e);
+            }
+        };
+
+        let (handle, incoming) = mpsc::unbounded_channel();
+        let (outgoing, _watcher) = broadcast::channel(64);
+        let (dropper, dropped) = oneshot::channel();
+        //a backup is not live, local players stay with the current stream
+        if !backup {
+            if let Ok(addr) = frame.get_ref().peer_addr() {
+                self.upstreams.insert(name.clone(), addr.to_string());
+            }
+            let mut sessions = self.channels.write().await;
+            sessions.insert(name.clone(), (handle.clone(), outgoing.clone()));
+
+            let triggers = self.triggers.read().await;
+            if let Some(event_triggers) = triggers.get("create_session") {
+                for trigger in event_triggers {
+                    trigger.send((name.clone(), outgoing.subscribe()))?;
+                }
+            }
+        }
+
+        let full_gop = self.full_gop;
+        let name_copy = name.clone();
+        let manager_handle = self.handle.clone();
+        tokio::spawn(async move {
+            //published channels live as long as their publisher
+            _ = Channel::new(
+                name_copy,
+                incoming,
+                outgoing,
+                manager_handle,
+                None,
+                full_gop,
+            )
+            .run(Some(frame), Some(dropper))
+            .await
+            .map_err(|e| log::error!("{:?}", e));
+        });
+
+        let publishing = Publishing {
+            handle,
+            backup,
+            dropped,
+        };
+        if let Err(Ok(publishing)) = responder.send(Ok(publishing)) {
+            _ = publishing.handle.send(Message::Disconnect);
+            bail!("Failed to send response");
+        }
+        Ok(())
+    }
+
+    async fn process_pulled(&mut self, name: AppName, frame: Result<UpstreamFrame>) -> Result<()> {
+        let waiting = self.pulling.remove(&name).unwrap_or_default();
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => bail!("pull {} from upstream err {}", name, e),
+        };
+        if let Ok(addr) = frame.get_ref().peer_addr() {
+            self.upstreams.insert(name.clone(), addr.to_string());
+        }
+
+        let (handle, incoming) = mpsc::unbounded_channel();
+        let (outgoing, _watcher) = broadcast::channel(64);
+        let mut sessions = self.channels.write().await;
+        sessions.insert(name.clone(), (handle.clone(), outgoing.clone()));
+
+        let triggers = self.triggers.read().await;
+        if let Some(event_triggers) = triggers.get("create_session") {
+            for trigger in event_triggers {
+                trigger.send((name.clone(), outgoing.subscribe()))?;
+            }
+        }
+
+        //the joins are queued before any packet from upstream,
+        //so the players get an empty snapshot and the whole stream
+        for responder in waiting {
+            if handle.send(Message::Join(responder)).is_err() {
+                bail!("Failed to join channel {}", name);
+            }
+        }
+
+        let full_gop = self.full_gop;
+        let name_copy = name.clone();
+        let manager_handle = self.handle.clone();
+        let idle_timeout = self.idle_timeout;
+        tokio::spawn(async move {
+            _ = Channel::new(
+                name_copy,
+                incoming,
+                outgoing,
+                manager_handle,
+                idle_timeout,
+                full_gop,
+            )
+            .run(None, None)
+            .await
+            .map_err(|e| log::error!("{:?}", e));
+        });
+        let upstream = self.upstream.clone();
+        let manager_handle = self.handle.clone();
+        tokio::spawn(relay(name, frame, upstream, handle, manager_handle));
+        Ok(())
+    }
+
     pub async fn run(mut self) {
-        while let Some(message) = self.incoming.recv().await {
-            if let Err(err) = self.process_message(message).await {
+        loop {
+            let result = tokio::select! {
+                Some(message) = self.incoming.recv() => self.process_message(message).await,
+                Some((name, frame)) = self.pulled_incoming.recv() => {
+                    self.process_pulled(name, frame).await
+                }
+                Some((name, responder, result)) = self.pushed_incoming.recv() => {
+                    self.process_pushed(name, responder, result).await
+                }
+                else => break,
+            };
+            if let Err(err) = result {
                 log::error!("{}", err);
             };
         }
     }
 }
+
+//join the origin as the publisher of a channel,
+//returns whether the origin holds it as a backup of the current publisher
+//the configured origin is used when the register can not pick one
+async fn pick_origin(
+    client: &RegisterClient,
+    name: &str,
+    policy: PickPolicy,
+    fallback: String,
+) -> String {
+    match client.pick_origin(name, policy).await {
+        Ok(Some(origin)) => {
+            log::info!(
+                "publish {} to origin {} {}",
+                name,
+                origin.node_id,
+                origin.addr
+            );
+            origin.addr
+        }
+        Ok(None) => {
+            log::warn!("register knows no origin, publish {} to {}", name, fallback);
+            fallback
+        }
+        Err(e) => {
+            log::warn!("pick origin of {} err {}, publish to {}", name, e, fallback);
+            fallback
+        }
+    }
+}
+
+async fn push(origin_addr: &str, name: &str) -> Result<(UpstreamFrame, bool)> {
+    let stream = TcpStream::connect(origin_addr).await?;
+    let mut frame = Framed::new(stream, LengthDelimitedCodec::new());
+    frame
+        .send(ProtoMessage::new_proto_init(true, name).into())
+        .await?;
+
+    match frame.next().await {
+        Some(Ok(data)) => {
+            let proto_msg = ProtoMessage::try_from(data.freeze())?;
+            match proto_msg.kind {
+                Kind::Errors => bail!("{}", String::from_utf8_lossy(&proto_msg.payload)),
+                Kind::Init | Kind::Media => bail!(
+                    "unexpected {:?} from origin {} on publish",
+                    proto_msg.kind,
+                    origin_addr
+                ),
+                Kind::Ok => {
+                    let backup = proto_msg.is_backup();
+                    Ok((frame, backup))
+                }
+            }
+        }
+        _ => bail!("origin {} closed before publish", origin_addr),
+    }
+}
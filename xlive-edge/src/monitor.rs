@@ -6,9 +6,19 @@ use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Request, Response, Server, StatusCode};
 use std::convert::Infallible;
 use tokio::sync::oneshot;
+
+//announced to the register, which fills in the ip
+pub const MONITOR_ADDR: &str = "[::]:3032";
+
 async fn monitor(manager_handle: ManagerHandle, req: Request<Body>) -> Result<Response<Body>> {
     let path = req.uri().path();
 
+    if path.eq("/metrics") {
+        return Ok(Response::builder()
+            .header("Content-Type", core::metrics::content_type())
+            .body(Body::from(core::metrics::encode()))
+            .unwrap());
+    }
     if path.is_empty() || !path.eq("/monitor") {
         return Ok(Response::builder()
             .status(StatusCode::NOT_FOUND)
@@ -50,7 +60,7 @@ impl Service {
                 Ok::<_, Infallible>(service_fn(move |req| monitor(manager_handle.clone(), req)))
             }
         });
-        let addr = "[::]:3032".parse().unwrap();
+        let addr = MONITOR_ADDR.parse().unwrap();
         let server = Server::bind(&addr).serve(make_service);
         log::info!("monitor Listening on http://{}", addr);
         _ = server.await;
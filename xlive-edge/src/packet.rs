@@ -244,8 +244,8 @@ pub fn from_metadata(val: StreamMetadata) -> Metadata {
         map.insert("audio.channels", v.to_string());
     }
 
-    if let Some(v) = val.audio_codec {
-        map.insert("audio.codec", v);
+    if let Some(v) = val.audio_codec_id {
+        map.insert("audio.codec", v.to_string());
     }
 
     if let Some(v) = val.audio_is_stereo {
@@ -260,8 +260,8 @@ pub fn from_metadata(val: StreamMetadata) -> Metadata {
         map.insert("video.bitrate", v.to_string());
     }
 
-    if let Some(v) = val.video_codec {
-        map.insert("video.codec", v);
+    if let Some(v) = val.video_codec_id {
+        map.insert("video.codec", v.to_string());
     }
 
     if let Some(v) = val.video_frame_rate {
@@ -287,10 +287,10 @@ pub(crate) fn into_metadata(val: Metadata) -> StreamMetadata {
     StreamMetadata {
         video_width: val.get("video.width"),
         video_height: val.get("video.height"),
-        video_codec: val.get("video.codec"),
+        video_codec_id: val.get("video.codec"),
         video_frame_rate: val.get("video.frame_rate"),
         video_bitrate_kbps: val.get("video.bitrate"),
-        audio_codec: val.get("audio.codec"),
+        audio_codec_id: val.get("audio.codec"),
         audio_bitrate_kbps: val.get("audio.bitrate"),
         audio_sample_rate: val.get("audio.sampling_rate"),
         audio_channels: val.get("audio.channels"),
@@ -7,21 +7,20 @@ use rml_rtmp::sessions::{
 };
 use rml_rtmp::time::RtmpTimestamp;
 use std::convert::{TryFrom, TryInto};
-use std::rc::Rc;
+
 pub enum Event {
     ReturnData(Bytes),
     SendPacket(Packet),
+    //answered with accept_publish or reject_publish
     AcquireChannel {
+        request_id: u32,
         app_name: String,
         stream_key: String,
     },
     JoinChannel {
         app_name: String,
-        stream_key: String,
-    },
-    SendInitData {
-        app_name: String,
     },
+    SendInitData,
     ReleaseChannel,
     LeaveChannel,
 }
@@ -118,11 +117,28 @@ impl Protocol {
         self.handle_results(results)
     }
 
+    pub fn accept_publish(&mut self, request_id: u32) -> Result<Vec<Event>> {
+        self.accept_request(request_id)?;
+        self.state = State::Publishing;
+        Ok(self.return_queue.drain(..).collect())
+    }
+
+    //the session answers on the stream of the publish request
+    pub fn reject_publish(&mut self, request_id: u32, reason: &str) -> Result<Vec<Event>> {
+        let results = self
+            .session()?
+            .reject_request(request_id, "NetStream.Publish.BadName", reason)
+            .map_err(|_| anyhow::anyhow!("RequestRejected"))?;
+        self.handle_results(results)?;
+        self.state = State::Finished;
+        Ok(self.return_queue.drain(..).collect())
+    }
+
     pub fn pack_metadata(&mut self, packet: Packet) -> Result<Vec<u8>> {
         let stream_id = self.stream_id()?;
         let metadata = packet::into_metadata(packet.try_into().unwrap());
         self.session()?
-            .send_metadata(stream_id, Rc::new(metadata))
+            .send_metadata(stream_id, &metadata)
             .map_err(|_| anyhow::anyhow!("InvalidInput"))
             .map(|v| v.bytes)
     }
@@ -193,11 +209,10 @@ impl Protocol {
                 ..
             } => {
                 self.emit(Event::AcquireChannel {
+                    request_id,
                     app_name,
                     stream_key,
                 });
-                self.accept_request(request_id)?;
-                self.state = State::Publishing;
             }
             PublishStreamFinished { .. } => {
                 self.emit(Event::ReleaseChannel);
@@ -206,16 +221,12 @@ impl Protocol {
             PlayStreamRequested {
                 request_id,
                 app_name,
-                stream_key,
                 stream_id,
                 ..
             } => {
-                self.emit(Event::JoinChannel {
-                    app_name: app_name.clone(),
-                    stream_key,
-                });
+                self.emit(Event::JoinChannel { app_name });
                 self.accept_request(request_id)?;
-                self.emit(Event::SendInitData { app_name });
+                self.emit(Event::SendInitData);
                 self.state = State::Playing { stream_id };
             }
             PlayStreamFinished { .. } => {
@@ -0,0 +1,440 @@
+use crate::monitor::NodeState;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+/// The `[alert]` section of the config.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AlertConfig {
+    /// urls the fired and resolved alerts are posted to
+    pub webhooks: Vec<String>,
+    pub rules: Vec<Rule>,
+    pub silences: Vec<Silence>,
+    /// seconds between two notifications of an alert that keeps firing, 0 notifies once
+    pub repeat_secs: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Rule {
+    //consecutive failed scrapes of a node
+    NodeDown {
+        #[serde(default = "default_failures")]
+        failures: u32,
+    },
+    //a channel of a node carried no data for that long
+    BitrateZero {
+        #[serde(default = "default_zero_secs")]
+        for_secs: u64,
+    },
+    //viewers of a channel on all the nodes grew by ratio within the window
+    ViewerSpike {
+        ratio: f64,
+        #[serde(default)]
+        min_viewers: u64,
+        window_secs: u64,
+    },
+    //upstream reconnects of a channel on a node within the window
+    ReconnectStorm {
+        reconnects: u64,
+        window_secs: u64,
+    },
+}
+
+fn default_failures() -> u32 {
+    3
+}
+
+fn default_zero_secs() -> u64 {
+    10
+}
+
+impl Rule {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::NodeDown { .. } => "node_down",
+            Rule::BitrateZero { .. } => "bitrate_zero",
+            Rule::ViewerSpike { .. } => "viewer_spike",
+            Rule::ReconnectStorm { .. } => "reconnect_storm",
+        }
+    }
+}
+
+/// Mutes the notifications of the alerts it matches, a missing field matches all.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Silence {
+    pub rule: Option<String>,
+    pub node: Option<String>,
+    pub channel: Option<String>,
+    /// unix seconds, forever if missing
+    pub until: Option<u64>,
+}
+
+impl Silence {
+    fn matches(&self, alert: &Alert, at: u64) -> bool {
+        let field = |silenced: &Option<String>, value: &Option<String>| {
+            silenced.is_none() || silenced == value
+        };
+        self.until.map_or(true, |until| at < until)
+            && self.rule.as_ref().map_or(true, |rule| rule == &alert.rule)
+            && field(&self.node, &alert.node)
+            && field(&self.channel, &alert.channel)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Firing,
+    Resolved,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Alert {
+    pub rule: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    pub status: Status,
+    pub message: String,
+    /// unix seconds
+    pub started_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_at: Option<u64>,
+}
+
+type AlertKey = (String, Option<String>, Option<String>);
+
+/// Evaluates the rules against the scraped nodes and tracks the firing alerts.
+pub struct Alerter {
+    rules: Vec<Rule>,
+    silences: Vec<Silence>,
+    repeat_secs: u64,
+    //firing alerts and when they were last notified
+    active: HashMap<AlertKey, (Alert, u64)>,
+    //since when a channel of a node carries no data
+    zero_since: HashMap<(String, String), u64>,
+    //reconnect counters of a channel of a node
+    reconnects: HashMap<(String, String), VecDeque<(u64, u64)>>,
+    //viewers of a channel on all the nodes
+    viewers: HashMap<String, VecDeque<(u64, u64)>>,
+    //the longest window of the rules, older samples are dropped
+    window_secs: u64,
+}
+
+impl Alerter {
+    pub fn new(config: &AlertConfig) -> Self {
+        Self {
+            rules: config.rules.clone(),
+            silences: config.silences.clone(),
+            repeat_secs: config.repeat_secs,
+            active: HashMap::new(),
+            zero_since: HashMap::new(),
+            reconnects: HashMap::new(),
+            viewers: HashMap::new(),
+            window_secs: config
+                .rules
+                .iter()
+                .map(|rule| match rule {
+                    Rule::ViewerSpike { window_secs, .. }
+                    | Rule::ReconnectStorm { window_secs, .. } => *window_secs,
+                    _ => 0,
+                })
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    pub fn silence(&mut self, silence: Silence) {
+        self.silences.push(silence);
+    }
+
+    pub fn silences(&self, at: u64) -> Vec<Silence> {
+        self.silences
+            .iter()
+            .filter(|silence| silence.until.map_or(true, |until| at < until))
+            .cloned()
+            .collect()
+    }
+
+    pub fn active(&self) -> Vec<Alert> {
+        self.active
+            .values()
+            .map(|(alert, _)| alert.clone())
+            .collect()
+    }
+
+    /// Evaluates the rules after `node` was scraped, returns the alerts to notify.
+    pub fn evaluate(
+        &mut self,
+        at: u64,
+        node: &str,
+        nodes: &HashMap<String, NodeState>,
+    ) -> Vec<Alert> {
+        self.silences
+            .retain(|silence| silence.until.map_or(true, |until| at < until));
+        let mut firing = HashMap::new();
+        let state = nodes.get(node);
+        let channels = state.filter(|state| state.up).map(|state| &state.channels);
+        self.zero_since.retain(|(name, channel), _| {
+            name != node || channels.is_some_and(|c| c.contains_key(channel))
+        });
+        self.reconnects.retain(|(name, channel), _| {
+            name != node || channels.is_some_and(|c| c.contains_key(channel))
+        });
+        for (channel, info) in channels.into_iter().flatten() {
+            let key = (node.to_owned(), channel.clone());
+            let stats = &info["stats"];
+            let bps = ["video_bps", "audio_bps"]
+                .iter()
+                .map(|field| stats[*field].as_u64().unwrap_or(0))
+                .sum::<u64>();
+            if stats.is_object() && bps == 0 {
+                self.zero_since.entry(key.clone()).or_insert(at);
+            } else {
+                self.zero_since.remove(&key);
+            }
+            let reconnects = self.reconnects.entry(key).or_default();
+            let count = info["reconnects"].as_u64().unwrap_or(0);
+            //a channel created again counts from zero
+            if reconnects.back().is_some_and(|(_, last)| count < *last) {
+                reconnects.clear();
+            }
+            reconnects.push_back((at, count));
+            trim(reconnects, at, self.window_secs);
+        }
+        let totals = channel_viewers(nodes);
+        self.viewers
+            .retain(|channel, _| totals.contains_key(channel));
+        for (channel, total) in &totals {
+            let samples = self.viewers.entry(channel.clone()).or_default();
+            if samples.back().is_some_and(|(last, _)| *last == at) {
+                samples.pop_back();
+            }
+            samples.push_back((at, *total));
+            trim(samples, at, self.window_secs);
+        }
+
+        for rule in &self.rules {
+            match *rule {
+                Rule::NodeDown { failures } => {
+                    if let Some(state) =
+                        state.filter(|state| !state.up && state.failures >= failures)
+                    {
+                        let error = state.error.as_deref().unwrap_or_default();
+                        let message = format!("{} failed scrapes: {}", state.failures, error);
+                        firing.insert(key(rule, Some(node), None), message);
+                    }
+                }
+                Rule::BitrateZero { for_secs } => {
+                    for ((_, channel), since) in
+                        self.zero_since.iter().filter(|((name, _), _)| name == node)
+                    {
+                        let secs = at.saturating_sub(*since);
+                        if secs >= for_secs {
+                            let message = format!("no data for {}s", secs);
+                            firing.insert(key(rule, Some(node), Some(channel)), message);
+                        }
+                    }
+                }
+                Rule::ReconnectStorm {
+                    reconnects,
+                    window_secs,
+                } => {
+                    for ((_, channel), samples) in
+                        self.reconnects.iter().filter(|((name, _), _)| name == node)
+                    {
+                        let mut window = samples
+                            .iter()
+                            .filter(|(first, _)| first + window_secs >= at);
+                        let first = window.next().map_or(0, |(_, count)| *count);
+                        let last = samples.back().map_or(0, |(_, count)| *count);
+                        if last - first >= reconnects {
+                            let message =
+                                format!("{} upstream reconnects in {}s", last - first, window_secs);
+                            firing.insert(key(rule, Some(node), Some(channel)), message);
+                        }
+                    }
+                }
+                Rule::ViewerSpike {
+                    ratio,
+                    min_viewers,
+                    window_secs,
+                } => {
+                    for (channel, samples) in &self.viewers {
+                        let low = samples
+                            .iter()
+                            .filter(|(first, _)| first + window_secs >= at)
+                            .map(|(_, viewers)| *viewers)
+                            .min()
+                            .unwrap_or(0);
+                        let now = samples.back().map_or(0, |(_, viewers)| *viewers);
+                        //a channel with no viewers in the window has no base to grow from
+                        if low > 0 && now >= min_viewers && now as f64 >= low as f64 * ratio {
+                            let message =
+                                format!("{} viewers, {} within {}s", now, low, window_secs);
+                            firing.insert(key(rule, None, Some(channel)), message);
+                        }
+                    }
+                }
+            }
+        }
+
+        //the alerts of other nodes are left to their own scrapes
+        let evaluated = |(rule, alert_node, _): &AlertKey| {
+            rule == "viewer_spike" || alert_node.as_deref() == Some(node)
+        };
+        let mut notify = vec![];
+        let resolved: Vec<AlertKey> = self
+            .active
+            .keys()
+            .filter(|key| evaluated(key) && !firing.contains_key(*key))
+            .cloned()
+            .collect();
+        for key in resolved {
+            if let Some((mut alert, _)) = self.active.remove(&key) {
+                alert.status = Status::Resolved;
+                alert.resolved_at = Some(at);
+                notify.push(alert);
+            }
+        }
+        for ((rule, node, channel), message) in firing {
+            let key = (rule.clone(), node.clone(), channel.clone());
+            match self.active.get_mut(&key) {
+                //deduplicated until it is due again
+                Some((alert, notified)) => {
+                    alert.message = message;
+                    if self.repeat_secs > 0 && at.saturating_sub(*notified) >= self.repeat_secs {
+                        *notified = at;
+                        notify.push(alert.clone());
+                    }
+                }
+                None => {
+                    let alert = Alert {
+                        rule,
+                        node,
+                        channel,
+                        status: Status::Firing,
+                        message,
+                        started_at: at,
+                        resolved_at: None,
+                    };
+                    notify.push(alert.clone());
+                    self.active.insert(key, (alert, at));
+                }
+            }
+        }
+        notify.retain(|alert| {
+            !self
+                .silences
+                .iter()
+                .any(|silence| silence.matches(alert, at))
+        });
+        notify
+    }
+
+    /// A node left the cluster, the alerts of its channels are resolved.
+    pub fn remove(&mut self, at: u64, node: &str) -> Vec<Alert> {
+        self.zero_since.retain(|(name, _), _| name != node);
+        self.reconnects.retain(|(name, _), _| name != node);
+        //a crashed node expires from the register too, it is down until it is scraped up again
+        let keys: Vec<AlertKey> = self
+            .active
+            .keys()
+            .filter(|(rule, alert_node, _)| {
+                alert_node.as_deref() == Some(node) && rule != "node_down"
+            })
+            .cloned()
+            .collect();
+        let mut notify = vec![];
+        for key in keys {
+            if let Some((mut alert, _)) = self.active.remove(&key) {
+                alert.status = Status::Resolved;
+                alert.resolved_at = Some(at);
+                notify.push(alert);
+            }
+        }
+        notify.retain(|alert| {
+            !self
+                .silences
+                .iter()
+                .any(|silence| silence.matches(alert, at))
+        });
+        notify
+    }
+}
+
+fn trim(samples: &mut VecDeque<(u64, u64)>, at: u64, window_secs: u64) {
+    while samples
+        .front()
+        .is_some_and(|(first, _)| first + window_secs < at)
+    {
+        samples.pop_front();
+    }
+}
+
+fn key(rule: &Rule, node: Option<&str>, channel: Option<&str>) -> AlertKey {
+    (
+        rule.name().to_owned(),
+        node.map(str::to_owned),
+        channel.map(str::to_owned),
+    )
+}
+
+//viewers of every channel on the nodes that are up
+fn channel_viewers(nodes: &HashMap<String, NodeState>) -> HashMap<String, u64> {
+    let mut totals = HashMap::new();
+    for state in nodes.values().filter(|state| state.up) {
+        for (channel, info) in &state.channels {
+            *totals.entry(channel.clone()).or_default() += info["viewers"].as_u64().unwrap_or(0);
+        }
+    }
+    totals
+}
+
+/// Posts the alerts to the webhooks, `{"alerts": [...]}` per batch.
+pub struct Notifier {
+    webhooks: Vec<String>,
+    incoming: UnboundedReceiver<Vec<Alert>>,
+}
+
+impl Notifier {
+    pub fn new(webhooks: Vec<String>, incoming: UnboundedReceiver<Vec<Alert>>) -> Self {
+        Self { webhooks, incoming }
+    }
+
+    pub async fn run(mut self) {
+        let client = match reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+        {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("alert webhook client err {}", e);
+                return;
+            }
+        };
+        while let Some(alerts) = self.incoming.recv().await {
+            for alert in &alerts {
+                log::info!(
+                    "alert {} {:?} node {:?} channel {:?}: {}",
+                    alert.rule,
+                    alert.status,
+                    alert.node,
+                    alert.channel,
+                    alert.message
+                );
+            }
+            let body = serde_json::json!({ "alerts": alerts });
+            for webhook in &self.webhooks {
+                let sent = client.post(webhook).json(&body).send().await;
+                if let Err(e) = sent.and_then(|resp| resp.error_for_status()) {
+                    log::warn!("alert webhook {} err {}", webhook, e);
+                }
+            }
+        }
+    }
+}
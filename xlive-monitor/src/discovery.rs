@@ -0,0 +1,130 @@
+use crate::monitor::NodeAddr;
+use crate::spider::{ScrapeConfig, Task};
+use crate::IncomingMessage;
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+#[derive(Deserialize, Debug)]
+struct ServerInfo {
+    addr: String,
+    node_id: Option<String>,
+    role: Option<String>,
+    monitor_addr: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ServersInfo {
+    servers: Vec<ServerInfo>,
+}
+
+//keeps one spider task per node announced to the register
+pub struct Discovery {
+    //http address of the register, e.g. http://127.0.0.1:3033
+    register: String,
+    interval: Duration,
+    scrape: ScrapeConfig,
+    outgoing: UnboundedSender<IncomingMessage>,
+    //node name to its monitor url and task
+    tasks: HashMap<String, (String, JoinHandle<()>)>,
+    //node name to where it serves, sent to the monitor when it changes
+    addrs: HashMap<String, NodeAddr>,
+}
+
+impl Discovery {
+    pub fn new(
+        register: &str,
+        interval: Duration,
+        scrape: ScrapeConfig,
+        outgoing: UnboundedSender<IncomingMessage>,
+    ) -> Self {
+        Self {
+            register: register.trim_end_matches('/').to_owned(),
+            interval,
+            scrape,
+            outgoing,
+            tasks: HashMap::new(),
+            addrs: HashMap::new(),
+        }
+    }
+
+    //node name to its monitor url and address
+    pub async fn nodes(register: &str) -> Result<HashMap<String, (String, NodeAddr)>> {
+        let resp = reqwest::get(format!("{}/servers_info", register))
+            .await?
+            .json::<ServersInfo>()
+            .await?;
+        Ok(resp
+            .servers
+            .into_iter()
+            .filter_map(|server| {
+                let role = server.role?;
+                let monitor_addr = server.monitor_addr?;
+                let id = server.node_id.unwrap_or_else(|| server.addr.clone());
+                Some((
+                    format!("{}/{}", role, id),
+                    (
+                        format!("http://{}/monitor", monitor_addr),
+                        NodeAddr {
+                            role,
+                            addr: server.addr,
+                        },
+                    ),
+                ))
+            })
+            .collect())
+    }
+
+    pub async fn run(mut self) -> Result<()> {
+        let mut interval = time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            match Self::nodes(&self.register).await {
+                Ok(nodes) => self.update(nodes),
+                //the nodes are kept until the register answers again
+                Err(e) => log::warn!("discover nodes from {} err {}", self.register, e),
+            }
+        }
+    }
+
+    fn update(&mut self, nodes: HashMap<String, (String, NodeAddr)>) {
+        let gone: Vec<String> = self
+            .tasks
+            .iter()
+            .filter(|(name, (url, _))| nodes.get(*name).map(|(url, _)| url) != Some(url))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in gone {
+            if let Some((_, task)) = self.tasks.remove(&name) {
+                log::info!("node {} left", name);
+                task.abort();
+                self.addrs.remove(&name);
+                _ = self.outgoing.send(IncomingMessage::Remove(name));
+            }
+        }
+        for (name, (url, node_addr)) in nodes {
+            if self.addrs.get(&name) != Some(&node_addr) {
+                self.addrs.insert(name.clone(), node_addr.clone());
+                _ = self
+                    .outgoing
+                    .send(IncomingMessage::Discovered((name.clone(), node_addr)));
+            }
+            match self.tasks.get(&name) {
+                Some((_, task)) if !task.is_finished() => continue,
+                Some(_) => log::info!("restart spider of node {}", name),
+                None => log::info!("node {} joined, monitor {}", name, url),
+            }
+            let mut task = Task::new(&name, &url, self.scrape, self.outgoing.clone());
+            let handle = tokio::spawn(async move {
+                if let Err(e) = task.run().await {
+                    log::error!("spider {} err {}", task.name(), e);
+                }
+            });
+            self.tasks.insert(name, (url, handle));
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::monitor::NodeState;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug)]
+pub struct HistoryConfig {
+    //samples older than it are dropped
+    pub retention: Duration,
+    //at most one sample of a node per interval
+    pub interval: Duration,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            retention: Duration::from_secs(24 * 3600),
+            interval: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ChannelSample {
+    pub viewers: u64,
+    pub relays: u64,
+    pub video_bps: u64,
+    pub audio_bps: u64,
+    pub fps: f64,
+}
+
+//a node as it was scraped at a time
+#[derive(Clone, Debug, Serialize)]
+pub struct Sample {
+    /// unix time in seconds
+    pub at: u64,
+    pub node: String,
+    pub up: bool,
+    pub channels: HashMap<String, ChannelSample>,
+}
+
+#[derive(Debug, Default)]
+pub struct HistoryQuery {
+    pub channel: Option<String>,
+    pub node: Option<String>,
+    //unix seconds, both included
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+/// Viewers of a channel over the queried range.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ChannelReport {
+    /// most viewers on all the nodes at the same time
+    pub peak_viewers: u64,
+    pub peak_at: u64,
+    pub viewer_minutes: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryResult {
+    pub samples: Vec<Sample>,
+    pub channels: HashMap<String, ChannelReport>,
+}
+
+/// Ring buffer of the node samples, in time order.
+pub struct History {
+    config: HistoryConfig,
+    samples: VecDeque<Sample>,
+    //last sample time of every node
+    last: HashMap<String, u64>,
+}
+
+impl History {
+    pub fn new(config: HistoryConfig) -> Self {
+        Self {
+            config,
+            samples: VecDeque::new(),
+            last: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, at: u64, node: &str, state: &NodeState) {
+        let interval = self.config.interval.as_secs().max(1);
+        if matches!(self.last.get(node), Some(last) if at < last + interval) {
+            return;
+        }
+        self.last.insert(node.to_owned(), at);
+        let channels = if state.up {
+            state
+                .channels
+                .iter()
+                .map(|(name, info)| (name.clone(), channel_sample(info)))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        self.samples.push_back(Sample {
+            at,
+            node: node.to_owned(),
+            up: state.up,
+            channels,
+        });
+        let oldest = at.saturating_sub(self.config.retention.as_secs());
+        while matches!(self.samples.front(), Some(sample) if sample.at < oldest) {
+            self.samples.pop_front();
+        }
+        self.last.retain(|_, last| *last >= oldest);
+    }
+
+    pub fn query(&self, query: &HistoryQuery) -> HistoryResult {
+        let samples: Vec<Sample> = self
+            .samples
+            .iter()
+            .filter(|sample| query.from.map_or(true, |from| sample.at >= from))
+            .filter(|sample| query.to.map_or(true, |to| sample.at <= to))
+            .filter(|sample| {
+                query
+                    .node
+                    .as_ref()
+                    .map_or(true, |node| &sample.node == node)
+            })
+            .map(|sample| {
+                let mut sample = sample.clone();
+                if let Some(channel) = &query.channel {
+                    sample.channels.retain(|name, _| name == channel);
+                }
+                sample
+            })
+            .filter(|sample| query.channel.is_none() || !sample.channels.is_empty())
+            .collect();
+        let channels = self.report(&samples);
+        HistoryResult { samples, channels }
+    }
+
+    //the samples are bucketed by interval, the viewers of a bucket are
+    //the sum of the last sample of every node in it
+    fn report(&self, samples: &[Sample]) -> HashMap<String, ChannelReport> {
+        let interval = self.config.interval.as_secs().max(1);
+        let mut buckets: HashMap<(&str, u64), HashMap<&str, u64>> = HashMap::new();
+        for sample in samples {
+            for (name, channel) in &sample.channels {
+                buckets
+                    .entry((name.as_str(), sample.at / interval))
+                    .or_default()
+                    .insert(sample.node.as_str(), channel.viewers);
+            }
+        }
+        let mut reports: HashMap<String, ChannelReport> = HashMap::new();
+        //summed in seconds, so the minutes do not depend on the bucket order
+        let mut viewer_secs: HashMap<&str, u64> = HashMap::new();
+        for ((name, bucket), nodes) in buckets {
+            let viewers: u64 = nodes.values().sum();
+            *viewer_secs.entry(name).or_default() += viewers * interval;
+            let report = reports.entry(name.to_owned()).or_default();
+            let at = bucket * interval;
+            if viewers > report.peak_viewers
+                || (viewers == report.peak_viewers && at < report.peak_at)
+            {
+                report.peak_viewers = viewers;
+                report.peak_at = at;
+            }
+        }
+        for (name, secs) in viewer_secs {
+            if let Some(report) = reports.get_mut(name) {
+                report.viewer_minutes = secs as f64 / 60.0;
+            }
+        }
+        reports
+    }
+}
+
+fn channel_sample(info: &serde_json::Value) -> ChannelSample {
+    let count = |value: &serde_json::Value, field: &str| {
+        value.get(field).and_then(|v| v.as_u64()).unwrap_or(0)
+    };
+    let stats = &info["stats"];
+    ChannelSample {
+        viewers: count(info, "viewers"),
+        relays: count(info, "relays"),
+        video_bps: count(stats, "video_bps"),
+        audio_bps: count(stats, "audio_bps"),
+        fps: stats.get("fps").and_then(|v| v.as_f64()).unwrap_or(0.0),
+    }
+}
@@ -1,26 +1,73 @@
+use crate::alert::Silence;
+use crate::history::HistoryQuery;
+use crate::metrics;
 use crate::IncomingMessage;
 use anyhow::Result;
+use hyper::header::AUTHORIZATION;
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Request, Response, Server, StatusCode};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
 use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::oneshot;
+use xcore::http::{bearer_matches, parse_query};
 async fn monitor(
     handle: UnboundedSender<IncomingMessage>,
+    admin_token: Arc<Option<String>>,
     req: Request<Body>,
 ) -> Result<Response<Body>> {
     let path = req.uri().path();
 
-    if path.is_empty() || !path.eq("/info") {
+    if path.eq("/metrics") {
         return Ok(Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::empty())
+            .header("Content-Type", metrics::content_type())
+            .body(Body::from(metrics::encode()))
             .unwrap());
     }
-
+    let mut query = parse_query(req.uri().query().unwrap_or(""));
     let (re, rv) = oneshot::channel();
-
-    _ = handle.send(IncomingMessage::Oneshot(re));
+    match path {
+        "/info" => _ = handle.send(IncomingMessage::Oneshot(re)),
+        //?channel=<app_name> for a single channel
+        "/topology" => _ = handle.send(IncomingMessage::Topology((query.remove("channel"), re))),
+        //?channel=&node=&from=&to=, the times in unix seconds
+        "/history" => {
+            let mut time = |key: &str| query.remove(key).and_then(|value| value.parse().ok());
+            let (from, to) = (time("from"), time("to"));
+            let history_query = HistoryQuery {
+                channel: query.remove("channel"),
+                node: query.remove("node"),
+                from,
+                to,
+            };
+            _ = handle.send(IncomingMessage::History((history_query, re)));
+        }
+        "/alerts" => _ = handle.send(IncomingMessage::Alerts(re)),
+        //POST ?rule=&node=&channel=&secs=, a missing field matches all, no secs is forever
+        "/silence" => {
+            if req.method() != Method::POST {
+                return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+            }
+            if let Some(code) = check_token(&req, &admin_token) {
+                return Ok(status(code));
+            }
+            let until = query
+                .remove("secs")
+                .and_then(|secs| secs.parse::<u64>().ok())
+                .map(|secs| now() + secs);
+            let silence = Silence {
+                rule: query.remove("rule"),
+                node: query.remove("node"),
+                channel: query.remove("channel"),
+                until,
+            };
+            let value = serde_json::json!(silence);
+            _ = handle.send(IncomingMessage::Silence(silence));
+            _ = re.send(value);
+        }
+        _ => return Ok(status(StatusCode::NOT_FOUND)),
+    }
 
     let resp = rv.await?;
 
@@ -30,20 +77,54 @@ async fn monitor(
     Ok(res)
 }
 
+//silences need `Authorization: Bearer <token>` like the admin endpoints of the register,
+//and are refused without a configured token
+fn check_token(req: &Request<Body>, admin_token: &Option<String>) -> Option<StatusCode> {
+    let admin_token = match admin_token {
+        Some(admin_token) => admin_token,
+        None => return Some(StatusCode::FORBIDDEN),
+    };
+    let authorization = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if !bearer_matches(authorization, admin_token) {
+        return Some(StatusCode::UNAUTHORIZED);
+    }
+    None
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(code)
+        .body(Body::empty())
+        .unwrap()
+}
+
 pub struct Service {
     handle: UnboundedSender<IncomingMessage>,
+    admin_token: Arc<Option<String>>,
 }
 
 impl Service {
-    pub fn new(handle: UnboundedSender<IncomingMessage>) -> Self {
-        Self { handle }
+    pub fn new(handle: UnboundedSender<IncomingMessage>, admin_token: Option<String>) -> Self {
+        Self {
+            handle,
+            admin_token: Arc::new(admin_token),
+        }
     }
 
     pub async fn run(&self) -> Result<()> {
         let handle_cp = self.handle.clone();
+        let admin_token = self.admin_token.clone();
         let make_service = make_service_fn(move |_| {
             let handle_cp = handle_cp.clone();
-            async move { Ok::<_, Infallible>(service_fn(move |req| monitor(handle_cp.clone(), req))) }
+            let admin_token = admin_token.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    monitor(handle_cp.clone(), admin_token.clone(), req)
+                }))
+            }
         });
         let addr = "[::]:3033".parse().unwrap();
         let server = Server::bind(&addr).serve(make_service);
@@ -52,3 +133,10 @@ impl Service {
         Ok(())
     }
 }
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
@@ -1,12 +1,31 @@
-use std::collections::HashMap;
-
+use crate::alert::Silence;
+use crate::history::HistoryQuery;
+use crate::monitor::NodeAddr;
+use crate::spider::Scrape;
 use tokio::sync::oneshot;
 
+pub mod alert;
+pub mod discovery;
+pub mod history;
 pub mod http_service;
+pub mod metrics;
 pub mod monitor;
 pub mod spider;
+pub mod topology;
 
 pub enum IncomingMessage {
-    TaskMsg((String, HashMap<String, usize>)),
+    TaskMsg((String, Scrape)),
+    //role and address a node announced to the register
+    Discovered((String, NodeAddr)),
+    //a node left the cluster
+    Remove(String),
     Oneshot(oneshot::Sender<serde_json::Value>),
+    //samples and viewer reports of a time range
+    History((HistoryQuery, oneshot::Sender<serde_json::Value>)),
+    //firing alerts and active silences
+    Alerts(oneshot::Sender<serde_json::Value>),
+    //mute the notifications of the alerts it matches
+    Silence(Silence),
+    //the distribution tree of one or all channels
+    Topology((Option<String>, oneshot::Sender<serde_json::Value>)),
 }
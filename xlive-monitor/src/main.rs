@@ -1,23 +1,35 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use chrono::Local;
 use serde::Deserialize;
 use std::fs;
 use std::io::Write;
+use std::time::Duration;
 use structopt::StructOpt;
 use tokio::sync::mpsc::unbounded_channel;
-use xlive_monitor::monitor::Monitor;
+use xlive_monitor::alert::AlertConfig;
+use xlive_monitor::discovery::Discovery;
+use xlive_monitor::history::HistoryConfig;
+use xlive_monitor::http_service::Service;
+use xlive_monitor::monitor::{Monitor, NodeAddr};
+use xlive_monitor::spider::{ScrapeConfig, Task};
 use xlive_monitor::IncomingMessage;
-use xlive_monitor::{http_service::Service, spider::Task};
 
 #[derive(Deserialize, Debug)]
 struct Host {
     name: Option<String>,
     addr: Option<String>,
+    //role and serving address, to place the node in the channel topology
+    role: Option<String>,
+    node_addr: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
 struct Conf {
     hosts: Option<Vec<Host>>,
+    //http address of the register the nodes are discovered from
+    register: Option<String>,
+    //rules, webhooks and silences of the alerts
+    alert: Option<AlertConfig>,
 }
 
 #[derive(Debug, StructOpt)]
@@ -25,6 +37,40 @@ struct Conf {
 struct Opt {
     #[structopt(short = "c", long = "config", default_value = "config.toml")]
     config: String,
+
+    /// http address of the register to discover the nodes from, e.g. http://127.0.0.1:3033,
+    /// overrides the register of the config
+    #[structopt(short = "r", long = "register", default_value = "")]
+    register: String,
+
+    /// seconds between two refreshes of the node list from the register
+    #[structopt(long = "discover-interval", default_value = "5")]
+    discover_interval: u64,
+
+    /// ms between two scrapes of a node
+    #[structopt(long = "scrape-interval", default_value = "1000")]
+    scrape_interval: u64,
+
+    /// ms a node has to answer a scrape before it is marked down
+    #[structopt(long = "scrape-timeout", default_value = "800")]
+    scrape_timeout: u64,
+
+    /// seconds the node and channel history is kept
+    #[structopt(long = "history-retention", default_value = "86400")]
+    history_retention: u64,
+
+    /// seconds between two history samples of a node
+    #[structopt(long = "history-interval", default_value = "10")]
+    history_interval: u64,
+
+    /// bearer token of `/silence`, empty disables it
+    #[structopt(
+        long = "admin-token",
+        env = "XLIVE_ADMIN_TOKEN",
+        default_value = "",
+        hide_env_values = true
+    )]
+    admin_token: String,
 }
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -41,36 +87,70 @@ async fn main() -> Result<()> {
             )
         })
         .init();
-    let opt = Opt::from_args();
+    let mut opt = Opt::from_args();
+    //kept out of the log
+    let admin_token = std::mem::take(&mut opt.admin_token);
     log::info!("opt:{:?}", opt);
 
-    let file_path = opt.config;
-    let conf_str = fs::read_to_string(file_path)?;
-    let config: Conf = toml::from_str(&conf_str)?;
+    //the config is optional when the nodes are discovered from the register
+    let mut config: Conf = match fs::read_to_string(&opt.config) {
+        Ok(conf_str) => toml::from_str(&conf_str)?,
+        Err(e) if opt.register.is_empty() => bail!("read config {} err {}", opt.config, e),
+        Err(_) => Conf::default(),
+    };
+    if !opt.register.is_empty() {
+        config.register = Some(opt.register.clone());
+    }
     log::info!("config:{:?}", config);
     let (sender, recivicer) = unbounded_channel::<IncomingMessage>();
 
+    let scrape = ScrapeConfig {
+        interval: Duration::from_millis(opt.scrape_interval),
+        timeout: Duration::from_millis(opt.scrape_timeout),
+        ..ScrapeConfig::default()
+    };
+    let history = HistoryConfig {
+        retention: Duration::from_secs(opt.history_retention),
+        interval: Duration::from_secs(opt.history_interval),
+    };
     let mut handles = vec![];
-    let hosts = config.hosts.unwrap();
+    if let Some(register) = &config.register {
+        let discovery = Discovery::new(
+            register,
+            Duration::from_secs(opt.discover_interval),
+            scrape,
+            sender.clone(),
+        );
+        handles.push(tokio::spawn(discovery.run()));
+    }
+    //static hosts, for nodes that do not announce themselves to the register
+    let hosts = config.hosts.unwrap_or_default();
     for host in hosts {
+        let name = host.name.unwrap();
+        if let (Some(role), Some(addr)) = (host.role, host.node_addr) {
+            _ = sender.send(IncomingMessage::Discovered((
+                name.clone(),
+                NodeAddr { role, addr },
+            )));
+        }
+        let addr = host.addr.unwrap();
         let sender_cp = sender.clone();
         handles.push(tokio::spawn(async move {
-            Task::new(&host.name.unwrap(), &host.addr.unwrap(), sender_cp)
-                .run()
-                .await?;
+            Task::new(&name, &addr, scrape, sender_cp).run().await?;
             Ok::<(), anyhow::Error>(())
         }));
     }
 
+    let alert = config.alert.unwrap_or_default();
     handles.push(tokio::spawn(async move {
-        Monitor::new(recivicer).run().await?;
+        Monitor::new(recivicer, history, alert).run().await?;
         Ok::<(), anyhow::Error>(())
     }));
 
     let sender_cp = sender.clone();
-
+    let admin_token = Some(admin_token).filter(|token| !token.is_empty());
     handles.push(tokio::spawn(async move {
-        Service::new(sender_cp).run().await?;
+        Service::new(sender_cp, admin_token).run().await?;
         Ok::<(), anyhow::Error>(())
     }));
 
@@ -0,0 +1,68 @@
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, TextEncoder,
+};
+use std::sync::LazyLock;
+
+/// Metrics of the monitor, in the default prometheus registry.
+pub struct Metrics {
+    pub node_up: IntGaugeVec,
+    pub scrape_seconds: HistogramVec,
+    pub scrape_failures: IntCounterVec,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let metrics = Metrics {
+        node_up: IntGaugeVec::new(
+            Opts::new(
+                "xlive_monitor_node_up",
+                "whether the last scrape of the node succeeded",
+            ),
+            &["node"],
+        )
+        .unwrap(),
+        scrape_seconds: HistogramVec::new(
+            HistogramOpts::new(
+                "xlive_monitor_scrape_seconds",
+                "time successful scrapes took",
+            )
+            .buckets(vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0]),
+            &["node"],
+        )
+        .unwrap(),
+        scrape_failures: IntCounterVec::new(
+            Opts::new("xlive_monitor_scrape_failures_total", "failed scrapes"),
+            &["node"],
+        )
+        .unwrap(),
+    };
+    let registry = prometheus::default_registry();
+    registry
+        .register(Box::new(metrics.node_up.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.scrape_seconds.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.scrape_failures.clone()))
+        .unwrap();
+    metrics
+});
+
+//a node that is no longer discovered leaves no series behind
+pub fn remove_node(name: &str) {
+    _ = METRICS.node_up.remove_label_values(&[name]);
+    _ = METRICS.scrape_seconds.remove_label_values(&[name]);
+    _ = METRICS.scrape_failures.remove_label_values(&[name]);
+}
+
+pub fn encode() -> String {
+    LazyLock::force(&METRICS);
+    let mut buf = vec![];
+    //writing to a vec does not fail
+    _ = TextEncoder::new().encode(&prometheus::gather(), &mut buf);
+    String::from_utf8(buf).unwrap_or_default()
+}
+
+pub fn content_type() -> String {
+    TextEncoder::new().format_type().to_owned()
+}
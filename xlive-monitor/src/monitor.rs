@@ -1,33 +1,151 @@
+use crate::alert::{Alert, AlertConfig, Alerter, Notifier};
+use crate::history::{History, HistoryConfig};
+use crate::metrics::{self, METRICS};
+use crate::spider::Scrape;
+use crate::topology;
 use crate::IncomingMessage;
 use anyhow::Result;
+use serde::Serialize;
 use std::collections::HashMap;
-use tokio::sync::mpsc::UnboundedReceiver;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+//where a discovered node serves, caches and edges report it as the upstream of their channels
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeAddr {
+    pub role: String,
+    pub addr: String,
+}
+
+//the last scrapes of a node
+#[derive(Debug, Default, Serialize)]
+pub struct NodeState {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addr: Option<String>,
+    pub up: bool,
+    //ms the last successful scrape took
+    pub latency_ms: u64,
+    pub last_scrape: u64,
+    //the channels are from then, they are stale while the node is down
+    pub last_success: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub failures: u32,
+    pub channels: HashMap<String, serde_json::Value>,
+}
 
 pub struct Monitor {
-    state: HashMap<String, HashMap<String, usize>>,
+    state: HashMap<String, NodeState>,
+    history: History,
+    alerter: Alerter,
+    webhooks: Vec<String>,
     incoming: UnboundedReceiver<IncomingMessage>,
 }
 
 impl Monitor {
-    pub fn new(incoming: UnboundedReceiver<IncomingMessage>) -> Self {
+    pub fn new(
+        incoming: UnboundedReceiver<IncomingMessage>,
+        history: HistoryConfig,
+        alert: AlertConfig,
+    ) -> Self {
         Self {
             state: HashMap::new(),
+            history: History::new(history),
+            alerter: Alerter::new(&alert),
+            webhooks: alert.webhooks,
             incoming,
         }
     }
 
     pub async fn run(&mut self) -> Result<()> {
+        //the webhooks are posted to outside of the monitor loop
+        let (notify, notifications) = mpsc::unbounded_channel();
+        tokio::spawn(Notifier::new(self.webhooks.clone(), notifications).run());
         while let Some(msg) = self.incoming.recv().await {
             match msg {
-                IncomingMessage::TaskMsg((name, value)) => {
-                    self.state.insert(name, value);
+                IncomingMessage::TaskMsg((name, scrape)) => {
+                    let node_up = METRICS.node_up.with_label_values(&[&name]);
+                    match &scrape {
+                        Scrape::Up { latency, .. } => {
+                            node_up.set(1);
+                            METRICS
+                                .scrape_seconds
+                                .with_label_values(&[&name])
+                                .observe(latency.as_secs_f64());
+                        }
+                        Scrape::Down { .. } => {
+                            node_up.set(0);
+                            METRICS.scrape_failures.with_label_values(&[&name]).inc();
+                        }
+                    }
+                    let node = self.state.entry(name.clone()).or_default();
+                    node.last_scrape = now();
+                    match scrape {
+                        Scrape::Up { channels, latency } => {
+                            node.up = true;
+                            node.latency_ms = latency.as_millis() as u64;
+                            node.last_success = Some(node.last_scrape);
+                            node.error = None;
+                            node.failures = 0;
+                            node.channels = channels;
+                        }
+                        Scrape::Down { error, failures } => {
+                            node.up = false;
+                            node.error = Some(error);
+                            node.failures = failures;
+                        }
+                    }
+                    self.history.record(node.last_scrape, &name, node);
+                    let at = node.last_scrape;
+                    send(&notify, self.alerter.evaluate(at, &name, &self.state));
+                }
+                IncomingMessage::Discovered((name, node_addr)) => {
+                    let node = self.state.entry(name).or_default();
+                    node.role = Some(node_addr.role);
+                    node.addr = Some(node_addr.addr);
+                }
+                IncomingMessage::Remove(name) => {
+                    metrics::remove_node(&name);
+                    self.state.remove(&name);
+                    send(&notify, self.alerter.remove(now(), &name));
                 }
                 IncomingMessage::Oneshot(sender) => {
                     let value = serde_json::json!(self.state);
                     _ = sender.send(value);
                 }
+                IncomingMessage::History((query, sender)) => {
+                    let value = serde_json::json!(self.history.query(&query));
+                    _ = sender.send(value);
+                }
+                IncomingMessage::Alerts(sender) => {
+                    let value = serde_json::json!({
+                        "alerts": self.alerter.active(),
+                        "silences": self.alerter.silences(now()),
+                    });
+                    _ = sender.send(value);
+                }
+                IncomingMessage::Silence(silence) => self.alerter.silence(silence),
+                IncomingMessage::Topology((channel, sender)) => {
+                    let value = serde_json::json!(topology::build(&self.state, channel.as_deref()));
+                    _ = sender.send(value);
+                }
             }
         }
         Ok(())
     }
 }
+
+fn send(notify: &UnboundedSender<Vec<Alert>>, alerts: Vec<Alert>) {
+    if !alerts.is_empty() {
+        _ = notify.send(alerts);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
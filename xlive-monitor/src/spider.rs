@@ -1,40 +1,137 @@
 use crate::IncomingMessage;
-use anyhow::{Ok, Result};
-use std::{collections::HashMap, time::Duration};
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 use tokio::{sync::mpsc::UnboundedSender, time};
 
+#[derive(Clone, Copy, Debug)]
+pub struct ScrapeConfig {
+    pub interval: Duration,
+    //a node that does not answer within it is down
+    pub timeout: Duration,
+    //the wait after consecutive failures doubles up to it
+    pub max_backoff: Duration,
+}
+
+impl Default for ScrapeConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_millis(800),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+//the result of a scrape of a node
+#[derive(Debug)]
+pub enum Scrape {
+    Up {
+        channels: HashMap<String, serde_json::Value>,
+        latency: Duration,
+    },
+    Down {
+        error: String,
+        //consecutive failures
+        failures: u32,
+    },
+}
+
 pub struct Task {
     outgoing: UnboundedSender<IncomingMessage>,
     name: String,
     url: String,
+    config: ScrapeConfig,
 }
 
 impl Task {
-    pub fn new(name: &str, url: &str, outgoing: UnboundedSender<IncomingMessage>) -> Self {
+    pub fn new(
+        name: &str,
+        url: &str,
+        config: ScrapeConfig,
+        outgoing: UnboundedSender<IncomingMessage>,
+    ) -> Self {
         Self {
             outgoing,
             name: name.to_owned(),
             url: url.to_owned(),
+            config,
         }
     }
 
-    pub async fn monitor(url: &str) -> Result<HashMap<String, usize>> {
-        let resp = reqwest::get(url)
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    //channel name to its info, subscribers and publisher switches
+    pub async fn monitor(
+        client: &reqwest::Client,
+        url: &str,
+    ) -> Result<HashMap<String, serde_json::Value>> {
+        let resp = client
+            .get(url)
+            .send()
             .await?
-            .json::<HashMap<String, usize>>()
+            .error_for_status()?
+            .json::<HashMap<String, serde_json::Value>>()
             .await?;
 
         Ok(resp)
     }
 
+    //runs until the monitor is gone, a node that fails is scraped again after a backoff
     pub async fn run(&mut self) -> Result<()> {
+        let client = reqwest::Client::builder()
+            .timeout(self.config.timeout)
+            .build()?;
+        let mut interval = time::interval(self.config.interval);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        let mut failures = 0u32;
         loop {
-            let mut interval = time::interval(Duration::from_secs(1));
             interval.tick().await;
-            let resp = Self::monitor(&self.url).await?;
-            _ = self
+            let started = Instant::now();
+            let scrape = match Self::monitor(&client, &self.url).await {
+                Ok(channels) => {
+                    if failures > 0 {
+                        log::info!("node {} is up again", self.name);
+                    }
+                    failures = 0;
+                    Scrape::Up {
+                        channels,
+                        latency: started.elapsed(),
+                    }
+                }
+                Err(e) => {
+                    failures += 1;
+                    if failures == 1 {
+                        log::warn!("node {} is down: {}", self.name, e);
+                    }
+                    Scrape::Down {
+                        error: e.to_string(),
+                        failures,
+                    }
+                }
+            };
+            if self
                 .outgoing
-                .send(IncomingMessage::TaskMsg((self.name.clone(), resp)));
+                .send(IncomingMessage::TaskMsg((self.name.clone(), scrape)))
+                .is_err()
+            {
+                return Ok(());
+            }
+            if failures > 1 {
+                time::sleep(self.backoff(failures)).await;
+                interval.reset();
+            }
         }
     }
+
+    fn backoff(&self, failures: u32) -> Duration {
+        self.config
+            .interval
+            .saturating_mul(1 << (failures - 1).min(16))
+            .min(self.config.max_backoff)
+    }
 }
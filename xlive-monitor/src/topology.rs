@@ -0,0 +1,132 @@
+use crate::monitor::NodeState;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// A node serving a channel and the nodes pulling it from there.
+#[derive(Debug, Serialize)]
+pub struct TopologyNode {
+    pub node: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addr: Option<String>,
+    /// address the node pulls the channel from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+    pub viewers: u64,
+    pub relays: u64,
+    /// viewers of this node and all the nodes below it
+    pub total_viewers: u64,
+    pub children: Vec<TopologyNode>,
+}
+
+/// The distribution tree of a channel, origins at the roots.
+#[derive(Debug, Serialize)]
+pub struct ChannelTopology {
+    pub viewers: u64,
+    pub roots: Vec<TopologyNode>,
+}
+
+struct Entry<'a> {
+    node: &'a str,
+    state: &'a NodeState,
+    upstream: Option<String>,
+    viewers: u64,
+    relays: u64,
+}
+
+/// Channel name to its tree, assembled from the last scrape of the nodes that are up.
+pub fn build(
+    nodes: &HashMap<String, NodeState>,
+    channel: Option<&str>,
+) -> HashMap<String, ChannelTopology> {
+    //the address a node serves on, as its downstream nodes report it
+    let by_addr: HashMap<&str, &str> = nodes
+        .iter()
+        .filter_map(|(name, state)| Some((state.addr.as_deref()?, name.as_str())))
+        .collect();
+    let mut channels: HashMap<&str, Vec<Entry>> = HashMap::new();
+    for (name, state) in nodes.iter().filter(|(_, state)| state.up) {
+        for (channel_name, info) in &state.channels {
+            if channel.is_some_and(|channel| channel != channel_name.as_str()) {
+                continue;
+            }
+            let count = |field: &str| info.get(field).and_then(|v| v.as_u64()).unwrap_or(0);
+            channels
+                .entry(channel_name.as_str())
+                .or_default()
+                .push(Entry {
+                    node: name,
+                    state,
+                    upstream: info
+                        .get("upstream")
+                        .and_then(|v| v.as_str())
+                        .map(str::to_owned),
+                    viewers: count("viewers"),
+                    relays: count("relays"),
+                });
+        }
+    }
+    channels
+        .into_iter()
+        .map(|(channel_name, entries)| (channel_name.to_owned(), tree(entries, &by_addr)))
+        .collect()
+}
+
+fn tree(mut entries: Vec<Entry>, by_addr: &HashMap<&str, &str>) -> ChannelTopology {
+    entries.sort_by(|a, b| a.node.cmp(b.node));
+    let serving: HashSet<&str> = entries.iter().map(|entry| entry.node).collect();
+    let parent = |entry: &Entry| {
+        let upstream = by_addr.get(entry.upstream.as_deref()?)?;
+        (*upstream != entry.node && serving.contains(upstream)).then_some(*upstream)
+    };
+    let mut children: HashMap<&str, Vec<usize>> = HashMap::new();
+    let mut roots = vec![];
+    for (i, entry) in entries.iter().enumerate() {
+        match parent(entry) {
+            Some(parent) => children.entry(parent).or_default().push(i),
+            None => roots.push(i),
+        }
+    }
+    let mut visited = HashSet::new();
+    let mut trees: Vec<TopologyNode> = roots
+        .into_iter()
+        .map(|i| subtree(i, &entries, &children, &mut visited))
+        .collect();
+    //nodes that report each other as upstream are cut at the first one
+    for i in 0..entries.len() {
+        if !visited.contains(&i) {
+            trees.push(subtree(i, &entries, &children, &mut visited));
+        }
+    }
+    ChannelTopology {
+        viewers: entries.iter().map(|entry| entry.viewers).sum(),
+        roots: trees,
+    }
+}
+
+fn subtree(
+    i: usize,
+    entries: &[Entry],
+    children: &HashMap<&str, Vec<usize>>,
+    visited: &mut HashSet<usize>,
+) -> TopologyNode {
+    visited.insert(i);
+    let entry = &entries[i];
+    let mut nodes = vec![];
+    for &child in children.get(entry.node).into_iter().flatten() {
+        if !visited.contains(&child) {
+            nodes.push(subtree(child, entries, children, visited));
+        }
+    }
+    TopologyNode {
+        node: entry.node.to_owned(),
+        role: entry.state.role.clone(),
+        addr: entry.state.addr.clone(),
+        upstream: entry.upstream.clone(),
+        viewers: entry.viewers,
+        relays: entry.relays,
+        total_viewers: entry.viewers + nodes.iter().map(|node| node.total_viewers).sum::<u64>(),
+        children: nodes,
+    }
+}
@@ -0,0 +1,263 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use serde_json::json;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::time;
+use xlive_monitor::alert::{AlertConfig, Alerter, Silence, Status};
+use xlive_monitor::history::HistoryConfig;
+use xlive_monitor::monitor::{Monitor, NodeState};
+use xlive_monitor::spider::Scrape;
+use xlive_monitor::IncomingMessage;
+
+fn config(rules: &str) -> AlertConfig {
+    toml::from_str(rules).unwrap()
+}
+
+fn up(channels: serde_json::Value) -> NodeState {
+    NodeState {
+        up: true,
+        channels: serde_json::from_value(channels).unwrap(),
+        ..Default::default()
+    }
+}
+
+fn down(failures: u32) -> NodeState {
+    NodeState {
+        failures,
+        error: Some("timeout".to_owned()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn node_down_fires_once_and_resolves() {
+    let mut alerter = Alerter::new(&config(
+        r#"
+        [[rules]]
+        kind = "node_down"
+        failures = 2
+        "#,
+    ));
+    let mut nodes = HashMap::new();
+    nodes.insert("edge/a".to_owned(), down(1));
+    assert!(alerter.evaluate(1, "edge/a", &nodes).is_empty());
+    nodes.insert("edge/a".to_owned(), down(2));
+    let alerts = alerter.evaluate(2, "edge/a", &nodes);
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].rule, "node_down");
+    assert_eq!(alerts[0].node.as_deref(), Some("edge/a"));
+    assert_eq!(alerts[0].status, Status::Firing);
+    //deduplicated while it keeps firing
+    nodes.insert("edge/a".to_owned(), down(3));
+    assert!(alerter.evaluate(3, "edge/a", &nodes).is_empty());
+    assert_eq!(alerter.active().len(), 1);
+
+    nodes.insert("edge/a".to_owned(), up(json!({})));
+    let alerts = alerter.evaluate(4, "edge/a", &nodes);
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].status, Status::Resolved);
+    assert_eq!((alerts[0].started_at, alerts[0].resolved_at), (2, Some(4)));
+    assert!(alerter.active().is_empty());
+}
+
+#[test]
+fn expired_node_keeps_node_down_firing() {
+    let mut alerter = Alerter::new(&config(
+        r#"
+        [[rules]]
+        kind = "node_down"
+        failures = 2
+        "#,
+    ));
+    let mut nodes = HashMap::new();
+    nodes.insert("edge/a".to_owned(), down(2));
+    assert_eq!(alerter.evaluate(3, "edge/a", &nodes).len(), 1);
+    //the crashed node stopped heartbeating and the register expired it
+    nodes.remove("edge/a");
+    assert!(alerter.remove(15, "edge/a").is_empty());
+    assert_eq!(alerter.active().len(), 1);
+
+    //until it is scraped up again
+    nodes.insert("edge/a".to_owned(), up(json!({})));
+    let alerts = alerter.evaluate(60, "edge/a", &nodes);
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].status, Status::Resolved);
+}
+
+#[test]
+fn first_viewers_are_not_a_spike() {
+    let mut alerter = Alerter::new(&config(
+        r#"
+        [[rules]]
+        kind = "viewer_spike"
+        ratio = 2.0
+        window_secs = 10
+        "#,
+    ));
+    let mut nodes = HashMap::new();
+    for (at, viewers) in [(1, 0), (2, 1), (3, 5)] {
+        nodes.insert(
+            "edge/a".to_owned(),
+            up(json!({ "live/room": { "viewers": viewers } })),
+        );
+        assert!(alerter.evaluate(at, "edge/a", &nodes).is_empty());
+    }
+}
+
+#[test]
+fn channel_rules() {
+    let mut alerter = Alerter::new(&config(
+        r#"
+        [[rules]]
+        kind = "bitrate_zero"
+        for_secs = 5
+
+        [[rules]]
+        kind = "reconnect_storm"
+        reconnects = 3
+        window_secs = 10
+
+        [[rules]]
+        kind = "viewer_spike"
+        ratio = 2.0
+        min_viewers = 10
+        window_secs = 10
+        "#,
+    ));
+    let mut nodes = HashMap::new();
+    let channel = |bps: u64, reconnects: u64, viewers: u64| {
+        up(json!({ "live/room": {
+            "viewers": viewers,
+            "reconnects": reconnects,
+            "stats": { "video_bps": bps, "audio_bps": 0 },
+        }}))
+    };
+    let mut rules = |alerter: &mut Alerter, at: u64, state: NodeState| {
+        nodes.insert("edge/a".to_owned(), state);
+        let mut alerts: Vec<(String, Status)> = alerter
+            .evaluate(at, "edge/a", &nodes)
+            .into_iter()
+            .map(|alert| (alert.rule, alert.status))
+            .collect();
+        alerts.sort_by(|a, b| a.0.cmp(&b.0));
+        alerts
+    };
+
+    assert!(rules(&mut alerter, 100, channel(1000, 0, 6)).is_empty());
+    assert!(rules(&mut alerter, 101, channel(0, 1, 8)).is_empty());
+    assert_eq!(
+        rules(&mut alerter, 106, channel(0, 3, 12)),
+        vec![
+            ("bitrate_zero".to_owned(), Status::Firing),
+            ("reconnect_storm".to_owned(), Status::Firing),
+            ("viewer_spike".to_owned(), Status::Firing),
+        ]
+    );
+    //the spike and the storm are out of the window
+    assert_eq!(
+        rules(&mut alerter, 120, channel(1000, 3, 12)),
+        vec![
+            ("bitrate_zero".to_owned(), Status::Resolved),
+            ("reconnect_storm".to_owned(), Status::Resolved),
+            ("viewer_spike".to_owned(), Status::Resolved),
+        ]
+    );
+}
+
+#[test]
+fn silenced_alerts_are_not_notified() {
+    let mut alerter = Alerter::new(&config(
+        r#"
+        [[rules]]
+        kind = "node_down"
+        failures = 1
+
+        [[silences]]
+        node = "edge/a"
+        "#,
+    ));
+    alerter.silence(Silence {
+        rule: Some("node_down".to_owned()),
+        until: Some(10),
+        ..Default::default()
+    });
+    let mut nodes = HashMap::new();
+    nodes.insert("edge/a".to_owned(), down(1));
+    nodes.insert("edge/b".to_owned(), down(1));
+    assert!(alerter.evaluate(1, "edge/a", &nodes).is_empty());
+    assert!(alerter.evaluate(1, "edge/b", &nodes).is_empty());
+    assert_eq!(alerter.active().len(), 2);
+    assert_eq!(alerter.silences(10).len(), 1);
+
+    //edge/b is no longer silenced, but it already fired
+    nodes.insert("edge/b".to_owned(), up(json!({})));
+    let alerts = alerter.evaluate(10, "edge/b", &nodes);
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].status, Status::Resolved);
+}
+
+async fn recv(sink: &mut UnboundedReceiver<serde_json::Value>) -> serde_json::Value {
+    time::timeout(Duration::from_secs(3), sink.recv())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn alerts_are_posted_to_the_webhook() {
+    let (sink_sender, mut sink) = mpsc::unbounded_channel();
+    let make_service = make_service_fn(move |_| {
+        let sink_sender = sink_sender.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let sink_sender = sink_sender.clone();
+                async move {
+                    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                    _ = sink_sender.send(serde_json::from_slice(&body).unwrap());
+                    Ok::<_, Infallible>(Response::new(Body::empty()))
+                }
+            }))
+        }
+    });
+    tokio::spawn(Server::bind(&"127.0.0.1:29576".parse().unwrap()).serve(make_service));
+
+    let alert = config(
+        r#"
+        webhooks = ["http://127.0.0.1:29576/alerts"]
+
+        [[rules]]
+        kind = "node_down"
+        "#,
+    );
+    let (sender, incoming) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        Monitor::new(incoming, HistoryConfig::default(), alert)
+            .run()
+            .await
+    });
+    for failures in 1..=4 {
+        let scrape = Scrape::Down {
+            error: "timeout".to_owned(),
+            failures,
+        };
+        _ = sender.send(IncomingMessage::TaskMsg(("edge/a".to_owned(), scrape)));
+    }
+    let scrape = Scrape::Up {
+        channels: HashMap::new(),
+        latency: Duration::from_millis(1),
+    };
+    _ = sender.send(IncomingMessage::TaskMsg(("edge/a".to_owned(), scrape)));
+
+    let fired = recv(&mut sink).await;
+    assert_eq!(fired["alerts"][0]["rule"], "node_down");
+    assert_eq!(fired["alerts"][0]["node"], "edge/a");
+    assert_eq!(fired["alerts"][0]["status"], "firing");
+    let resolved = recv(&mut sink).await;
+    assert_eq!(resolved["alerts"][0]["status"], "resolved");
+    assert!(time::timeout(Duration::from_millis(200), sink.recv())
+        .await
+        .is_err());
+}
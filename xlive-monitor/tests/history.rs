@@ -0,0 +1,72 @@
+use serde_json::json;
+use std::time::Duration;
+use xlive_monitor::history::{ChannelReport, History, HistoryConfig, HistoryQuery};
+use xlive_monitor::monitor::NodeState;
+
+fn node(up: bool, viewers: u64) -> NodeState {
+    NodeState {
+        up,
+        channels: serde_json::from_value(json!({
+            "live/room": { "viewers": viewers, "relays": 0, "stats": { "video_bps": 800000, "fps": 25.0 } },
+            "live/other": { "viewers": 1, "relays": 0 },
+        }))
+        .unwrap(),
+        ..Default::default()
+    }
+}
+
+fn history(retention: u64) -> History {
+    History::new(HistoryConfig {
+        retention: Duration::from_secs(retention),
+        interval: Duration::from_secs(10),
+    })
+}
+
+#[test]
+fn reports_peak_and_viewer_minutes() {
+    let mut history = history(3600);
+    for (at, a, b) in [(1000, 3, 2), (1010, 5, 4), (1020, 6, 1)] {
+        history.record(at, "edge/a", &node(true, a));
+        history.record(at + 1, "edge/b", &node(true, b));
+    }
+    //within the interval of the last sample
+    history.record(1025, "edge/a", &node(true, 100));
+    //a node that is down has no viewers
+    history.record(1030, "edge/a", &node(false, 100));
+
+    let result = history.query(&HistoryQuery::default());
+    assert_eq!(result.samples.len(), 7);
+    assert_eq!(
+        result.channels["live/room"],
+        ChannelReport {
+            peak_viewers: 9,
+            peak_at: 1010,
+            viewer_minutes: (5 + 9 + 7) as f64 * 10.0 / 60.0,
+        }
+    );
+    assert_eq!(result.channels["live/other"].peak_viewers, 2);
+
+    let result = history.query(&HistoryQuery {
+        channel: Some("live/room".to_owned()),
+        node: Some("edge/a".to_owned()),
+        from: Some(1010),
+        to: Some(1020),
+    });
+    assert_eq!(result.samples.len(), 2);
+    assert!(result.samples.iter().all(|sample| sample.node == "edge/a"));
+    assert_eq!(result.samples[0].channels["live/room"].video_bps, 800000);
+    assert!(!result.samples[0].channels.contains_key("live/other"));
+    assert_eq!(result.channels.len(), 1);
+    assert_eq!(result.channels["live/room"].peak_viewers, 6);
+}
+
+#[test]
+fn old_samples_are_dropped() {
+    let mut history = history(60);
+    for at in (0..=120).step_by(10) {
+        history.record(at, "edge/a", &node(true, 1));
+    }
+    let samples = history.query(&HistoryQuery::default()).samples;
+    assert_eq!(samples.first().map(|sample| sample.at), Some(60));
+    assert_eq!(samples.len(), 7);
+}
@@ -0,0 +1,55 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response, Server};
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::time;
+use xlive_monitor::spider::{Scrape, ScrapeConfig, Task};
+use xlive_monitor::IncomingMessage;
+
+async fn next(incoming: &mut tokio::sync::mpsc::UnboundedReceiver<IncomingMessage>) -> Scrape {
+    match time::timeout(Duration::from_secs(3), incoming.recv()).await {
+        Ok(Some(IncomingMessage::TaskMsg((_, scrape)))) => scrape,
+        _ => panic!("no scrape"),
+    }
+}
+
+#[tokio::test]
+async fn a_node_is_scraped_again_after_it_failed() {
+    let (sender, mut incoming) = unbounded_channel();
+    let config = ScrapeConfig {
+        interval: Duration::from_millis(50),
+        timeout: Duration::from_millis(200),
+        max_backoff: Duration::from_millis(200),
+    };
+    let mut task = Task::new(
+        "edge/test",
+        "http://127.0.0.1:29546/monitor",
+        config,
+        sender,
+    );
+    tokio::spawn(async move { task.run().await });
+
+    assert!(matches!(
+        next(&mut incoming).await,
+        Scrape::Down { failures: 1, .. }
+    ));
+    assert!(matches!(
+        next(&mut incoming).await,
+        Scrape::Down { failures: 2, .. }
+    ));
+
+    let make_service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|_| async {
+            Ok::<_, Infallible>(Response::new(Body::from(r#"{"live/room":1}"#)))
+        }))
+    });
+    tokio::spawn(Server::bind(&"127.0.0.1:29546".parse().unwrap()).serve(make_service));
+
+    loop {
+        if let Scrape::Up { channels, .. } = next(&mut incoming).await {
+            assert_eq!(channels["live/room"], 1);
+            break;
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use serde_json::json;
+use std::collections::HashMap;
+use xlive_monitor::monitor::NodeState;
+use xlive_monitor::topology::{self, TopologyNode};
+
+fn node(role: &str, addr: &str, channels: serde_json::Value) -> NodeState {
+    NodeState {
+        role: Some(role.to_owned()),
+        addr: Some(addr.to_owned()),
+        up: true,
+        channels: serde_json::from_value(channels).unwrap(),
+        ..Default::default()
+    }
+}
+
+//node names of a tree, depth first
+fn names(node: &TopologyNode, out: &mut Vec<String>) {
+    out.push(node.node.clone());
+    for child in &node.children {
+        names(child, out);
+    }
+}
+
+#[test]
+fn channels_are_assembled_into_trees() {
+    let mut nodes = HashMap::new();
+    nodes.insert(
+        "origin/a".to_owned(),
+        node(
+            "origin",
+            "10.0.0.1:9878",
+            json!({ "live/room": { "subscribers": 1, "relays": 1, "viewers": 0 } }),
+        ),
+    );
+    nodes.insert(
+        "cache/b".to_owned(),
+        node(
+            "cache",
+            "10.0.0.2:9888",
+            json!({ "live/room": { "relays": 2, "viewers": 0, "upstream": "10.0.0.1:9878" } }),
+        ),
+    );
+    for (name, viewers) in [("edge/c", 3), ("edge/d", 4)] {
+        nodes.insert(
+            name.to_owned(),
+            node(
+                "edge",
+                "10.0.0.3:40000",
+                json!({
+                    "live/room": { "viewers": viewers, "relays": 0, "upstream": "10.0.0.2:9888" },
+                    "live/other": { "viewers": 1, "relays": 0, "upstream": "10.0.0.9:9878" },
+                }),
+            ),
+        );
+    }
+    //the channels of a node that is down are stale
+    let mut down = node(
+        "edge",
+        "10.0.0.4:40000",
+        json!({ "live/room": { "viewers": 100, "upstream": "10.0.0.2:9888" } }),
+    );
+    down.up = false;
+    nodes.insert("edge/e".to_owned(), down);
+
+    let channels = topology::build(&nodes, None);
+    let room = &channels["live/room"];
+    assert_eq!(room.viewers, 7);
+    assert_eq!(room.roots.len(), 1);
+    let mut order = vec![];
+    names(&room.roots[0], &mut order);
+    assert_eq!(order, vec!["origin/a", "cache/b", "edge/c", "edge/d"]);
+    assert_eq!(room.roots[0].total_viewers, 7);
+    assert_eq!(room.roots[0].children[0].relays, 2);
+
+    //an upstream that is not a known node leaves the edges at the roots
+    let other = &channels["live/other"];
+    assert_eq!(other.viewers, 2);
+    assert_eq!(other.roots.len(), 2);
+    assert_eq!(other.roots[0].upstream.as_deref(), Some("10.0.0.9:9878"));
+
+    let only = topology::build(&nodes, Some("live/other"));
+    assert_eq!(only.len(), 1);
+}
+
+#[test]
+fn nodes_reporting_each_other_as_upstream_are_cut() {
+    let mut nodes = HashMap::new();
+    nodes.insert(
+        "cache/a".to_owned(),
+        node(
+            "cache",
+            "10.0.0.1:9888",
+            json!({ "live/room": { "viewers": 0, "upstream": "10.0.0.2:9888" } }),
+        ),
+    );
+    nodes.insert(
+        "cache/b".to_owned(),
+        node(
+            "cache",
+            "10.0.0.2:9888",
+            json!({ "live/room": { "viewers": 0, "upstream": "10.0.0.1:9888" } }),
+        ),
+    );
+    let channels = topology::build(&nodes, None);
+    let room = &channels["live/room"];
+    assert_eq!(room.roots.len(), 1);
+    let mut order = vec![];
+    names(&room.roots[0], &mut order);
+    assert_eq!(order, vec!["cache/a", "cache/b"]);
+}
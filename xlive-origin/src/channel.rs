@@ -1,21 +1,77 @@
+use crate::manager::{PublishConfig, PublishPolicy};
 use anyhow::Result;
-use bytes::Bytes;
 use core::message::{MediaKind, MediaPacket};
-use core::register::{Register, RegisterKind};
-use core::transport::{IncomingBroadcast, Message, OutgoingBroadcast};
-use std::time::{Duration, SystemTime};
-use tokio::net::UdpSocket;
+use core::metrics::{GaugeGuard, METRICS};
+use core::stats::StatsCollector;
+use core::transport::{
+    IncomingBroadcast, InitData, JoinResp, Message, OutgoingBroadcast, PublishResp, Publishing,
+    Responder, SwitchEvent, SwitchReason,
+};
+use core::{ChannelMessage, ManagerHandle, RegisterClient};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::{Stream, StreamExt, StreamMap};
+
+//gap between the last packet of a publisher and the first one of its successor
+const TIMESTAMP_GAP: u32 = 40;
+const SLATE_TICK: Duration = Duration::from_millis(10);
+
+type PublisherId = u64;
+//a publisher stream always ends with a disconnect, even when its connection is just dropped
+type PublisherStream = Pin<Box<dyn Stream<Item = Message> + Send>>;
+
 pub struct Channel {
     name: String,
     incoming: IncomingBroadcast,
     outgoing: OutgoingBroadcast,
+    manager_handle: ManagerHandle,
+    //the live publisher and its hot backups
+    publishers: StreamMap<PublisherId, PublisherStream>,
+    next_publisher_id: PublisherId,
+    publisher: Option<PublisherId>,
+    publisher_states: HashMap<PublisherId, PublisherState>,
+    policy: PublishPolicy,
+    stall_timeout: Option<Duration>,
     metadata: Option<MediaPacket>,
     video_seq_header: Option<MediaPacket>,
     audio_seq_header: Option<MediaPacket>,
     gop: Option<Vec<MediaPacket>>,
     closing: bool,
+    //another origin took the lease, the channel is not deleted from the register
+    revoked: bool,
+    resyncing: bool,
     full_gop: bool,
-    udp_socket: Option<UdpSocket>,
+    register: Option<RegisterClient>,
+    publisher_grace: Option<Duration>,
+    //set while the channel waits for its publisher to come back
+    orphaned_since: Option<Instant>,
+    slate: Option<Arc<Vec<MediaPacket>>>,
+    slate_player: Option<SlatePlayer>,
+    //timestamps of a rebound publisher are shifted to continue the channel timeline
+    timestamp_offset: u32,
+    rebase: bool,
+    last_timestamp: Option<u32>,
+    //of the live publisher
+    stats: StatsCollector,
+}
+
+//channel names revoked by the register, never resolves without a register
+async fn next_revocation(revocations: &mut Option<broadcast::Receiver<String>>) -> String {
+    if let Some(revocations) = revocations {
+        loop {
+            match revocations.recv().await {
+                Ok(channel_name) => return channel_name,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+    std::future::pending().await
 }
 
 impl Channel {
@@ -23,84 +79,381 @@ impl Channel {
         name: String,
         incoming: IncomingBroadcast,
         outgoing: OutgoingBroadcast,
+        manager_handle: ManagerHandle,
         full_gop: bool,
-        udp_socket: Option<UdpSocket>,
+        register: Option<RegisterClient>,
+        publish_config: PublishConfig,
     ) -> Self {
+        let policy = publish_config.policy(&name);
         Self {
             name,
             incoming,
             outgoing,
+            manager_handle,
+            publishers: StreamMap::new(),
+            next_publisher_id: 0,
+            publisher: None,
+            publisher_states: HashMap::new(),
+            policy,
+            stall_timeout: publish_config.stall_timeout,
             metadata: None,
             video_seq_header: None,
             audio_seq_header: None,
             gop: None,
             closing: false,
+            revoked: false,
+            resyncing: false,
             full_gop,
-            udp_socket,
+            register,
+            publisher_grace: publish_config.grace,
+            orphaned_since: None,
+            slate: publish_config.slate,
+            slate_player: None,
+            timestamp_offset: 0,
+            rebase: false,
+            last_timestamp: None,
+            stats: StatsCollector::new(),
         }
     }
 
     pub async fn run(mut self) {
-        let has_udp_client = self.udp_socket.is_some();
-        let buf: Bytes = Register {
-            kind: RegisterKind::Set,
-            channel_name: self.name.clone(),
-        }
-        .try_into()
-        .unwrap();
-
-        if has_udp_client {
-            _ = self.udp_socket.as_mut().unwrap().send(&buf).await;
-        }
-        let mut time = SystemTime::now();
+        let _channel = GaugeGuard::new(METRICS.channels.clone());
+        //the register client keeps the channel alive with its heartbeats
+        let mut revocations = self.register.as_ref().map(|register| {
+            register.set(&self.name);
+            register.revoked()
+        });
+        let mut heartbeat = time::interval(Duration::from_secs(1));
+        let mut slate_ticker = time::interval(SLATE_TICK);
 
         while !self.closing {
-            if let Some(message) = self.incoming.recv().await {
-                self.handle_message(message).await;
-            }
-            if time.elapsed().unwrap() > Duration::from_secs(1) {
-                _ = self.udp_socket.as_mut().unwrap().send(&buf).await;
-                time = SystemTime::now();
+            tokio::select! {
+                message = self.incoming.recv() => match message {
+                    Some(message) => self.handle_message(message),
+                    None => break,
+                },
+                Some((id, message)) = self.publishers.next(), if !self.publishers.is_empty() => {
+                    self.handle_publisher_message(id, message);
+                }
+                _ = heartbeat.tick() => {
+                    self.check_orphaned();
+                    self.check_stalled();
+                    self.report_stats();
+                }
+                _ = slate_ticker.tick(), if self.slate_player.is_some() => self.play_slate(),
+                channel_name = next_revocation(&mut revocations) => {
+                    if channel_name == self.name {
+                        self.revoke();
+                    }
+                }
             }
         }
 
         //channel will be drop
-        let buf: Bytes = Register {
-            kind: RegisterKind::Delete,
-            channel_name: self.name.clone(),
+        if let Some(register) = &self.register {
+            if !self.revoked {
+                register.delete(&self.name);
+            }
         }
-        .try_into()
-        .unwrap();
+    }
+
+    //players move to the new owner, they are dropped with the channel
+    fn revoke(&mut self) {
+        log::warn!(
+            "channel {} lease taken by another origin, kick the publishers",
+            self.name
+        );
+        self.revoked = true;
+        self.publishers.clear();
+        self.publisher_states.clear();
+        self.publisher = None;
+        self.close();
+    }
 
-        if has_udp_client {
-            _ = self.udp_socket.as_mut().unwrap().send(&buf).await;
+    fn handle_message(&mut self, message: Message) {
+        match message {
+            Message::Join(responder) => {
+                let resp = JoinResp {
+                    init_data: InitData {
+                        metadata: self.metadata.clone(),
+                        video_seq_header: self.video_seq_header.clone(),
+                        audio_seq_header: self.audio_seq_header.clone(),
+                        gop: self.gop.clone(),
+                    },
+                    watcher: self.outgoing.subscribe(),
+                };
+                if responder.send(resp).is_err() {
+                    log::error!("Failed to send join response");
+                }
+            }
+            Message::Publish(responder) => self.attach(responder),
+            Message::Disconnect => self.close(),
+            Message::Packet(_) | Message::PacketFromOrigin(_) | Message::Reconnected => {
+                unreachable!()
+            }
         }
     }
 
-    async fn handle_message(&mut self, message: Message) {
+    fn handle_publisher_message(&mut self, id: PublisherId, message: Message) {
         match message {
             Message::Packet(packet) => {
-                if let Err(e) = self.set_cache(&packet) {
-                    log::error!("Failed to set channel cache {}", e);
+                if let Some(register) = &self.register {
+                    register.add_ingress(packet.payload.len());
+                }
+                if let Some(state) = self.publisher_states.get_mut(&id) {
+                    state.update(&packet);
                 }
-                self.broadcast_packet(packet);
-            }
-            Message::InitData(responder) => {
-                let response = (
-                    self.metadata.clone(),
-                    self.video_seq_header.clone(),
-                    self.audio_seq_header.clone(),
-                    self.gop.clone(),
-                );
-                if responder.send(response).is_err() {
-                    log::error!("Failed to send init data");
+                if self.publisher == Some(id) {
+                    self.stats.update(&packet);
+                    self.publish(packet);
                 }
             }
-            Message::PacketFromOrigin(_) => unreachable!(),
             Message::Disconnect => {
-                self.closing = true;
+                self.publishers.remove(&id);
+                self.publisher_states.remove(&id);
+                if self.publisher == Some(id) {
+                    match self.pick_backup(false) {
+                        Some(backup) => self.switch(id, backup, SwitchReason::Disconnect),
+                        None => {
+                            self.publisher = None;
+                            self.orphan();
+                        }
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    //every publisher gets its own handle, dropping its stream kicks the connection
+    fn attach(&mut self, responder: Responder<PublishResp>) {
+        let backup = match (self.publisher, self.policy) {
+            (None, _) | (Some(_), PublishPolicy::Kick) => false,
+            (Some(_), PublishPolicy::Backup) => true,
+            (Some(_), PublishPolicy::Reject) => {
+                log::info!("channel {} already has a publisher, reject", self.name);
+                _ = responder.send(Err(format!(
+                    "channel {} already has a publisher",
+                    self.name
+                )));
+                return;
+            }
+        };
+        let (handle, incoming) = mpsc::unbounded_channel();
+        let (dropper, dropped) = oneshot::channel();
+        let publishing = Publishing {
+            handle,
+            backup,
+            dropped,
+        };
+        if responder.send(Ok(publishing)).is_err() {
+            log::error!("Failed to send publisher handle");
+            return;
+        }
+        let id = self.next_publisher_id;
+        self.next_publisher_id += 1;
+        let stream =
+            UnboundedReceiverStream::new(incoming).chain(tokio_stream::once(Message::Disconnect));
+        self.publishers.insert(id, Box::pin(stream));
+        self.publisher_states
+            .insert(id, PublisherState::new(dropper));
+
+        if backup {
+            log::info!("channel {} publisher {} is held as backup", self.name, id);
+        } else if let Some(old) = self.publisher {
+            self.publishers.remove(&old);
+            if let Some(state) = self.publisher_states.remove(&old) {
+                _ = state.dropper.send(format!(
+                    "kicked from channel {} by a new publisher",
+                    self.name
+                ));
+            }
+            self.switch(old, id, SwitchReason::Kick);
+        } else {
+            log::info!("channel {} publisher {} is live", self.name, id);
+            self.go_live(id);
+        }
+    }
+
+    //the oldest healthy backup, or the oldest one at all
+    fn pick_backup(&self, healthy_only: bool) -> Option<PublisherId> {
+        let backups = || {
+            self.publisher_states
+                .iter()
+                .filter(|(id, _)| self.publisher != Some(**id))
+        };
+        let healthy = backups()
+            .filter(|(_, state)| state.healthy(self.stall_timeout))
+            .map(|(id, _)| *id)
+            .min();
+        if healthy_only {
+            return healthy;
+        }
+        healthy.or_else(|| backups().map(|(id, _)| *id).min())
+    }
+
+    //a stalled publisher stays as a backup, it may come back
+    fn check_stalled(&mut self) {
+        let id = match self.publisher {
+            Some(id) => id,
+            None => return,
+        };
+        if self
+            .publisher_states
+            .get(&id)
+            .map_or(true, |state| state.healthy(self.stall_timeout))
+        {
+            return;
+        }
+        if let Some(backup) = self.pick_backup(true) {
+            self.switch(id, backup, SwitchReason::Stall);
+        }
+    }
+
+    fn switch(&mut self, from: PublisherId, to: PublisherId, reason: SwitchReason) {
+        log::info!(
+            "channel {} switch publisher {} to {} on {:?}",
+            self.name,
+            from,
+            to,
+            reason
+        );
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let event = SwitchEvent {
+            at,
+            from,
+            to,
+            reason,
+        };
+        _ = self
+            .manager_handle
+            .send(ChannelMessage::Switched((self.name.clone(), event)));
+        self.go_live(to);
+    }
+
+    fn go_live(&mut self, id: PublisherId) {
+        self.publisher = Some(id);
+        if self.orphaned_since.take().is_some() {
+            log::info!("channel {} publisher is back", self.name);
+        }
+        self.slate_player = None;
+        if let Some(last_timestamp) = self.last_timestamp {
+            self.resyncing = true;
+            self.rebase = true;
+            //headers of the new publisher go out before its first keyframe
+            let headers = self
+                .publisher_states
+                .get(&id)
+                .map(PublisherState::headers)
+                .unwrap_or_default();
+            for mut header in headers {
+                self.stats.update(&header);
+                if self.header_changed(&header) {
+                    header.timestamp = last_timestamp;
+                    self.cache_and_broadcast(header);
+                }
+            }
+        }
+    }
+
+    fn orphan(&mut self) {
+        let grace = match self.publisher_grace {
+            Some(grace) => grace,
+            None => return self.close(),
+        };
+        log::info!(
+            "channel {} publisher left, wait {:?} for it to come back",
+            self.name,
+            grace
+        );
+        self.orphaned_since = Some(Instant::now());
+        if let Some(slate) = &self.slate {
+            let base_timestamp = self
+                .last_timestamp
+                .map_or(0, |ts| ts.wrapping_add(TIMESTAMP_GAP));
+            self.slate_player = Some(SlatePlayer::new(slate.clone(), base_timestamp));
+        }
+    }
+
+    fn check_orphaned(&mut self) {
+        if let (Some(since), Some(grace)) = (self.orphaned_since, self.publisher_grace) {
+            if since.elapsed() >= grace {
+                log::info!("channel {} publisher did not come back", self.name);
+                self.close();
+            }
+        }
+    }
+
+    fn report_stats(&mut self) {
+        _ = self.manager_handle.send(ChannelMessage::Stats((
+            self.name.clone(),
+            self.stats.stats(),
+        )));
+    }
+
+    //stop taking messages and hand the channel back to the manager
+    fn close(&mut self) {
+        self.incoming.close();
+        _ = self
+            .manager_handle
+            .send(ChannelMessage::Release(self.name.clone()));
+        //requests raced with the close go back to the manager
+        while let Ok(message) = self.incoming.try_recv() {
+            match message {
+                Message::Join(responder) => {
+                    _ = self
+                        .manager_handle
+                        .send(ChannelMessage::Join((self.name.clone(), responder)));
+                }
+                Message::Publish(responder) => {
+                    _ = self.manager_handle.send(ChannelMessage::Create((
+                        self.name.clone(),
+                        "".to_owned(),
+                        responder,
+                    )));
+                }
+                _ => {}
             }
         }
+        self.closing = true;
+    }
+
+    fn play_slate(&mut self) {
+        let packets = match &mut self.slate_player {
+            Some(slate_player) => slate_player.due(),
+            None => return,
+        };
+        for packet in packets {
+            self.last_timestamp = Some(packet.timestamp);
+            self.cache_and_broadcast(packet);
+        }
+    }
+
+    fn publish(&mut self, mut packet: MediaPacket) {
+        if self.resyncing && !self.resync(&packet) {
+            return;
+        }
+        if let MediaKind::Video | MediaKind::Audio = packet.kind {
+            if self.rebase {
+                self.rebase = false;
+                let next = self
+                    .last_timestamp
+                    .map_or(0, |ts| ts.wrapping_add(TIMESTAMP_GAP));
+                self.timestamp_offset = next.wrapping_sub(packet.timestamp);
+            }
+            packet.timestamp = packet.timestamp.wrapping_add(self.timestamp_offset);
+            self.last_timestamp = Some(packet.timestamp);
+        }
+        self.cache_and_broadcast(packet);
+    }
+
+    fn cache_and_broadcast(&mut self, packet: MediaPacket) {
+        if let Err(e) = self.set_cache(&packet) {
+            log::error!("Failed to set channel cache {}", e);
+        }
+        self.broadcast_packet(packet);
     }
 
     fn broadcast_packet(&self, packet: MediaPacket) {
@@ -109,6 +462,38 @@ impl Channel {
         }
     }
 
+    //after a publisher change players resume on the next keyframe,
+    //metadata and seq headers are only sent again when they changed
+    fn resync(&mut self, packet: &MediaPacket) -> bool {
+        let resume = match packet.kind {
+            MediaKind::Video => packet.is_key_frame && !packet.is_seq_header,
+            //audio only publishers have no keyframe to wait for
+            MediaKind::Audio => !packet.is_seq_header && !self.publisher_has_video(),
+            MediaKind::Metadata => false,
+        };
+        if resume {
+            self.resyncing = false;
+            return true;
+        }
+        self.header_changed(packet)
+    }
+
+    fn publisher_has_video(&self) -> bool {
+        self.publisher
+            .and_then(|id| self.publisher_states.get(&id))
+            .is_some_and(|state| state.video_seq_header.is_some())
+    }
+
+    fn header_changed(&self, packet: &MediaPacket) -> bool {
+        let cached = match packet.kind {
+            MediaKind::Metadata => &self.metadata,
+            MediaKind::Video if packet.is_seq_header => &self.video_seq_header,
+            MediaKind::Audio if packet.is_seq_header => &self.audio_seq_header,
+            _ => return false,
+        };
+        !matches!(cached, Some(cached) if cached.payload == packet.payload)
+    }
+
     fn set_cache(&mut self, packet: &MediaPacket) -> Result<()> {
         match packet.kind {
             MediaKind::Metadata => {
@@ -141,3 +526,101 @@ impl Drop for Channel {
         log::info!("channel {} closed", self.name);
     }
 }
+
+//what the channel knows about a publisher, live or backup
+struct PublisherState {
+    last_packet: Instant,
+    metadata: Option<MediaPacket>,
+    video_seq_header: Option<MediaPacket>,
+    audio_seq_header: Option<MediaPacket>,
+    dropper: oneshot::Sender<String>,
+}
+
+impl PublisherState {
+    fn new(dropper: oneshot::Sender<String>) -> Self {
+        Self {
+            last_packet: Instant::now(),
+            metadata: None,
+            video_seq_header: None,
+            audio_seq_header: None,
+            dropper,
+        }
+    }
+
+    fn update(&mut self, packet: &MediaPacket) {
+        self.last_packet = Instant::now();
+        match packet.kind {
+            MediaKind::Metadata => self.metadata = Some(packet.clone()),
+            MediaKind::Video if packet.is_seq_header => {
+                self.video_seq_header = Some(packet.clone())
+            }
+            MediaKind::Audio if packet.is_seq_header => {
+                self.audio_seq_header = Some(packet.clone())
+            }
+            _ => {}
+        }
+    }
+
+    fn healthy(&self, stall_timeout: Option<Duration>) -> bool {
+        stall_timeout.map_or(true, |timeout| self.last_packet.elapsed() < timeout)
+    }
+
+    fn headers(&self) -> Vec<MediaPacket> {
+        let mut headers = vec![];
+        headers.extend(self.metadata.clone());
+        headers.extend(self.video_seq_header.clone());
+        headers.extend(self.audio_seq_header.clone());
+        headers
+    }
+}
+
+//loops a clip on the channel timeline while the publisher is away
+struct SlatePlayer {
+    packets: Arc<Vec<MediaPacket>>,
+    pos: usize,
+    loops: u32,
+    started: Instant,
+    base_timestamp: u32,
+    duration: u32,
+}
+
+impl SlatePlayer {
+    fn new(packets: Arc<Vec<MediaPacket>>, base_timestamp: u32) -> Self {
+        let first = packets.first().map_or(0, |p| p.timestamp);
+        let last = packets.last().map_or(0, |p| p.timestamp);
+        Self {
+            packets,
+            pos: 0,
+            loops: 0,
+            started: Instant::now(),
+            base_timestamp,
+            duration: last.wrapping_sub(first) + TIMESTAMP_GAP,
+        }
+    }
+
+    //packets that are due by now, seq headers are only sent in the first loop
+    fn due(&mut self) -> Vec<MediaPacket> {
+        let elapsed = self.started.elapsed().as_millis() as u32;
+        let first = self.packets[0].timestamp;
+        let mut due = vec![];
+        loop {
+            if self.pos == self.packets.len() {
+                self.pos = 0;
+                self.loops += 1;
+            }
+            let packet = &self.packets[self.pos];
+            let at = self.loops * self.duration + packet.timestamp.wrapping_sub(first);
+            if at > elapsed {
+                break;
+            }
+            self.pos += 1;
+            if self.loops > 0 && packet.is_seq_header {
+                continue;
+            }
+            let mut packet = packet.clone();
+            packet.timestamp = self.base_timestamp.wrapping_add(at);
+            due.push(packet);
+        }
+        due
+    }
+}
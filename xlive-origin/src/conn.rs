@@ -1,7 +1,8 @@
 use anyhow::{bail, Result};
 use bytes::Bytes;
 use core::message::{Kind, MediaPacket, MessageInitPayload, MessageInitPayloadKind, ProtoMessage};
-use core::transport::JoinResp;
+use core::metrics::{GaugeGuard, METRICS, XLIVE};
+use core::transport::{JoinResp, RelayGuard};
 use core::{AppName, ChannelMessage, Handle, ManagerHandle, Message};
 use futures::{SinkExt, StreamExt};
 use tokio::net::TcpStream;
@@ -10,7 +11,7 @@ use tokio_util::codec::{Framed, LengthDelimitedCodec};
 #[derive(Debug)]
 enum State {
     Init,
-    Publisher(AppName, Handle),
+    Publisher(AppName, Handle, oneshot::Receiver<String>),
     Player(AppName),
 }
 
@@ -19,6 +20,10 @@ pub struct Connection {
     state: State,
     frame: Framed<TcpStream, LengthDelimitedCodec>,
     manager_handle: ManagerHandle,
+    //counts the connection as a publisher or player while it is alive
+    gauge: Option<GaugeGuard>,
+    //every player of this protocol is a cache or edge
+    relay: Option<RelayGuard>,
 }
 
 impl Connection {
@@ -29,22 +34,26 @@ impl Connection {
             state: State::Init,
             id,
             manager_handle,
+            gauge: None,
+            relay: None,
         }
     }
 
-    async fn disconnected(&mut self, msg: &'static str) -> Result<()> {
-        if let State::Publisher(app_name, session) = &mut self.state {
+    async fn disconnected(&mut self, msg: impl Into<Bytes>) -> Result<()> {
+        //the channel decides whether to wait for the publisher to come back
+        if let State::Publisher(_, session, _) = &mut self.state {
             _ = session.send(Message::Disconnect);
-            _ = self
-                .manager_handle
-                .send(ChannelMessage::Release(app_name.to_owned()));
         }
-        let proto_message = ProtoMessage::new_proto_error(Bytes::from(msg));
+        let proto_message = ProtoMessage::new_proto_error(msg.into());
         self.frame.send(proto_message.into()).await?;
         Ok(())
     }
 
     async fn send(&mut self, packet: MediaPacket) -> Result<()> {
+        METRICS
+            .bytes_sent
+            .with_label_values(&[XLIVE])
+            .inc_by(packet.payload.len() as u64);
         let proto_message: ProtoMessage = packet.into();
         self.frame.send(proto_message.into()).await?;
         Ok(())
@@ -53,7 +62,7 @@ impl Connection {
     pub async fn run(&mut self) -> Result<()> {
         while let Some(Ok(data)) = self.frame.next().await {
             let message = ProtoMessage::try_from(data.freeze())?;
-            match &self.state {
+            match &mut self.state {
                 State::Init => match message.kind {
                     Kind::Init => {
                         let init_message = MessageInitPayload::try_from(message.payload)?;
@@ -73,14 +82,34 @@ impl Connection {
                                     self.disconnected("send channel message error").await?;
                                     bail!("send channel message error");
                                 }
-                                let session_sender = match response.await {
-                                    Ok(a) => a,
+                                let publishing = match response.await {
+                                    Ok(Ok(publishing)) => publishing,
+                                    Ok(Err(reason)) => {
+                                        log::info!("publisher rejected: {}", reason);
+                                        let proto_message =
+                                            ProtoMessage::new_proto_error(Bytes::from(reason));
+                                        self.frame.send(proto_message.into()).await?;
+                                        return Ok(());
+                                    }
                                     Err(_) => {
                                         self.disconnected("session_sender send error").await?;
                                         bail!("session_sender send error");
                                     }
                                 };
-                                self.state = State::Publisher(init_message.app_name, session_sender)
+                                let proto_message = if publishing.backup {
+                                    ProtoMessage::new_proto_backup()
+                                } else {
+                                    ProtoMessage::new_proto_ok()
+                                };
+                                self.frame.send(proto_message.into()).await?;
+                                self.state = State::Publisher(
+                                    init_message.app_name,
+                                    publishing.handle,
+                                    publishing.dropped,
+                                );
+                                self.gauge = Some(GaugeGuard::new(
+                                    METRICS.publishers.with_label_values(&[XLIVE]),
+                                ));
                             }
                             MessageInitPayloadKind::Player => {
                                 log::info!("got new player");
@@ -97,50 +126,43 @@ impl Connection {
                                     bail!("ChannelJoinFailed");
                                 }
 
+                                self.relay = Some(RelayGuard::new(
+                                    init_message.app_name.clone(),
+                                    self.manager_handle.clone(),
+                                ));
                                 self.state = State::Player(init_message.app_name);
+                                self.gauge = Some(GaugeGuard::new(
+                                    METRICS.players.with_label_values(&[XLIVE]),
+                                ));
                                 //respone join ok
                                 log::info!("send proto message ok");
                                 self.frame.send(ProtoMessage::new_proto_ok().into()).await?;
 
-                                if let Ok(JoinResp::Local(session_sender, mut session_receiver)) =
-                                    response.await
+                                if let Ok(JoinResp {
+                                    init_data,
+                                    watcher: mut session_receiver,
+                                }) = response.await
                                 {
-                                    let (request, response) = oneshot::channel();
-                                    if session_sender.send(Message::InitData(request)).is_err() {
-                                        self.disconnected("session_sender send failed").await?;
-                                        return Ok(());
-                                    };
-                                    if let Ok((meta, video, audio, gop)) = response.await {
-                                        if let Some(m) = meta {
-                                            self.send(m).await?;
-                                        }
-                                        if let Some(v) = video {
-                                            self.send(v).await?;
-                                        }
-                                        if let Some(a) = audio {
-                                            self.send(a).await?;
-                                        }
-                                        if let Some(a) = gop {
-                                            for p in a {
-                                                self.send(p).await?;
-                                            }
-                                        }
+                                    for packet in init_data.into_packets() {
+                                        self.send(packet).await?;
                                     }
                                     log::info!("send proto init media packet finish");
 
                                     loop {
                                         use tokio::sync::broadcast::error::RecvError;
                                         match session_receiver.recv().await {
-                                            Ok(data) => {
-                                                let proto_message: ProtoMessage = data.into();
-                                                self.frame.send(proto_message.into()).await?;
-                                            }
+                                            Ok(data) => self.send(data).await?,
                                             Err(RecvError::Closed) => {
                                                 self.disconnected("session_receiver is closed")
                                                     .await?;
                                                 break;
                                             }
-                                            Err(_) => {}
+                                            Err(RecvError::Lagged(n)) => {
+                                                METRICS
+                                                    .lagged_packets
+                                                    .with_label_values(&[XLIVE])
+                                                    .inc_by(n);
+                                            }
                                         }
                                     }
                                     log::info!("session_receiver finish");
@@ -152,15 +174,24 @@ impl Connection {
                     }
                     _ => bail!("first message kind mustbe init"),
                 },
-                State::Publisher(_, handle) => match message.kind {
+                State::Publisher(_, handle, dropped) => match message.kind {
                     Kind::Errors | Kind::Init | Kind::Ok => {
                         bail!("unreachable")
                     }
                     Kind::Media => {
+                        METRICS
+                            .bytes_received
+                            .with_label_values(&[XLIVE])
+                            .inc_by(message.payload.len() as u64);
                         let media_packet: MediaPacket = message.payload.try_into()?;
-                        handle
-                            .send(Message::Packet(media_packet))
-                            .map_err(|_| anyhow::anyhow!("handle send message err "))?;
+                        //the channel dropped this publisher, e.g. for another one
+                        if handle.send(Message::Packet(media_packet)).is_err() {
+                            let reason = dropped
+                                .try_recv()
+                                .unwrap_or_else(|_| "replaced by another publisher".to_owned());
+                            self.disconnected(reason.clone()).await?;
+                            bail!("publisher dropped: {}", reason);
+                        }
                     }
                 },
                 State::Player(_) => unreachable!(),
@@ -176,7 +207,7 @@ impl Drop for Connection {
     fn drop(&mut self) {
         let (app_name, role) = match &self.state {
             State::Init => return,
-            State::Publisher(app_name, _) => (app_name, "publisher"),
+            State::Publisher(app_name, _, _) => (app_name, "publisher"),
             State::Player(app_name) => (app_name, "player"),
         };
         log::info!(
@@ -1,18 +1,54 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use bytes::Bytes;
 use chrono::Local;
-use core::ManagerHandle;
+use core::register::Role;
+use core::register_client::RegisterClientConfig;
+use core::{flv, ManagerHandle, RegisterClient};
+use std::fs;
 use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
 use structopt::StructOpt;
 use tokio::net::{TcpListener, TcpStream};
+use xlive_origin::conn::Connection;
+use xlive_origin::manager::{Manager, PublishConfig, PublishPolicy};
 #[cfg(feature = "monitor")]
 use xlive_origin::monitor::Service;
-use xlive_origin::{conn::Connection, manager::Manager};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "xlive-origin")]
 struct Opt {
+    /// comma separated register addresses, lookups fail over to the next one
     #[structopt(short = "r", long = "register", default_value = "127.0.0.1:9336")]
     register: String,
+
+    /// id of this origin in the register, defaults to the advertised address
+    #[structopt(long = "node-id", default_value = "")]
+    node_id: String,
+
+    /// tcp address edges and caches use to reach this origin, like 10.0.0.5:9878
+    #[structopt(long = "advertise", default_value = "")]
+    advertise: String,
+
+    /// seconds to keep a channel after its publisher dropped, closed at once when not set
+    #[structopt(long = "publisher-grace")]
+    publisher_grace: Option<u64>,
+
+    /// flv file looped to the players while the publisher is away
+    #[structopt(long = "slate", default_value = "")]
+    slate: String,
+
+    /// what to do with a second publisher of a live channel: reject, kick or backup
+    #[structopt(long = "publish-policy", default_value = "kick")]
+    publish_policy: PublishPolicy,
+
+    /// seconds without packets before a live publisher is replaced by a backup, 0 never
+    #[structopt(long = "publisher-stall", default_value = "3")]
+    publisher_stall: u64,
+
+    /// comma separated per app overrides of the publish policy, like live=reject,event=backup
+    #[structopt(long = "app-publish-policy", default_value = "")]
+    app_publish_policy: String,
 }
 
 #[tokio::main]
@@ -37,9 +73,48 @@ async fn main() -> Result<()> {
     let register = if opt.register.is_empty() {
         None
     } else {
-        Some(opt.register)
+        let node_id = if opt.node_id.is_empty() {
+            opt.advertise.clone()
+        } else {
+            opt.node_id
+        };
+        let mut config = RegisterClientConfig::new(&opt.register);
+        config.node_id = node_id;
+        config.advertise = opt.advertise;
+        config.report_load = true;
+        config.role = Some(Role::Origin);
+        #[cfg(feature = "monitor")]
+        {
+            config.monitor_addr = xlive_origin::monitor::MONITOR_ADDR.to_owned();
+        }
+        Some(RegisterClient::connect(config).await?)
+    };
+    let mut publish_config = PublishConfig {
+        grace: opt
+            .publisher_grace
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs),
+        ..Default::default()
     };
-    let manager = Manager::new(true, register);
+    if !opt.slate.is_empty() {
+        let packets = flv::read_media_packets(Bytes::from(fs::read(&opt.slate)?))?;
+        if packets.is_empty() {
+            bail!("slate {} has no media packet", opt.slate);
+        }
+        publish_config.slate = Some(Arc::new(packets));
+        if publish_config.grace.is_none() {
+            log::warn!(
+                "slate {} is never played without --publisher-grace",
+                opt.slate
+            );
+        }
+    }
+    publish_config.policy = opt.publish_policy;
+    if opt.publisher_stall > 0 {
+        publish_config.stall_timeout = Some(Duration::from_secs(opt.publisher_stall));
+    }
+    publish_config.app_policies = PublishConfig::parse_app_policies(&opt.app_publish_policy)?;
+    let manager = Manager::new(true, register, publish_config);
     let manager_handle = manager.handle();
     tokio::spawn(manager.run());
 
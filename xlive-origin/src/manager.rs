@@ -1,24 +1,107 @@
 use crate::channel::Channel;
 use anyhow::{bail, Result};
-use core::transport::JoinResp;
+use core::message::MediaPacket;
+use core::stats::ChannelStats;
 use core::transport::{
-    ChannelMessage, ChannelReceiver, Handle, ManagerHandle, OutgoingBroadcast, Trigger,
+    ChannelInfo, ChannelMessage, ChannelReceiver, Handle, ManagerHandle, Message,
+    OutgoingBroadcast, Relays, SwitchEvent, Trigger,
 };
-use core::{AppName, Event};
-use std::{collections::HashMap, sync::Arc};
-use tokio::net::UdpSocket;
+use core::{AppName, Event, RegisterClient};
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::error::SendError;
 use tokio::sync::{broadcast, mpsc, RwLock};
+
+const MAX_SWITCHES: usize = 16;
+
+//what happens when a second publisher shows up for a live channel
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PublishPolicy {
+    //the newcomer is refused
+    Reject,
+    //the newcomer replaces the current publisher
+    #[default]
+    Kick,
+    //the newcomer waits as a hot backup and goes live when the current publisher leaves
+    Backup,
+}
+
+impl FromStr for PublishPolicy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "reject" => Self::Reject,
+            "kick" => Self::Kick,
+            "backup" => Self::Backup,
+            _ => bail!(
+                "unknown publish policy {}, expect reject, kick or backup",
+                s
+            ),
+        })
+    }
+}
+
+//how a channel treats its publishers
+#[derive(Clone, Default)]
+pub struct PublishConfig {
+    //time to wait for a dropped publisher to come back before the channel closes
+    pub grace: Option<Duration>,
+    //clip looped to the players while the publisher is away
+    pub slate: Option<Arc<Vec<MediaPacket>>>,
+    pub policy: PublishPolicy,
+    //a live publisher silent this long is replaced by a healthy backup
+    pub stall_timeout: Option<Duration>,
+    //per app overrides of the policy
+    pub app_policies: HashMap<AppName, PublishPolicy>,
+}
+
+impl PublishConfig {
+    pub fn policy(&self, app_name: &str) -> PublishPolicy {
+        self.app_policies
+            .get(app_name)
+            .copied()
+            .unwrap_or(self.policy)
+    }
+
+    //parse overrides like `live=reject,event=backup`
+    pub fn parse_app_policies(s: &str) -> Result<HashMap<AppName, PublishPolicy>> {
+        let mut app_policies = HashMap::new();
+        for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            match item.split_once('=') {
+                Some((app_name, policy)) => {
+                    app_policies.insert(app_name.trim().to_owned(), policy.trim().parse()?);
+                }
+                None => bail!("invalid app publish policy {}, expect app=policy", item),
+            }
+        }
+        Ok(app_policies)
+    }
+}
+
 pub struct Manager {
     handle: ManagerHandle,
     incoming: ChannelReceiver,
     channels: Arc<RwLock<HashMap<AppName, (Handle, OutgoingBroadcast)>>>,
     triggers: Arc<RwLock<HashMap<Event, Vec<Trigger>>>>,
+    //latest publisher switches of every channel
+    switches: HashMap<AppName, VecDeque<SwitchEvent>>,
+    //latest stats reported by every channel
+    stats: HashMap<AppName, ChannelStats>,
+    //caches and edges pulling every channel, the other subscribers are local
+    relays: Relays,
     full_gop: bool,
-    register_addr: Option<String>,
+    register: Option<RegisterClient>,
+    publish_config: PublishConfig,
 }
 
 impl Manager {
-    pub fn new(full_gop: bool, register_addr: Option<String>) -> Self {
+    pub fn new(
+        full_gop: bool,
+        register: Option<RegisterClient>,
+        publish_config: PublishConfig,
+    ) -> Self {
         let (handle, incoming) = mpsc::unbounded_channel();
         let channels = Arc::new(RwLock::new(HashMap::new()));
         let triggers = Arc::new(RwLock::new(HashMap::new()));
@@ -28,8 +111,12 @@ impl Manager {
             incoming,
             channels,
             triggers,
+            switches: HashMap::new(),
+            stats: HashMap::new(),
+            relays: Relays::default(),
             full_gop,
-            register_addr,
+            register,
+            publish_config,
         }
     }
 
@@ -39,10 +126,20 @@ impl Manager {
 
     async fn process_message(&mut self, message: ChannelMessage) -> Result<()> {
         match message {
-            ChannelMessage::Create((name, _, responder)) => {
+            ChannelMessage::Create((name, _, mut responder)) => {
+                let mut sessions = self.channels.write().await;
+                if let Some((handle, _)) = sessions.get(&name) {
+                    //the channel may be waiting for its publisher to come back
+                    match handle.send(Message::Publish(responder)) {
+                        Ok(_) => return Ok(()),
+                        //the channel is closing, replace it with a new one
+                        Err(SendError(Message::Publish(r))) => responder = r,
+                        Err(_) => unreachable!(),
+                    }
+                }
+
                 let (handle, incoming) = mpsc::unbounded_channel();
                 let (outgoing, _watcher) = broadcast::channel(64);
-                let mut sessions = self.channels.write().await;
                 sessions.insert(name.clone(), (handle.clone(), outgoing.clone()));
 
                 let triggers = self.triggers.read().await;
@@ -55,39 +152,46 @@ impl Manager {
                 let full_gop = self.full_gop;
                 let name_copy = name.clone();
 
-                let mut udp_socket: Option<UdpSocket> = None;
-                if self.register_addr.is_some() {
-                    let udp = UdpSocket::bind("0.0.0.0:9878").await.unwrap();
-                    udp.connect(self.register_addr.as_ref().unwrap())
-                        .await
-                        .unwrap();
-                    udp_socket = Some(udp);
-                }
-
+                let register = self.register.clone();
+                let manager_handle = self.handle.clone();
+                let publish_config = self.publish_config.clone();
                 tokio::spawn(async move {
-                    Channel::new(name_copy, incoming, outgoing, full_gop, udp_socket)
-                        .run()
-                        .await;
+                    Channel::new(
+                        name_copy,
+                        incoming,
+                        outgoing,
+                        manager_handle,
+                        full_gop,
+                        register,
+                        publish_config,
+                    )
+                    .run()
+                    .await;
                 });
 
-                if responder.send(handle).is_err() {
-                    bail!("Failed to send response");
+                if handle.send(Message::Publish(responder)).is_err() {
+                    bail!("Failed to publish to channel {}", name);
                 }
             }
             ChannelMessage::Join((name, responder)) => {
                 let sessions = self.channels.read().await;
-                if let Some((handle, watcher)) = sessions.get(&name) {
-                    if responder
-                        .send(JoinResp::Local(handle.clone(), watcher.subscribe()))
-                        .is_err()
-                    {
-                        bail!("Failed to send response");
+                if let Some((handle, _)) = sessions.get(&name) {
+                    if handle.send(Message::Join(responder)).is_err() {
+                        bail!("Failed to join channel {}", name);
                     }
                 }
             }
             ChannelMessage::Release(name) => {
+                //a channel closes its handle before it asks for release,
+                //so a late release never removes a newer channel of the same name
                 let mut sessions = self.channels.write().await;
-                sessions.remove(&name);
+                if let Some((handle, _)) = sessions.get(&name) {
+                    if handle.is_closed() {
+                        sessions.remove(&name);
+                        self.switches.remove(&name);
+                        self.stats.remove(&name);
+                    }
+                }
             }
             ChannelMessage::RegisterTrigger(event, trigger) => {
                 log::debug!("Registering trigger for {}", event);
@@ -98,10 +202,33 @@ impl Manager {
                 let sessions = self.channels.read().await;
                 let mut info = HashMap::new();
                 for (k, v) in sessions.iter() {
-                    info.insert(k.to_owned(), v.1.receiver_count());
+                    let channel_info = ChannelInfo {
+                        subscribers: v.1.receiver_count(),
+                        relays: self.relays.get(k),
+                        switches: self
+                            .switches
+                            .get(k)
+                            .map(|switches| switches.iter().cloned().collect())
+                            .unwrap_or_default(),
+                        stats: self.stats.get(k).cloned(),
+                        ..Default::default()
+                    };
+                    info.insert(k.to_owned(), channel_info);
                 }
                 _ = responder.send(info);
             }
+            ChannelMessage::Switched((name, event)) => {
+                let switches = self.switches.entry(name).or_default();
+                if switches.len() == MAX_SWITCHES {
+                    switches.pop_front();
+                }
+                switches.push_back(event);
+            }
+            ChannelMessage::Stats((name, stats)) => {
+                self.stats.insert(name, stats);
+            }
+            ChannelMessage::Upstream(_) => unreachable!(),
+            ChannelMessage::Relay((name, started)) => self.relays.update(name, started),
         }
 
         Ok(())
@@ -0,0 +1,171 @@
+mod common;
+
+use bytes::Bytes;
+use common::block_on;
+use core::message::{MediaKind, MediaPacket};
+use core::transport::{
+    ChannelMessage, JoinResp, ManagerHandle, Message, Publishing, SwitchReason, Watcher,
+};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time;
+use xlive_origin::manager::{Manager, PublishConfig, PublishPolicy};
+
+fn packet(kind: MediaKind, timestamp: u32, is_key_frame: bool) -> MediaPacket {
+    MediaPacket {
+        kind,
+        is_seq_header: false,
+        is_key_frame,
+        timestamp,
+        payload: Bytes::from(timestamp.to_be_bytes().to_vec()),
+    }
+}
+
+fn video(timestamp: u32, is_key_frame: bool) -> MediaPacket {
+    packet(MediaKind::Video, timestamp, is_key_frame)
+}
+
+fn audio(timestamp: u32) -> MediaPacket {
+    packet(MediaKind::Audio, timestamp, false)
+}
+
+async fn publish(manager: &ManagerHandle, name: &str) -> Publishing {
+    let (request, response) = oneshot::channel();
+    let create = ChannelMessage::Create((name.to_owned(), "".to_owned(), request));
+    assert!(manager.send(create).is_ok());
+    response.await.unwrap().unwrap()
+}
+
+async fn join(manager: &ManagerHandle, name: &str) -> JoinResp {
+    let (request, response) = oneshot::channel();
+    assert!(manager
+        .send(ChannelMessage::Join((name.to_owned(), request)))
+        .is_ok());
+    response.await.unwrap()
+}
+
+async fn recv(watcher: &mut Watcher) -> MediaPacket {
+    time::timeout(Duration::from_secs(3), watcher.recv())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+fn send(publisher: &Publishing, packet: MediaPacket) {
+    assert!(publisher.handle.send(Message::Packet(packet)).is_ok());
+}
+
+//publishers are read concurrently, wait for the switch before the backup sends on
+async fn switched(manager: &ManagerHandle, name: &str) -> SwitchReason {
+    for _ in 0..300 {
+        let (request, response) = oneshot::channel();
+        assert!(manager.send(ChannelMessage::Snapshot(request)).is_ok());
+        let switches = response.await.unwrap().remove(name).unwrap().switches;
+        if let Some(switch) = switches.last() {
+            return switch.reason;
+        }
+        time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("channel {} did not switch", name);
+}
+
+fn manager(stall_timeout: Option<Duration>) -> ManagerHandle {
+    let config = PublishConfig {
+        policy: PublishPolicy::Backup,
+        stall_timeout,
+        ..Default::default()
+    };
+    let manager = Manager::new(true, None, config);
+    let handle = manager.handle();
+    tokio::spawn(manager.run());
+    handle
+}
+
+#[test]
+fn backup_goes_live_when_the_publisher_leaves() {
+    block_on(async {
+        let handle = manager(None);
+        let first = publish(&handle, "live").await;
+        assert!(!first.backup);
+        send(&first, video(0, true));
+        send(&first, video(40, false));
+        let mut resp = join(&handle, "live").await;
+
+        let second = publish(&handle, "live").await;
+        assert!(second.backup);
+        //held back while the first one is live
+        send(&second, video(1000, true));
+        assert!(
+            time::timeout(Duration::from_millis(100), resp.watcher.recv())
+                .await
+                .is_err()
+        );
+
+        drop(first);
+        assert!(matches!(
+            switched(&handle, "live").await,
+            SwitchReason::Disconnect
+        ));
+        //players resume on the next keyframe, after the last packet
+        send(&second, video(1040, false));
+        send(&second, video(1080, true));
+        let packet = recv(&mut resp.watcher).await;
+        assert!(packet.is_key_frame);
+        assert_eq!(packet.timestamp, 80);
+    });
+}
+
+#[test]
+fn backup_takes_over_on_stall() {
+    block_on(async {
+        let handle = manager(Some(Duration::from_millis(300)));
+        let first = publish(&handle, "live").await;
+        send(&first, video(0, true));
+        let mut resp = join(&handle, "live").await;
+
+        let second = publish(&handle, "live").await;
+        assert!(second.backup);
+        let sending = tokio::spawn(async move {
+            for i in 0..60 {
+                send(&second, video(1000 + i * 40, i % 5 == 0));
+                time::sleep(Duration::from_millis(50)).await;
+            }
+            second
+        });
+
+        //the first one stays connected, but sends nothing
+        let packet = recv(&mut resp.watcher).await;
+        assert!(packet.is_key_frame);
+        assert!(matches!(
+            switched(&handle, "live").await,
+            SwitchReason::Stall
+        ));
+        send(&first, video(40, false));
+        sending.await.unwrap();
+    });
+}
+
+#[test]
+fn audio_only_backup_resumes_without_a_keyframe() {
+    block_on(async {
+        let handle = manager(None);
+        let header = MediaPacket {
+            is_seq_header: true,
+            ..audio(0)
+        };
+        let first = publish(&handle, "live").await;
+        send(&first, header.clone());
+        send(&first, audio(0));
+        send(&first, audio(20));
+        let mut resp = join(&handle, "live").await;
+
+        let second = publish(&handle, "live").await;
+        send(&second, header);
+        drop(first);
+        switched(&handle, "live").await;
+        send(&second, audio(500));
+        send(&second, audio(520));
+        assert_eq!(recv(&mut resp.watcher).await.timestamp, 60);
+        assert_eq!(recv(&mut resp.watcher).await.timestamp, 80);
+    });
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use bytes::Bytes;
+use common::block_on;
+use core::message::{MediaKind, MediaPacket};
+use core::transport::{ChannelMessage, JoinResp, ManagerHandle, Message, Publishing, Watcher};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::oneshot;
+use tokio::time;
+use xlive_origin::manager::{Manager, PublishConfig};
+
+fn video(timestamp: u32, is_key_frame: bool) -> MediaPacket {
+    MediaPacket {
+        kind: MediaKind::Video,
+        is_seq_header: false,
+        is_key_frame,
+        timestamp,
+        payload: Bytes::from(timestamp.to_be_bytes().to_vec()),
+    }
+}
+
+async fn publish(manager: &ManagerHandle, name: &str) -> Publishing {
+    let (request, response) = oneshot::channel();
+    let create = ChannelMessage::Create((name.to_owned(), "".to_owned(), request));
+    assert!(manager.send(create).is_ok());
+    response.await.unwrap().unwrap()
+}
+
+async fn join(manager: &ManagerHandle, name: &str) -> JoinResp {
+    let (request, response) = oneshot::channel();
+    assert!(manager
+        .send(ChannelMessage::Join((name.to_owned(), request)))
+        .is_ok());
+    response.await.unwrap()
+}
+
+async fn recv(watcher: &mut Watcher) -> Result<u32, RecvError> {
+    let packet = time::timeout(Duration::from_secs(3), watcher.recv())
+        .await
+        .unwrap()?;
+    Ok(packet.timestamp)
+}
+
+fn send(publisher: &Publishing, packet: MediaPacket) {
+    assert!(publisher.handle.send(Message::Packet(packet)).is_ok());
+}
+
+#[test]
+fn publisher_back_within_grace_continues_the_timeline() {
+    block_on(async {
+        let config = PublishConfig {
+            grace: Some(Duration::from_secs(2)),
+            ..Default::default()
+        };
+        let manager = Manager::new(true, None, config);
+        let handle = manager.handle();
+        tokio::spawn(manager.run());
+
+        let publisher = publish(&handle, "live").await;
+        for i in 0..3 {
+            send(&publisher, video(i * 40, i == 0));
+        }
+        let mut resp = join(&handle, "live").await;
+        assert_eq!(resp.init_data.gop.unwrap().len(), 3);
+        drop(publisher);
+        time::sleep(Duration::from_millis(100)).await;
+
+        let publisher = publish(&handle, "live").await;
+        assert!(!publisher.backup);
+        //its own timeline, shifted to go on after the last packet
+        send(&publisher, video(5000, true));
+        send(&publisher, video(5040, false));
+        assert_eq!(recv(&mut resp.watcher).await, Ok(120));
+        assert_eq!(recv(&mut resp.watcher).await, Ok(160));
+    });
+}
+
+#[test]
+fn slate_plays_until_the_grace_ends() {
+    block_on(async {
+        let config = PublishConfig {
+            grace: Some(Duration::from_millis(500)),
+            slate: Some(Arc::new(vec![video(0, true), video(40, false)])),
+            ..Default::default()
+        };
+        let manager = Manager::new(true, None, config);
+        let handle = manager.handle();
+        tokio::spawn(manager.run());
+
+        let publisher = publish(&handle, "live").await;
+        for i in 0..3 {
+            send(&publisher, video(i * 40, i == 0));
+        }
+        let mut resp = join(&handle, "live").await;
+        drop(publisher);
+
+        //looped on the channel timeline
+        for timestamp in [120, 160, 200, 240] {
+            assert_eq!(recv(&mut resp.watcher).await, Ok(timestamp));
+        }
+        let closed = loop {
+            match recv(&mut resp.watcher).await {
+                Ok(_) => continue,
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(closed, RecvError::Closed);
+    });
+}
+
+#[test]
+fn channel_without_grace_closes_with_its_publisher() {
+    block_on(async {
+        let manager = Manager::new(true, None, PublishConfig::default());
+        let handle = manager.handle();
+        tokio::spawn(manager.run());
+
+        let publisher = publish(&handle, "live").await;
+        send(&publisher, video(0, true));
+        let mut resp = join(&handle, "live").await;
+        drop(publisher);
+        assert_eq!(recv(&mut resp.watcher).await, Err(RecvError::Closed));
+    });
+}
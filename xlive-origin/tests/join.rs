@@ -0,0 +1,103 @@
+mod common;
+
+use bytes::Bytes;
+use common::block_on;
+use core::message::{MediaKind, MediaPacket};
+use core::transport::{ChannelMessage, JoinResp, ManagerHandle, Message, Publishing};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time;
+use xlive_origin::manager::{Manager, PublishConfig};
+
+fn video(timestamp: u32, is_key_frame: bool) -> MediaPacket {
+    MediaPacket {
+        kind: MediaKind::Video,
+        is_seq_header: false,
+        is_key_frame,
+        timestamp,
+        payload: Bytes::from(timestamp.to_be_bytes().to_vec()),
+    }
+}
+
+async fn publish(manager: &ManagerHandle, name: &str) -> Publishing {
+    let (request, response) = oneshot::channel();
+    let create = ChannelMessage::Create((name.to_owned(), "".to_owned(), request));
+    assert!(manager.send(create).is_ok());
+    response.await.unwrap().unwrap()
+}
+
+async fn join(manager: &ManagerHandle, name: &str) -> JoinResp {
+    let (request, response) = oneshot::channel();
+    assert!(manager
+        .send(ChannelMessage::Join((name.to_owned(), request)))
+        .is_ok());
+    response.await.unwrap()
+}
+
+#[test]
+fn join_during_publish_gets_gop_and_live_without_gaps() {
+    block_on(async {
+        let manager = Manager::new(true, None, PublishConfig::default());
+        let handle = manager.handle();
+        tokio::spawn(manager.run());
+
+        let publisher = publish(&handle, "live").await;
+        //a keyframe every 10 frames, 40ms apart in stream time
+        let sending = tokio::spawn(async move {
+            for i in 0..200 {
+                let packet = video(i * 40, i % 10 == 0);
+                assert!(publisher.handle.send(Message::Packet(packet)).is_ok());
+                time::sleep(Duration::from_millis(1)).await;
+            }
+            publisher
+        });
+        time::sleep(Duration::from_millis(50)).await;
+
+        let JoinResp {
+            init_data,
+            mut watcher,
+        } = join(&handle, "live").await;
+        let gop = init_data.gop.unwrap();
+        assert!(gop[0].is_key_frame);
+        let mut timestamps: Vec<u32> = gop.iter().map(|p| p.timestamp).collect();
+        while timestamps.last() != Some(&(199 * 40)) {
+            let packet = time::timeout(Duration::from_secs(3), watcher.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            timestamps.push(packet.timestamp);
+        }
+        let expected: Vec<u32> = (timestamps[0] / 40..200).map(|i| i * 40).collect();
+        assert_eq!(timestamps, expected);
+        //joined mid stream, not from the start
+        assert!(timestamps[0] > 0);
+        sending.await.unwrap();
+    });
+}
+
+#[test]
+fn join_before_the_first_keyframe_gets_the_whole_stream() {
+    block_on(async {
+        let manager = Manager::new(true, None, PublishConfig::default());
+        let handle = manager.handle();
+        tokio::spawn(manager.run());
+
+        let publisher = publish(&handle, "live").await;
+        let JoinResp {
+            init_data,
+            mut watcher,
+        } = join(&handle, "live").await;
+        assert!(init_data.into_packets().is_empty());
+        for i in 0..5 {
+            let packet = video(i * 40, i == 0);
+            assert!(publisher.handle.send(Message::Packet(packet)).is_ok());
+        }
+        for i in 0..5 {
+            let packet = time::timeout(Duration::from_secs(3), watcher.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(packet.timestamp, i * 40);
+        }
+    });
+}
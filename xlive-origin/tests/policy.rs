@@ -0,0 +1,121 @@
+mod common;
+
+use bytes::Bytes;
+use common::block_on;
+use core::message::{MediaKind, MediaPacket};
+use core::transport::{
+    ChannelMessage, JoinResp, ManagerHandle, Message, PublishResp, SwitchReason,
+};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time;
+use xlive_origin::manager::{Manager, PublishConfig, PublishPolicy};
+
+fn video(timestamp: u32, is_key_frame: bool) -> MediaPacket {
+    MediaPacket {
+        kind: MediaKind::Video,
+        is_seq_header: false,
+        is_key_frame,
+        timestamp,
+        payload: Bytes::from(timestamp.to_be_bytes().to_vec()),
+    }
+}
+
+async fn publish(manager: &ManagerHandle, name: &str) -> PublishResp {
+    let (request, response) = oneshot::channel();
+    let create = ChannelMessage::Create((name.to_owned(), "".to_owned(), request));
+    assert!(manager.send(create).is_ok());
+    response.await.unwrap()
+}
+
+async fn join(manager: &ManagerHandle, name: &str) -> JoinResp {
+    let (request, response) = oneshot::channel();
+    assert!(manager
+        .send(ChannelMessage::Join((name.to_owned(), request)))
+        .is_ok());
+    response.await.unwrap()
+}
+
+fn manager(app_policies: &str) -> ManagerHandle {
+    let config = PublishConfig {
+        app_policies: PublishConfig::parse_app_policies(app_policies).unwrap(),
+        ..Default::default()
+    };
+    let manager = Manager::new(true, None, config);
+    let handle = manager.handle();
+    tokio::spawn(manager.run());
+    handle
+}
+
+#[test]
+fn parse_app_policies() {
+    let config = PublishConfig {
+        app_policies: PublishConfig::parse_app_policies(" live=reject, event = backup,").unwrap(),
+        ..Default::default()
+    };
+    assert_eq!(config.policy("live"), PublishPolicy::Reject);
+    assert_eq!(config.policy("event"), PublishPolicy::Backup);
+    assert_eq!(config.policy("other"), PublishPolicy::Kick);
+    assert!(PublishConfig::parse_app_policies("").unwrap().is_empty());
+
+    for invalid in ["live", "live=drop", "live=reject,event"] {
+        assert!(PublishConfig::parse_app_policies(invalid).is_err());
+    }
+}
+
+#[test]
+fn reject_keeps_the_current_publisher() {
+    block_on(async {
+        let handle = manager("live=reject");
+        let first = publish(&handle, "live").await.unwrap();
+        let reason = match publish(&handle, "live").await {
+            Ok(_) => panic!("second publisher accepted"),
+            Err(reason) => reason,
+        };
+        assert!(reason.contains("already has a publisher"));
+
+        let mut resp = join(&handle, "live").await;
+        assert!(first.handle.send(Message::Packet(video(0, true))).is_ok());
+        let packet = time::timeout(Duration::from_secs(3), resp.watcher.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(packet.timestamp, 0);
+    });
+}
+
+#[test]
+fn kick_drops_the_current_publisher_with_a_reason() {
+    block_on(async {
+        let handle = manager("");
+        let mut first = publish(&handle, "live").await.unwrap();
+        assert!(first.handle.send(Message::Packet(video(0, true))).is_ok());
+        let mut resp = join(&handle, "live").await;
+
+        let second = publish(&handle, "live").await.unwrap();
+        assert!(!second.backup);
+        let reason = time::timeout(Duration::from_secs(3), &mut first.dropped)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(reason.contains("kicked"));
+        assert!(first
+            .handle
+            .send(Message::Packet(video(40, false)))
+            .is_err());
+
+        //the new publisher goes on with its first keyframe
+        assert!(second.handle.send(Message::Packet(video(0, true))).is_ok());
+        let packet = time::timeout(Duration::from_secs(3), resp.watcher.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(packet.is_key_frame && packet.timestamp > 0);
+
+        let (request, response) = oneshot::channel();
+        assert!(handle.send(ChannelMessage::Snapshot(request)).is_ok());
+        let switches = response.await.unwrap()["live"].switches.clone();
+        assert_eq!(switches.len(), 1);
+        assert!(matches!(switches[0].reason, SwitchReason::Kick));
+    });
+}
@@ -0,0 +1,32 @@
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::UnboundedReceiver;
+use xcore::register::Register;
+
+//sends the messages of the origins registered here to the other registers,
+//every register then answers lookups for the whole cluster
+pub struct Relay {
+    peers: Vec<String>,
+    incoming: UnboundedReceiver<Register>,
+}
+
+impl Relay {
+    pub fn new(peers: Vec<String>, incoming: UnboundedReceiver<Register>) -> Self {
+        Self { peers, incoming }
+    }
+
+    pub async fn run(mut self) -> Result<()> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        while let Some(msg) = self.incoming.recv().await {
+            let buf: Bytes = msg.try_into()?;
+            for peer in &self.peers {
+                //a peer that is down catches up with the next heartbeats
+                if let Err(e) = socket.send_to(&buf, peer.as_str()).await {
+                    log::debug!("relay to {} err {}", peer, e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,2 @@
+//the grpc api of the register, generated from proto/register.proto
+tonic::include_proto!("xlive.register");
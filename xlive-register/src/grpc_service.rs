@@ -0,0 +1,345 @@
+use crate::grpc::{
+    self,
+    register_service_server::{RegisterService, RegisterServiceServer},
+    EventKind, HeartbeatReply, HeartbeatRequest, LeaseReply, LeaseRequest, ListServersReply,
+    ListServersRequest, LookupReply, LookupRequest, PickOriginRequest, ReleaseReply, WatchRequest,
+};
+use crate::register::{
+    DownReason, EventRecord, IncomingMessage,
+    OneshotMsg::{self, GetChannel, GetServers, Subscribed},
+    OneshotMsgKind, RegisterEvent,
+};
+use futures::{Stream, TryStreamExt};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tokio_stream::StreamExt;
+use tonic::{transport::Server, Request, Response, Status};
+use xcore::register::{Load, PickPolicy, Register, RegisterKind, RegisterResp, RegisterRespKind};
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<grpc::Event, Status>> + Send>>;
+
+//the grpc api, answered by the same server loop as the udp protocol
+pub struct GrpcService {
+    handle: UnboundedSender<IncomingMessage>,
+    addr: SocketAddr,
+}
+
+impl GrpcService {
+    pub fn new(handle: UnboundedSender<IncomingMessage>, addr: SocketAddr) -> Self {
+        Self { handle, addr }
+    }
+
+    pub async fn run(self) {
+        let addr = self.addr;
+        log::info!("register grpc service Listening on {}", addr);
+        let api = Api {
+            handle: self.handle,
+        };
+        if let Err(e) = Server::builder()
+            .add_service(RegisterServiceServer::new(api))
+            .serve(addr)
+            .await
+        {
+            log::error!("register grpc service {} err {}", addr, e);
+        }
+    }
+}
+
+struct Api {
+    handle: UnboundedSender<IncomingMessage>,
+}
+
+impl Api {
+    async fn request(&self, msg_kind: OneshotMsgKind) -> Result<OneshotMsg, Status> {
+        let (request, receiver) = oneshot::channel();
+        self.handle
+            .send(IncomingMessage::Oneshot(msg_kind, request))
+            .map_err(|_| Status::unavailable("register is closed"))?;
+        //dropped on a storage error
+        receiver
+            .await
+            .map_err(|_| Status::unavailable("register storage error"))
+    }
+
+    //a get or pick origin, answered like the udp one
+    async fn query(&self, register: Register, addr: SocketAddr) -> Result<LookupReply, Status> {
+        let (request, receiver) = oneshot::channel();
+        self.handle
+            .send(IncomingMessage::Register(register, addr, Some(request)))
+            .map_err(|_| Status::unavailable("register is closed"))?;
+        let buf = receiver
+            .await
+            .map_err(|_| Status::unavailable("register storage error"))?;
+        let resp = RegisterResp::try_from(&buf[..]).map_err(|e| Status::internal(e.to_string()))?;
+        Ok(match resp.kind {
+            RegisterRespKind::OK => LookupReply {
+                found: true,
+                node_id: resp.node_id,
+                addr: resp.payload,
+            },
+            RegisterRespKind::NOFOUND | RegisterRespKind::REVOKED => LookupReply::default(),
+        })
+    }
+
+    fn lease(&self, register: Register, addr: SocketAddr) -> Result<(), ApiError> {
+        self.handle
+            .send(IncomingMessage::Lease(register, addr))
+            .map_err(|_| ApiError::Closed)
+    }
+
+    //the messages are handled in order, so the owner read after a lease reflects it
+    async fn owner(&self, channel_name: &str) -> Result<Option<(String, u64)>, Status> {
+        match self
+            .request(OneshotMsgKind::GetChannel(channel_name.to_owned()))
+            .await?
+        {
+            GetChannel(owner) => Ok(owner.map(|owner| (owner.node_id, owner.epoch))),
+            _ => Err(Status::internal("unexpected register response")),
+        }
+    }
+}
+
+//the source address stands in for the udp source, used for nodes that do not advertise one
+fn remote_addr<T>(request: &Request<T>) -> SocketAddr {
+    request
+        .remote_addr()
+        .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)))
+}
+
+fn register(kind: RegisterKind, channel_name: String, node_id: String, addr: String) -> Register {
+    Register {
+        kind,
+        node_id,
+        addr,
+        ..Register::get(0, &channel_name)
+    }
+}
+
+fn check_lease(lease: &LeaseRequest) -> Result<(), ApiError> {
+    if lease.channel.is_empty() || lease.node_id.is_empty() {
+        return Err(ApiError::InvalidArgument(
+            "channel and node_id are required",
+        ));
+    }
+    Ok(())
+}
+
+//errors of the helpers, a Status is too large to pass around, it is made at the rpc boundary
+#[derive(Debug)]
+enum ApiError {
+    Closed,
+    InvalidArgument(&'static str),
+}
+
+impl From<ApiError> for Status {
+    fn from(e: ApiError) -> Self {
+        match e {
+            ApiError::Closed => Status::unavailable("register is closed"),
+            ApiError::InvalidArgument(message) => Status::invalid_argument(message),
+        }
+    }
+}
+
+//the stream ends, the client resumes from the last id it got
+fn lagged(BroadcastStreamRecvError::Lagged(n): BroadcastStreamRecvError) -> Status {
+    Status::data_loss(format!(
+        "{} events dropped, watch again from the last event id",
+        n
+    ))
+}
+
+#[tonic::async_trait]
+impl RegisterService for Api {
+    async fn lookup(
+        &self,
+        request: Request<LookupRequest>,
+    ) -> Result<Response<LookupReply>, Status> {
+        let addr = remote_addr(&request);
+        let channel_name = request.into_inner().channel;
+        let reply = self.query(Register::get(0, &channel_name), addr).await?;
+        Ok(Response::new(reply))
+    }
+
+    async fn pick_origin(
+        &self,
+        request: Request<PickOriginRequest>,
+    ) -> Result<Response<LookupReply>, Status> {
+        let addr = remote_addr(&request);
+        let request = request.into_inner();
+        let policy = match request.policy() {
+            grpc::PickPolicy::LeastLoaded => PickPolicy::LeastLoaded,
+            grpc::PickPolicy::ConsistentHash => PickPolicy::ConsistentHash,
+        };
+        let register = Register::pick_origin(0, &request.channel, policy);
+        Ok(Response::new(self.query(register, addr).await?))
+    }
+
+    async fn acquire(
+        &self,
+        request: Request<LeaseRequest>,
+    ) -> Result<Response<LeaseReply>, Status> {
+        let addr = remote_addr(&request);
+        let lease = request.into_inner();
+        check_lease(&lease)?;
+        let channel_name = lease.channel.clone();
+        self.lease(
+            register(RegisterKind::Set, lease.channel, lease.node_id, lease.addr),
+            addr,
+        )?;
+        match self.owner(&channel_name).await? {
+            Some((owner, epoch)) => Ok(Response::new(LeaseReply { owner, epoch })),
+            //evicted or expired right away
+            None => Err(Status::aborted("lease lost")),
+        }
+    }
+
+    async fn heartbeat(
+        &self,
+        request: Request<HeartbeatRequest>,
+    ) -> Result<Response<HeartbeatReply>, Status> {
+        let addr = remote_addr(&request);
+        let heartbeat = request.into_inner();
+        if heartbeat.node_id.is_empty() {
+            return Err(Status::invalid_argument("node_id is required"));
+        }
+        let load = heartbeat.load.unwrap_or_default();
+        let channels = heartbeat.channels;
+        let mut register = register(
+            RegisterKind::Heartbeat,
+            "".to_owned(),
+            heartbeat.node_id.clone(),
+            heartbeat.addr,
+        );
+        register.channels = channels.clone();
+        register.load = Load {
+            channels: load.channels,
+            ingress_bps: load.ingress_bps,
+        };
+        self.lease(register, addr)?;
+
+        let mut revoked = vec![];
+        for channel_name in channels {
+            match self.owner(&channel_name).await? {
+                Some((owner, _)) if owner == heartbeat.node_id => {}
+                _ => revoked.push(channel_name),
+            }
+        }
+        Ok(Response::new(HeartbeatReply { revoked }))
+    }
+
+    async fn release(
+        &self,
+        request: Request<LeaseRequest>,
+    ) -> Result<Response<ReleaseReply>, Status> {
+        let addr = remote_addr(&request);
+        let lease = request.into_inner();
+        check_lease(&lease)?;
+        self.lease(
+            register(
+                RegisterKind::Delete,
+                lease.channel,
+                lease.node_id,
+                lease.addr,
+            ),
+            addr,
+        )?;
+        Ok(Response::new(ReleaseReply {}))
+    }
+
+    type WatchStream = EventStream;
+
+    async fn watch(&self, request: Request<WatchRequest>) -> Result<Response<EventStream>, Status> {
+        let last_event_id = match request.into_inner().last_event_id {
+            0 => None,
+            id => Some(id),
+        };
+        let (missed, receiver) = match self
+            .request(OneshotMsgKind::Subscribe(last_event_id))
+            .await?
+        {
+            Subscribed(missed, receiver) => (missed, receiver),
+            _ => return Err(Status::internal("unexpected register response")),
+        };
+        let stream = tokio_stream::iter(missed.into_iter().map(Ok))
+            .chain(BroadcastStream::new(receiver))
+            .map_ok(event)
+            .map_err(lagged);
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn list_servers(
+        &self,
+        request: Request<ListServersRequest>,
+    ) -> Result<Response<ListServersReply>, Status> {
+        let prefix = request.into_inner().prefix;
+        let mut servers = match self.request(OneshotMsgKind::GetServers).await? {
+            GetServers(servers) => servers,
+            _ => return Err(Status::internal("unexpected register response")),
+        };
+        servers.retain(|server| server.addr.starts_with(&prefix));
+        servers.sort_by(|a, b| a.addr.cmp(&b.addr));
+        let servers = servers
+            .into_iter()
+            .map(|server| grpc::Server {
+                addr: server.addr,
+                last_seen: server.last_seen,
+                node_id: server.node_id.unwrap_or_default(),
+                load: server.load.map(|load| grpc::Load {
+                    channels: load.channels,
+                    ingress_bps: load.ingress_bps,
+                }),
+                role: server
+                    .role
+                    .map(|role| format!("{:?}", role).to_lowercase())
+                    .unwrap_or_default(),
+                monitor_addr: server.monitor_addr.unwrap_or_default(),
+            })
+            .collect();
+        Ok(Response::new(ListServersReply { servers }))
+    }
+}
+
+fn event(record: EventRecord) -> grpc::Event {
+    let mut event = grpc::Event {
+        id: record.id,
+        ..Default::default()
+    };
+    match record.event {
+        RegisterEvent::ChannelUp {
+            channel,
+            node_id,
+            addr,
+        } => {
+            event.set_kind(EventKind::ChannelUp);
+            event.channel = channel;
+            event.node_id = node_id;
+            event.addr = addr;
+        }
+        RegisterEvent::ChannelDown {
+            channel,
+            node_id,
+            reason,
+        } => {
+            event.set_kind(EventKind::ChannelDown);
+            event.channel = channel;
+            event.node_id = node_id;
+            event.set_reason(match reason {
+                DownReason::Expired => grpc::DownReason::Expired,
+                DownReason::Deleted => grpc::DownReason::Deleted,
+                DownReason::Evicted => grpc::DownReason::Evicted,
+            });
+        }
+        RegisterEvent::ServerUp { addr } => {
+            event.set_kind(EventKind::ServerUp);
+            event.addr = addr;
+        }
+        RegisterEvent::ServerDown { addr } => {
+            event.set_kind(EventKind::ServerDown);
+            event.addr = addr;
+        }
+    }
+    event
+}
@@ -1,73 +1,332 @@
+use crate::metrics;
 use crate::register::{
-    IncomingMessage,
-    OneshotMsg::{GetAppsMap, GetServers},
-    OneshotMsgKind,
+    EventRecord, IncomingMessage,
+    OneshotMsg::{self, Evicted, GetAppsMap, GetChannel, GetConflicts, GetServers, Subscribed},
+    OneshotMsgKind, Owner,
 };
 use anyhow::Result;
+use bytes::Bytes;
+use hyper::header::AUTHORIZATION;
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Request, Response, Server, StatusCode};
-use serde_json::json;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc::UnboundedSender;
-use tokio::sync::oneshot;
+use tokio::sync::{broadcast, oneshot};
+use tokio::time;
+use xcore::http::{bearer_matches, parse_query, percent_decode};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+//comment lines keep proxies from closing an idle event stream
+const SSE_KEEPALIVE: Duration = Duration::from_secs(15);
+
 async fn monitor(
     handle: UnboundedSender<IncomingMessage>,
+    admin_token: Arc<Option<String>>,
     req: Request<Body>,
 ) -> Result<Response<Body>> {
-    let path = req.uri().path();
+    let path = req.uri().path().to_owned();
+    let query = parse_query(req.uri().query().unwrap_or(""));
+    let method = req.method().clone();
 
-    if path.is_empty() || (!path.eq("/servers_info") && !path.eq("/channels_info")) {
+    //channel names may contain slashes, the rest of the path is the name
+    if let Some(channel_name) = path.strip_prefix("/channels/") {
+        let channel_name = percent_decode(channel_name);
+        return match method {
+            Method::GET => {
+                match request(&handle, OneshotMsgKind::GetChannel(channel_name.clone())).await {
+                    Some(GetChannel(Some(owner))) => Ok(json_response(
+                        StatusCode::OK,
+                        channel_json(&channel_name, &owner, now()),
+                    )),
+                    Some(_) => Ok(status(StatusCode::NOT_FOUND)),
+                    None => Ok(status(StatusCode::SERVICE_UNAVAILABLE)),
+                }
+            }
+            Method::DELETE => {
+                if let Some(resp) = check_token(&req, &admin_token) {
+                    return Ok(resp);
+                }
+                Ok(evicted(
+                    request(&handle, OneshotMsgKind::EvictChannel(channel_name)).await,
+                ))
+            }
+            _ => Ok(status(StatusCode::METHOD_NOT_ALLOWED)),
+        };
+    }
+    if let Some(addr) = path.strip_prefix("/servers/") {
+        let addr = percent_decode(addr);
+        return match method {
+            Method::DELETE => {
+                if let Some(resp) = check_token(&req, &admin_token) {
+                    return Ok(resp);
+                }
+                Ok(evicted(
+                    request(&handle, OneshotMsgKind::EvictServer(addr)).await,
+                ))
+            }
+            _ => Ok(status(StatusCode::METHOD_NOT_ALLOWED)),
+        };
+    }
+    if method != Method::GET {
+        return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    if path == "/metrics" {
         return Ok(Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::empty())
+            .header("Content-Type", metrics::content_type())
+            .body(Body::from(metrics::encode()))
             .unwrap());
     }
+    if path == "/events" {
+        //EventSource sends the header when it reconnects, other clients may use the query
+        let last_event_id = req
+            .headers()
+            .get("Last-Event-ID")
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| query.get("last_event_id").map(String::as_str))
+            .and_then(|id| id.parse().ok());
+        return Ok(
+            match request(&handle, OneshotMsgKind::Subscribe(last_event_id)).await {
+                Some(Subscribed(missed, receiver)) => sse(missed, receiver),
+                _ => status(StatusCode::SERVICE_UNAVAILABLE),
+            },
+        );
+    }
 
-    let msg_kind = if path.eq("/servers_info") {
-        OneshotMsgKind::GetServers
-    } else {
-        OneshotMsgKind::GetAppsMap
+    let msg_kind = match path.as_str() {
+        "/servers_info" | "/servers" => OneshotMsgKind::GetServers,
+        "/channels_info" | "/channels" => OneshotMsgKind::GetAppsMap,
+        "/conflicts" => OneshotMsgKind::GetConflicts,
+        _ => return Ok(status(StatusCode::NOT_FOUND)),
+    };
+    let data = match request(&handle, msg_kind).await {
+        Some(data) => data,
+        None => return Ok(status(StatusCode::SERVICE_UNAVAILABLE)),
+    };
+    let resp = match (path.as_str(), data) {
+        ("/servers", GetServers(mut servers)) => {
+            let timestamp = now();
+            servers.retain(|server| matches_prefix(&server.addr, &query));
+            servers.sort_by(|a, b| a.addr.cmp(&b.addr));
+            let total = servers.len();
+            let servers: Vec<Value> = page(servers, &query)
+                .into_iter()
+                .map(|server| {
+                    let mut value = json!(server);
+                    value["age"] = json!(timestamp.saturating_sub(server.last_seen));
+                    value
+                })
+                .collect();
+            json!({ "total": total, "servers": servers })
+        }
+        ("/channels", GetAppsMap(channels)) => {
+            let timestamp = now();
+            let mut channels: Vec<(String, Owner)> = channels
+                .into_iter()
+                .filter(|(channel_name, _)| matches_prefix(channel_name, &query))
+                .collect();
+            channels.sort_by(|a, b| a.0.cmp(&b.0));
+            let total = channels.len();
+            let channels: Vec<Value> = page(channels, &query)
+                .iter()
+                .map(|(channel_name, owner)| channel_json(channel_name, owner, timestamp))
+                .collect();
+            json!({ "total": total, "channels": channels })
+        }
+        (_, GetServers(servers)) => {
+            json!({ "servers": servers })
+        }
+        (_, GetAppsMap(channels)) => {
+            json!({ "channels": channels })
+        }
+        (_, GetConflicts(conflicts)) => {
+            json!({ "conflicts": conflicts })
+        }
+        _ => json!({}),
     };
+    Ok(json_response(StatusCode::OK, resp))
+}
 
+async fn request(
+    handle: &UnboundedSender<IncomingMessage>,
+    msg_kind: OneshotMsgKind,
+) -> Option<OneshotMsg> {
     let (request, receviver) = oneshot::channel();
     handle
         .send(IncomingMessage::Oneshot(msg_kind, request))
-        .map_err(|_| anyhow::anyhow!("ChannelJoinFailed"))?;
+        .ok()?;
+    //dropped on a storage error
+    receviver.await.ok()
+}
 
-    let mut resp = json!({});
-    if let Ok(data) = receviver.await {
-        resp = match data {
-            GetServers(servers) => {
-                json!({ "servers": servers })
+//server-sent events, the missed ones first
+fn sse(missed: Vec<EventRecord>, mut receiver: broadcast::Receiver<EventRecord>) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        if sender
+            .send_data(Bytes::from_static(b"retry: 1000\n\n"))
+            .await
+            .is_err()
+        {
+            return;
+        }
+        for record in missed {
+            if sender.send_data(sse_event(&record)).await.is_err() {
+                return;
             }
-            GetAppsMap(channels) => {
-                json!({ "channels": channels })
+        }
+        let mut keepalive = time::interval(SSE_KEEPALIVE);
+        loop {
+            let data = tokio::select! {
+                record = receiver.recv() => match record {
+                    Ok(record) => sse_event(&record),
+                    //a lagging client reconnects and gets the missed events from the history
+                    Err(_) => break,
+                },
+                _ = keepalive.tick() => Bytes::from_static(b": keepalive\n\n"),
+            };
+            if sender.send_data(data).await.is_err() {
+                break;
             }
-        };
+        }
+    });
+    Response::builder()
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(body)
+        .unwrap()
+}
+
+fn sse_event(record: &EventRecord) -> Bytes {
+    let data = serde_json::to_value(&record.event).unwrap();
+    let name = data["event"].as_str().unwrap_or("message").to_owned();
+    Bytes::from(format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        record.id, name, data
+    ))
+}
+
+//mutating requests need `Authorization: Bearer <token>`, and are refused without a configured token
+fn check_token(req: &Request<Body>, admin_token: &Option<String>) -> Option<Response<Body>> {
+    let admin_token = match admin_token {
+        Some(admin_token) => admin_token,
+        None => return Some(status(StatusCode::FORBIDDEN)),
+    };
+    let authorization = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if !bearer_matches(authorization, admin_token) {
+        return Some(status(StatusCode::UNAUTHORIZED));
+    }
+    None
+}
+
+fn evicted(data: Option<OneshotMsg>) -> Response<Body> {
+    match data {
+        Some(Evicted(true, channels)) => {
+            json_response(StatusCode::OK, json!({ "evicted": channels }))
+        }
+        Some(_) => status(StatusCode::NOT_FOUND),
+        None => status(StatusCode::SERVICE_UNAVAILABLE),
     }
-    let mut res = Response::new(Body::from(resp.to_string()));
-    res.headers_mut()
-        .insert("Access-Control-Allow-Origin", "*".parse().unwrap());
-    Ok(res)
+}
+
+fn channel_json(channel_name: &str, owner: &Owner, timestamp: u64) -> Value {
+    json!({
+        "channel": channel_name,
+        "node_id": owner.node_id,
+        "addr": owner.addr,
+        "epoch": owner.epoch,
+        "confirmed": owner.confirmed,
+        //seconds since the last heartbeat
+        "age": timestamp.saturating_sub(owner.timestamp),
+    })
+}
+
+fn matches_prefix(name: &str, query: &HashMap<String, String>) -> bool {
+    query
+        .get("prefix")
+        .map_or(true, |prefix| name.starts_with(prefix.as_str()))
+}
+
+fn page<T>(items: Vec<T>, query: &HashMap<String, String>) -> Vec<T> {
+    let offset = query
+        .get("offset")
+        .and_then(|offset| offset.parse().ok())
+        .unwrap_or(0);
+    let limit = query
+        .get("limit")
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(DEFAULT_LIMIT)
+        .min(MAX_LIMIT);
+    items.into_iter().skip(offset).take(limit).collect()
+}
+
+fn json_response(code: StatusCode, value: Value) -> Response<Body> {
+    Response::builder()
+        .status(code)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(Body::from(value.to_string()))
+        .unwrap()
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(code)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
 }
 
 pub struct Service {
     handle: UnboundedSender<IncomingMessage>,
+    addr: SocketAddr,
+    admin_token: Arc<Option<String>>,
 }
 
 impl Service {
-    pub fn new(handle: UnboundedSender<IncomingMessage>) -> Self {
-        Self { handle }
+    pub fn new(
+        handle: UnboundedSender<IncomingMessage>,
+        addr: SocketAddr,
+        admin_token: Option<String>,
+    ) -> Self {
+        Self {
+            handle,
+            addr,
+            admin_token: Arc::new(admin_token),
+        }
     }
     pub async fn run(&self) {
         let handle_cp = self.handle.clone();
+        let admin_token = self.admin_token.clone();
         let make_service = make_service_fn(move |_| {
             let handle_cp = handle_cp.clone();
-            async move { Ok::<_, Infallible>(service_fn(move |req| monitor(handle_cp.clone(), req))) }
+            let admin_token = admin_token.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    monitor(handle_cp.clone(), admin_token.clone(), req)
+                }))
+            }
         });
-        let addr = "[::]:3033".parse().unwrap();
-        let server = Server::bind(&addr).serve(make_service);
-        log::info!("register http service Listening on http://{}", addr);
+        let server = match Server::try_bind(&self.addr) {
+            Ok(builder) => builder.serve(make_service),
+            Err(e) => return log::error!("register http service bind {} err {}", self.addr, e),
+        };
+        log::info!("register http service Listening on http://{}", self.addr);
         _ = server.await;
     }
 }
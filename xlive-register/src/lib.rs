@@ -1,2 +1,151 @@
+pub mod cluster;
+pub mod grpc;
+pub mod grpc_service;
 pub mod http_service;
+pub mod metrics;
 pub mod register;
+pub mod storage;
+
+use anyhow::Result;
+use bytes::Bytes;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{
+    net::UdpSocket,
+    sync::{mpsc::unbounded_channel, oneshot},
+};
+use xcore::register::{Register, RegisterKind};
+
+use crate::cluster::Relay;
+use crate::grpc_service::GrpcService;
+use crate::http_service::Service;
+use crate::register::{IncomingMessage, Server};
+use crate::storage::{MemoryStorage, RedisStorage, Storage};
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    //udp address the nodes register and look up at
+    pub addr: String,
+    pub http_addr: SocketAddr,
+    //the grpc api, disabled when not set
+    pub grpc_addr: Option<SocketAddr>,
+    //udp addresses of the other registers of the cluster
+    pub peers: Vec<String>,
+    pub channel_ttl: Duration,
+    pub server_ttl: Duration,
+    pub snapshot: Option<PathBuf>,
+    pub snapshot_max_age: Duration,
+    //keep the state in redis instead of memory
+    pub redis_url: Option<String>,
+    pub redis_prefix: String,
+    //required by the mutating admin endpoints, which are refused without it
+    pub admin_token: Option<String>,
+}
+
+impl Config {
+    pub fn new(addr: &str, http_addr: SocketAddr) -> Self {
+        Self {
+            addr: addr.to_owned(),
+            http_addr,
+            grpc_addr: None,
+            peers: vec![],
+            channel_ttl: Duration::from_secs(10),
+            server_ttl: Duration::from_secs(10),
+            snapshot: None,
+            snapshot_max_age: Duration::from_secs(60),
+            redis_url: None,
+            redis_prefix: "xlive".to_owned(),
+            admin_token: None,
+        }
+    }
+}
+
+//runs a register until one of its services stops
+pub async fn run(config: Config) -> Result<()> {
+    match &config.redis_url {
+        Some(url) => {
+            //the sweeper removes the leases, the key ttl only covers registers all being down
+            let key_ttl = config.channel_ttl.max(config.server_ttl) * 2;
+            let storage = RedisStorage::connect(url, &config.redis_prefix, key_ttl).await?;
+            serve(config, storage).await
+        }
+        None => serve(config, MemoryStorage::default()).await,
+    }
+}
+
+async fn serve<S: Storage>(config: Config, storage: S) -> Result<()> {
+    let (sender, revciver) = unbounded_channel();
+    let mut server = Server::new(revciver, storage, config.channel_ttl, config.server_ttl);
+    if let Some(path) = &config.snapshot {
+        server.restore(path, config.snapshot_max_age).await?;
+    }
+    if !config.peers.is_empty() {
+        let (relay, relay_incoming) = unbounded_channel();
+        server.relay_to(relay);
+        tokio::spawn(Relay::new(config.peers.clone(), relay_incoming).run());
+    }
+
+    let socket = Arc::new(UdpSocket::bind(&config.addr).await?);
+    log::info!("register Listening on: {}", socket.local_addr()?);
+
+    let (outgoing, mut outgoing_incoming) = unbounded_channel::<(Bytes, SocketAddr)>();
+    server.notify_to(outgoing);
+    let outgoing_socket = socket.clone();
+    tokio::spawn(async move {
+        while let Some((buf, addr)) = outgoing_incoming.recv().await {
+            if let Err(e) = outgoing_socket.send_to(&buf, addr).await {
+                log::debug!("register send to {} err {}", addr, e);
+            }
+        }
+    });
+
+    let mut handles = vec![];
+    handles.push(tokio::spawn(server.run()));
+
+    let sender_cp = sender.clone();
+    handles.push(tokio::spawn(async move {
+        let mut buf = vec![0u8; 65536];
+        while let Ok((size, addr)) = socket.recv_from(&mut buf).await {
+            let buffer = &buf[..size];
+            if let Ok(msg) = Register::try_from(buffer) {
+                match msg.kind {
+                    RegisterKind::Set | RegisterKind::Delete | RegisterKind::Heartbeat => {
+                        _ = sender.send(IncomingMessage::Register(msg, addr, None));
+                    }
+                    RegisterKind::Get | RegisterKind::PickOrigin => {
+                        let (s, r) = oneshot::channel();
+                        _ = sender.send(IncomingMessage::Register(msg, addr, Some(s)));
+                        if let Ok(buf) = r.await {
+                            socket.send_to(&buf, &addr).await?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    }));
+
+    if let Some(grpc_addr) = config.grpc_addr {
+        let handle = sender_cp.clone();
+        handles.push(tokio::spawn(async move {
+            GrpcService::new(handle, grpc_addr).run().await;
+            Ok::<(), anyhow::Error>(())
+        }));
+    }
+
+    let http_addr = config.http_addr;
+    let admin_token = config.admin_token.clone();
+    handles.push(tokio::spawn(async move {
+        Service::new(sender_cp, http_addr, admin_token).run().await;
+        Ok::<(), anyhow::Error>(())
+    }));
+
+    for handle in handles {
+        if let Err(e) = handle.await {
+            log::error!("{}", e);
+        }
+    }
+    Ok(())
+}
@@ -1,14 +1,62 @@
 use anyhow::Result;
 use chrono::Local;
-use core::register::Register;
-use core::register::RegisterKind;
-use std::{collections::HashMap, io::Write};
-use tokio::{
-    net::UdpSocket,
-    sync::{mpsc::unbounded_channel, oneshot},
-};
-use xlive_register::http_service::Service;
-use xlive_register::register::{self, IncomingMessage};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use structopt::StructOpt;
+use xlive_register::Config;
+#[derive(Debug, StructOpt)]
+#[structopt(name = "xlive-register")]
+struct Opt {
+    /// udp address the nodes register and look up at
+    #[structopt(long = "addr", default_value = "0.0.0.0:9336")]
+    addr: String,
+
+    #[structopt(long = "http-addr", default_value = "[::]:3033")]
+    http_addr: String,
+
+    /// address of the grpc api, empty disables it
+    #[structopt(long = "grpc-addr", default_value = "[::]:9337")]
+    grpc_addr: String,
+
+    /// comma separated udp addresses of the other registers of the cluster
+    #[structopt(long = "peers", default_value = "")]
+    peers: String,
+
+    /// seconds a channel is kept without a heartbeat from its origin
+    #[structopt(long = "channel-ttl", default_value = "10")]
+    channel_ttl: u64,
+
+    /// seconds a node is kept in the server list after its last message
+    #[structopt(long = "server-ttl", default_value = "10")]
+    server_ttl: u64,
+
+    /// file the state is saved to and reloaded from on restart, empty disables it
+    #[structopt(long = "snapshot", default_value = "")]
+    snapshot: String,
+
+    /// seconds since their last heartbeat after which saved channels are not restored
+    #[structopt(long = "snapshot-max-age", default_value = "60")]
+    snapshot_max_age: u64,
+
+    /// keep the state in redis, e.g. redis://127.0.0.1/, empty keeps it in memory
+    #[structopt(long = "redis", default_value = "")]
+    redis: String,
+
+    /// prefix of the redis keys and of the events pub/sub channel
+    #[structopt(long = "redis-prefix", default_value = "xlive")]
+    redis_prefix: String,
+
+    /// bearer token of the admin endpoints that evict channels and servers, empty disables them
+    #[structopt(
+        long = "admin-token",
+        env = "XLIVE_ADMIN_TOKEN",
+        default_value = "",
+        hide_env_values = true
+    )]
+    admin_token: String,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let env = env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info");
@@ -25,54 +73,33 @@ async fn main() -> Result<()> {
         })
         .init();
 
-    let (sender, revciver) = unbounded_channel();
-
-    let mut handles = vec![];
-
-    handles.push(tokio::spawn(async move {
-        register::Server {
-            incoming: revciver,
-            channel_map: HashMap::new(),
-            servers: HashMap::new(),
-        }
-        .run()
-        .await
-    }));
-
-    let sender_cp = sender.clone();
-    handles.push(tokio::spawn(async move {
-        let socket = UdpSocket::bind("0.0.0.0:9336").await?;
-        println!("Listening on: {}", socket.local_addr()?);
-        let mut buf = vec![0u8; 1000];
-        while let Ok((size, addr)) = socket.recv_from(&mut buf).await {
-            let buffer = &buf[..size];
-            if let Ok(msg) = Register::try_from(buffer) {
-                match msg.kind {
-                    RegisterKind::Set | RegisterKind::Delete => {
-                        _ = sender.send(IncomingMessage::Register(msg, addr, None));
-                    }
-                    RegisterKind::Get => {
-                        let (s, r) = oneshot::channel();
-                        _ = sender.send(IncomingMessage::Register(msg, addr, Some(s)));
-                        if let Ok(buf) = r.await {
-                            socket.send_to(&buf, &addr).await?;
-                        }
-                    }
-                }
-            }
-        }
-        Ok::<(), anyhow::Error>(())
-    }));
+    let mut opt = Opt::from_args();
+    //kept out of the log
+    let admin_token = std::mem::take(&mut opt.admin_token);
+    log::info!("{:?}", opt);
 
-    handles.push(tokio::spawn(async move {
-        Service::new(sender_cp).run().await;
-        Ok::<(), anyhow::Error>(())
-    }));
-
-    for handle in handles {
-        if let Err(e) = handle.await {
-            log::error!("{}", e);
-        }
+    let mut config = Config::new(&opt.addr, opt.http_addr.parse()?);
+    if !opt.grpc_addr.is_empty() {
+        config.grpc_addr = Some(opt.grpc_addr.parse()?);
+    }
+    config.peers = opt
+        .peers
+        .split(',')
+        .map(|peer| peer.trim().to_owned())
+        .filter(|peer| !peer.is_empty())
+        .collect();
+    config.channel_ttl = Duration::from_secs(opt.channel_ttl);
+    config.server_ttl = Duration::from_secs(opt.server_ttl);
+    if !opt.snapshot.is_empty() {
+        config.snapshot = Some(PathBuf::from(&opt.snapshot));
+    }
+    config.snapshot_max_age = Duration::from_secs(opt.snapshot_max_age);
+    if !opt.redis.is_empty() {
+        config.redis_url = Some(opt.redis.clone());
+    }
+    config.redis_prefix = opt.redis_prefix.clone();
+    if !admin_token.is_empty() {
+        config.admin_token = Some(admin_token);
     }
-    Ok(())
+    xlive_register::run(config).await
 }
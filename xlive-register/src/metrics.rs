@@ -0,0 +1,75 @@
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, TextEncoder,
+};
+use std::sync::LazyLock;
+
+/// Metrics of the register, in the default prometheus registry.
+pub struct Metrics {
+    pub messages: IntCounterVec,
+    //gets and pick origins by whether a node was found
+    pub lookups: IntCounterVec,
+    pub message_seconds: HistogramVec,
+    //set by the sweeper
+    pub channels: IntGauge,
+    pub servers: IntGauge,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let metrics = Metrics {
+        messages: IntCounterVec::new(
+            Opts::new(
+                "xlive_register_messages_total",
+                "register messages handled, udp and grpc",
+            ),
+            &["kind"],
+        )
+        .unwrap(),
+        lookups: IntCounterVec::new(
+            Opts::new("xlive_register_lookups_total", "channel lookups answered"),
+            &["kind", "result"],
+        )
+        .unwrap(),
+        message_seconds: HistogramVec::new(
+            HistogramOpts::new(
+                "xlive_register_message_seconds",
+                "time the server loop spent on a register message",
+            )
+            .buckets(vec![
+                0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0,
+            ]),
+            &["kind"],
+        )
+        .unwrap(),
+        channels: IntGauge::new("xlive_register_channels", "registered channels").unwrap(),
+        servers: IntGauge::new("xlive_register_servers", "live servers").unwrap(),
+    };
+    let registry = prometheus::default_registry();
+    registry
+        .register(Box::new(metrics.messages.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.lookups.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.message_seconds.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.channels.clone()))
+        .unwrap();
+    registry
+        .register(Box::new(metrics.servers.clone()))
+        .unwrap();
+    metrics
+});
+
+pub fn encode() -> String {
+    LazyLock::force(&METRICS);
+    let mut buf = vec![];
+    //writing to a vec does not fail
+    _ = TextEncoder::new().encode(&prometheus::gather(), &mut buf);
+    String::from_utf8(buf).unwrap_or_default()
+}
+
+pub fn content_type() -> String {
+    TextEncoder::new().format_type().to_owned()
+}
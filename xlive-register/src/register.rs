@@ -1,105 +1,898 @@
+use crate::metrics::METRICS;
+use crate::storage::Storage;
 use anyhow::Result;
 use bytes::Bytes;
-use core::register::{Register, RegisterKind, RegisterResp, RegisterRespKind};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::{collections::HashMap, net::SocketAddr};
-use tokio::sync::mpsc::UnboundedReceiver;
-use tokio::sync::oneshot;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{fs, net::SocketAddr};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::{broadcast, oneshot};
+use tokio::time;
+use xcore::register::{
+    Load, PickPolicy, Register, RegisterKind, RegisterResp, RegisterRespKind, Role,
+};
+
+const ORIGIN_PORT: u16 = 9878;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+//heartbeats only move the timestamps, the snapshot is rewritten for them this often,
+//so the restored ones stay within the max age
+const SNAPSHOT_REFRESH: Duration = Duration::from_secs(10);
+//seconds a conflict is reported after its last occurrence
+const CONFLICT_TTL: u64 = 300;
+//events kept for subscribers resuming after a reconnect
+const EVENT_HISTORY: usize = 1024;
+
+//the origin a channel was last registered by
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Owner {
+    pub node_id: String,
+    //advertised tcp address of the origin
+    pub addr: String,
+    pub timestamp: u64,
+    //false for entries restored from a snapshot until their origin sends a heartbeat
+    pub confirmed: bool,
+    //raised every time a set moves the lease to another origin, reported but not checked
+    #[serde(default)]
+    pub epoch: u64,
+    //udp address the origin is told at when it loses the lease
+    #[serde(default)]
+    pub notify: String,
+}
+
+//state saved across restarts
+#[derive(Default, Serialize, Deserialize)]
+struct Snapshot {
+    channels: HashMap<String, Owner>,
+    servers: HashMap<String, u64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ServerInfo {
+    pub addr: String,
+    pub last_seen: u64,
+    //origins and nodes that announce themselves
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load: Option<Load>,
+    //nodes that announce themselves
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monitor_addr: Option<String>,
+}
+
+//last announce of a node
+#[derive(Clone, Debug)]
+struct NodeInfo {
+    node_id: String,
+    role: Role,
+    monitor_addr: String,
+    updated: u64,
+}
+
+//last load heartbeat of an origin
+#[derive(Clone, Debug)]
+struct OriginLoad {
+    node_id: String,
+    load: Load,
+    updated: u64,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownReason {
+    //no heartbeat within the ttl
+    Expired,
+    //the origin deleted the channel
+    Deleted,
+    //removed through the admin api
+    Evicted,
+}
+
+//transitions of the register state
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RegisterEvent {
+    ChannelUp {
+        channel: String,
+        node_id: String,
+        addr: String,
+    },
+    ChannelDown {
+        channel: String,
+        node_id: String,
+        reason: DownReason,
+    },
+    ServerUp {
+        addr: String,
+    },
+    ServerDown {
+        addr: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictKind {
+    //another origin acquired the lease held by a live origin
+    Takeover,
+    //an origin kept sending heartbeats for a lease it lost
+    StaleHeartbeat,
+}
+
+//two origins claiming the same channel
+#[derive(Clone, Debug, Serialize)]
+pub struct Conflict {
+    pub channel: String,
+    //the lease holder at the last occurrence
+    pub owner: String,
+    pub epoch: u64,
+    //the other origin
+    pub node_id: String,
+    pub kind: ConflictKind,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub count: u64,
+}
+
+//an event and the id subscribers resume after
+#[derive(Clone, Debug)]
+pub struct EventRecord {
+    pub id: u64,
+    pub event: RegisterEvent,
+}
 
 pub enum OneshotMsg {
-    GetServers(Vec<String>),
-    GetAppsMap(HashMap<String, (SocketAddr, u64)>),
+    GetServers(Vec<ServerInfo>),
+    GetAppsMap(HashMap<String, Owner>),
+    GetConflicts(Vec<Conflict>),
+    GetChannel(Option<Owner>),
+    //whether the channel or server was known, and the channels removed
+    Evicted(bool, Vec<String>),
+    //the events missed since the id asked for, then the live ones
+    Subscribed(Vec<EventRecord>, broadcast::Receiver<EventRecord>),
 }
 pub enum OneshotMsgKind {
     GetServers,
     GetAppsMap,
+    GetConflicts,
+    GetChannel(String),
+    EvictChannel(String),
+    //the server and all the channels it owns
+    EvictServer(String),
+    //the id of the last event received before a reconnect
+    Subscribe(Option<u64>),
 }
 pub enum IncomingMessage {
     Register(Register, SocketAddr, Option<oneshot::Sender<Bytes>>),
+    //a set, heartbeat or delete from the grpc api, no revocation is sent to its source
+    Lease(Register, SocketAddr),
     Oneshot(OneshotMsgKind, oneshot::Sender<OneshotMsg>),
 }
 
-pub struct Server {
-    pub incoming: UnboundedReceiver<IncomingMessage>,
-    pub channel_map: HashMap<String, (SocketAddr, u64)>,
-    pub servers: HashMap<SocketAddr, u64>,
+//a claim of a channel by an origin
+struct Lease<'a> {
+    node_id: &'a str,
+    addr: &'a str,
+    notify: Option<SocketAddr>,
+    acquire: bool,
 }
 
-impl Server {
-    pub async fn run(self) -> Result<()> {
-        let Server {
-            mut incoming,
-            mut channel_map,
-            mut servers,
-        } = self;
+pub struct Server<S: Storage> {
+    incoming: UnboundedReceiver<IncomingMessage>,
+    //channels and the servers, keyed by the advertised address or the source address of nodes that only look up
+    storage: S,
+    channel_ttl: u64,
+    server_ttl: u64,
+    events: broadcast::Sender<EventRecord>,
+    history: VecDeque<EventRecord>,
+    //starts from the boot time in ms, so ids keep growing across restarts
+    next_event_id: u64,
+    snapshot_path: Option<PathBuf>,
+    //set when the state changed since the last snapshot
+    dirty: bool,
+    //when the snapshot was last written
+    saved: Instant,
+    //origin messages are copied to the other registers of the cluster
+    relay: Option<UnboundedSender<Register>>,
+    //datagrams the register sends unasked, the revocations
+    outgoing: Option<UnboundedSender<(Bytes, SocketAddr)>>,
+    //keyed by channel and the origin that is not the owner
+    conflicts: HashMap<(String, String), Conflict>,
+    //origins new publishers can be sent to, keyed by their address
+    origins: HashMap<String, OriginLoad>,
+    //roles and monitor addresses of the nodes, keyed by their address
+    nodes: HashMap<String, NodeInfo>,
+}
+
+impl<S: Storage> Server<S> {
+    pub fn new(
+        incoming: UnboundedReceiver<IncomingMessage>,
+        storage: S,
+        channel_ttl: Duration,
+        server_ttl: Duration,
+    ) -> Self {
+        let (events, _) = broadcast::channel(256);
+        Self {
+            incoming,
+            storage,
+            channel_ttl: channel_ttl.as_secs(),
+            server_ttl: server_ttl.as_secs(),
+            events,
+            history: VecDeque::new(),
+            next_event_id: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+            snapshot_path: None,
+            dirty: false,
+            saved: Instant::now(),
+            relay: None,
+            outgoing: None,
+            conflicts: HashMap::new(),
+            origins: HashMap::new(),
+            nodes: HashMap::new(),
+        }
+    }
+
+    //copy the messages of the origins to the peers through `relay`
+    pub fn relay_to(&mut self, relay: UnboundedSender<Register>) {
+        self.relay = Some(relay);
+    }
+
+    //tell origins that lost a lease through `outgoing`
+    pub fn notify_to(&mut self, outgoing: UnboundedSender<(Bytes, SocketAddr)>) {
+        self.outgoing = Some(outgoing);
+    }
+
+    //reload the state saved by a previous run and keep saving it to `path`,
+    //restored channels get a ttl from now to be confirmed by their origin
+    pub async fn restore(&mut self, path: &Path, max_age: Duration) -> Result<()> {
+        self.snapshot_path = Some(path.to_owned());
+        if !path.exists() {
+            return Ok(());
+        }
+        let snapshot: Snapshot = serde_json::from_slice(&fs::read(path)?)?;
+        let timestamp = now();
+        let mut restored = 0;
+        for (channel_name, mut owner) in snapshot.channels {
+            if timestamp.saturating_sub(owner.timestamp) > max_age.as_secs() {
+                continue;
+            }
+            owner.timestamp = timestamp;
+            owner.confirmed = false;
+            self.storage.set_channel(&channel_name, &owner).await?;
+            restored += 1;
+        }
+        for (addr, last_seen) in snapshot.servers {
+            self.storage.touch_server(&addr, last_seen).await?;
+        }
+        log::info!(
+            "restored {} channels from snapshot {}",
+            restored,
+            path.display()
+        );
+        Ok(())
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<EventRecord> {
+        self.events.subscribe()
+    }
 
+    pub async fn run(mut self) -> Result<()> {
+        let mut sweeper = time::interval(SWEEP_INTERVAL);
         loop {
-            while let Some(data) = incoming.recv().await {
-                let timestamp = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-                match data {
-                    IncomingMessage::Register(msg, addr, outgoing) => {
-                        servers.insert(addr, timestamp);
-                        match msg.kind {
-                            RegisterKind::Set => {
-                                channel_map.insert(msg.channel_name, (addr, timestamp));
-                            }
-                            RegisterKind::Get => {
-                                let mut resp = RegisterResp {
-                                    kind: RegisterRespKind::NOFOUND,
-                                    payload: "".to_owned(),
-                                };
-                                if let Some((ref socket_addr, ref last_timestamp)) =
-                                    channel_map.get(&msg.channel_name)
-                                {
-                                    log::info!(
-                                        "get {} found socket_addr:{} last_timestamp:{}",
-                                        msg.channel_name,
-                                        socket_addr,
-                                        last_timestamp
-                                    );
-                                    if timestamp - last_timestamp < 10 {
-                                        resp = RegisterResp {
-                                            kind: RegisterRespKind::OK,
-                                            payload: socket_addr.to_string(),
-                                        };
-                                    } else {
-                                        channel_map.remove(&msg.channel_name);
-                                        log::info!(
-                                            "remove {} alter 10 sec no update",
-                                            msg.channel_name,
-                                        );
-                                    }
-                                }
-                                let buf: Bytes = resp.try_into().unwrap();
-                                if outgoing.is_some() {
-                                    _ = outgoing.unwrap().send(buf);
-                                }
-                            }
-                            RegisterKind::Delete => {
-                                channel_map.remove(&msg.channel_name);
-                            }
+            tokio::select! {
+                data = self.incoming.recv() => match data {
+                    Some(data) => {
+                        //a get without a response is sent again, possibly to another register
+                        if let Err(e) = self.handle_message(data).await {
+                            log::error!("register storage err {}", e);
                         }
                     }
-                    IncomingMessage::Oneshot(kind, sender) => match kind {
-                        OneshotMsgKind::GetServers => {
-                            let servers_list: Vec<String> = servers
-                                .iter()
-                                .filter(|(_k, &v)| timestamp - v < 10)
-                                .map(|(k, _v)| k.to_string())
-                                .collect();
-
-                            _ = sender.send(OneshotMsg::GetServers(servers_list));
-                        }
-                        OneshotMsgKind::GetAppsMap => {
-                            let new_map = channel_map.clone();
-                            _ = sender.send(OneshotMsg::GetAppsMap(new_map));
+                    None => break,
+                },
+                _ = sweeper.tick() => {
+                    if let Err(e) = self.sweep().await {
+                        log::error!("register sweep err {}", e);
+                    }
+                    if let Err(e) = self.save().await {
+                        log::error!("save register snapshot err {}", e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_message(&mut self, data: IncomingMessage) -> Result<()> {
+        match data {
+            IncomingMessage::Register(msg, addr, outgoing) => {
+                //relayed messages come from another register
+                let notify = (!msg.relayed).then_some(addr);
+                self.handle_register(msg, addr, notify, outgoing).await?;
+            }
+            //grpc origins learn about revocations from their heartbeats
+            IncomingMessage::Lease(msg, addr) => {
+                self.handle_register(msg, addr, None, None).await?;
+            }
+            IncomingMessage::Oneshot(kind, sender) => match kind {
+                OneshotMsgKind::GetServers => {
+                    let servers_list: Vec<ServerInfo> = self
+                        .storage
+                        .servers()
+                        .await?
+                        .into_iter()
+                        .map(|(k, v)| {
+                            let origin = self.origins.get(&k);
+                            let node = self.nodes.get(&k);
+                            ServerInfo {
+                                node_id: origin
+                                    .map(|origin| origin.node_id.clone())
+                                    .or_else(|| node.map(|node| node.node_id.clone())),
+                                load: origin.map(|origin| origin.load),
+                                role: node.map(|node| node.role),
+                                monitor_addr: node.map(|node| node.monitor_addr.clone()),
+                                addr: k,
+                                last_seen: v,
+                            }
+                        })
+                        .collect();
+
+                    _ = sender.send(OneshotMsg::GetServers(servers_list));
+                }
+                OneshotMsgKind::GetAppsMap => {
+                    let new_map = self.storage.channels().await?;
+                    _ = sender.send(OneshotMsg::GetAppsMap(new_map));
+                }
+                OneshotMsgKind::GetConflicts => {
+                    let conflicts = self.conflicts.values().cloned().collect();
+                    _ = sender.send(OneshotMsg::GetConflicts(conflicts));
+                }
+                //answered from the server loop, so no event falls between the history and the receiver
+                OneshotMsgKind::Subscribe(last_event_id) => {
+                    let missed = match last_event_id {
+                        Some(last_event_id) => self
+                            .history
+                            .iter()
+                            .filter(|record| record.id > last_event_id)
+                            .cloned()
+                            .collect(),
+                        None => vec![],
+                    };
+                    _ = sender.send(OneshotMsg::Subscribed(missed, self.events.subscribe()));
+                }
+                OneshotMsgKind::GetChannel(channel_name) => {
+                    let owner = self.storage.get_channel(&channel_name).await?;
+                    _ = sender.send(OneshotMsg::GetChannel(owner));
+                }
+                OneshotMsgKind::EvictChannel(channel_name) => {
+                    let found = self.evict_channel(&channel_name).await?;
+                    let channels = if found { vec![channel_name] } else { vec![] };
+                    _ = sender.send(OneshotMsg::Evicted(found, channels));
+                }
+                OneshotMsgKind::EvictServer(addr) => {
+                    let mut channels = vec![];
+                    for (channel_name, owner) in self.storage.channels().await? {
+                        if owner.addr == addr && self.evict_channel(&channel_name).await? {
+                            channels.push(channel_name);
                         }
+                    }
+                    self.origins.remove(&addr);
+                    self.nodes.remove(&addr);
+                    let found = self.storage.remove_server(&addr).await?;
+                    if found {
+                        self.dirty = true;
+                        self.emit(RegisterEvent::ServerDown { addr }).await;
+                    }
+                    _ = sender.send(OneshotMsg::Evicted(found || !channels.is_empty(), channels));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    async fn handle_register(
+        &mut self,
+        msg: Register,
+        addr: SocketAddr,
+        notify: Option<SocketAddr>,
+        outgoing: Option<oneshot::Sender<Bytes>>,
+    ) -> Result<()> {
+        let kind = kind_label(msg.kind);
+        let start = Instant::now();
+        METRICS.messages.with_label_values(&[kind]).inc();
+        let res = self.register_message(msg, addr, notify, outgoing).await;
+        METRICS
+            .message_seconds
+            .with_label_values(&[kind])
+            .observe(start.elapsed().as_secs_f64());
+        res
+    }
+
+    async fn register_message(
+        &mut self,
+        msg: Register,
+        addr: SocketAddr,
+        notify: Option<SocketAddr>,
+        outgoing: Option<oneshot::Sender<Bytes>>,
+    ) -> Result<()> {
+        let timestamp = now();
+        //origins that do not advertise an address are reached
+        //on the default origin port at the source of their heartbeats
+        let node_addr = match msg.kind {
+            _ if !msg.addr.is_empty() => with_source_ip(&msg.addr, addr),
+            //caches and edges are only reached through their monitor
+            RegisterKind::Heartbeat if matches!(msg.role, Some(Role::Cache | Role::Edge)) => {
+                addr.to_string()
+            }
+            RegisterKind::Set | RegisterKind::Heartbeat | RegisterKind::Delete => {
+                SocketAddr::new(addr.ip(), ORIGIN_PORT).to_string()
+            }
+            RegisterKind::Get | RegisterKind::PickOrigin => addr.to_string(),
+        };
+        let monitor_addr = with_source_ip(&msg.monitor_addr, addr);
+        let query = matches!(msg.kind, RegisterKind::Get | RegisterKind::PickOrigin);
+        if let Some(relay) = &self.relay {
+            if !msg.relayed && !query {
+                //peers see the relay as source, the origin and monitor addresses are filled in
+                _ = relay.send(Register {
+                    addr: node_addr.clone(),
+                    monitor_addr: monitor_addr.clone(),
+                    relayed: true,
+                    ..msg.clone()
+                });
+            }
+        }
+        if self.storage.touch_server(&node_addr, timestamp).await? {
+            self.dirty = true;
+            self.emit(RegisterEvent::ServerUp {
+                addr: node_addr.clone(),
+            })
+            .await;
+        }
+        let node_id = if msg.node_id.is_empty() {
+            node_addr.clone()
+        } else {
+            msg.node_id
+        };
+        match msg.kind {
+            RegisterKind::Set => {
+                let lease = Lease {
+                    node_id: &node_id,
+                    addr: &node_addr,
+                    notify,
+                    acquire: true,
+                };
+                self.set_channel(msg.channel_name, lease, timestamp).await?;
+            }
+            RegisterKind::Heartbeat => {
+                if let Some(role) = msg.role {
+                    self.nodes.insert(
+                        node_addr.clone(),
+                        NodeInfo {
+                            node_id: node_id.clone(),
+                            role,
+                            monitor_addr,
+                            updated: timestamp,
+                        },
+                    );
+                }
+                //nodes without a role are origins of an older version
+                if matches!(msg.role, None | Some(Role::Origin)) {
+                    self.origins.insert(
+                        node_addr.clone(),
+                        OriginLoad {
+                            node_id: node_id.clone(),
+                            load: msg.load,
+                            updated: timestamp,
+                        },
+                    );
+                }
+                for channel_name in msg.channels {
+                    let lease = Lease {
+                        node_id: &node_id,
+                        addr: &node_addr,
+                        notify,
+                        acquire: false,
+                    };
+                    self.set_channel(channel_name, lease, timestamp).await?;
+                }
+            }
+            RegisterKind::Get => {
+                let mut resp = RegisterResp {
+                    id: msg.id,
+                    kind: RegisterRespKind::NOFOUND,
+                    payload: "".to_owned(),
+                    node_id: "".to_owned(),
+                };
+                if let Some(owner) = self.storage.get_channel(&msg.channel_name).await? {
+                    log::info!(
+                        "get {} found node:{} addr:{} last_timestamp:{}",
+                        msg.channel_name,
+                        owner.node_id,
+                        owner.addr,
+                        owner.timestamp
+                    );
+                    //the sweeper may not have run yet
+                    if timestamp.saturating_sub(owner.timestamp) < self.channel_ttl {
+                        resp = RegisterResp {
+                            id: msg.id,
+                            kind: RegisterRespKind::OK,
+                            payload: owner.addr,
+                            node_id: owner.node_id,
+                        };
+                    }
+                }
+                METRICS
+                    .lookups
+                    .with_label_values(&[kind_label(msg.kind), result_label(&resp)])
+                    .inc();
+                let buf: Bytes = resp.try_into().unwrap();
+                if let Some(outgoing) = outgoing {
+                    _ = outgoing.send(buf);
+                }
+            }
+            RegisterKind::PickOrigin => {
+                let picked = self
+                    .pick_origin(&msg.channel_name, msg.policy, timestamp)
+                    .await?;
+                let resp = match picked {
+                    Some((node_id, addr)) => RegisterResp {
+                        id: msg.id,
+                        kind: RegisterRespKind::OK,
+                        payload: addr,
+                        node_id,
+                    },
+                    None => RegisterResp {
+                        id: msg.id,
+                        kind: RegisterRespKind::NOFOUND,
+                        payload: "".to_owned(),
+                        node_id: "".to_owned(),
                     },
+                };
+                METRICS
+                    .lookups
+                    .with_label_values(&[kind_label(msg.kind), result_label(&resp)])
+                    .inc();
+                if let Some(outgoing) = outgoing {
+                    _ = outgoing.send(resp.try_into().unwrap());
+                }
+            }
+            RegisterKind::Delete => {
+                //the origin that lost the lease releases the channel too
+                match self.storage.get_channel(&msg.channel_name).await? {
+                    Some(owner) if owner.node_id == node_id => {}
+                    _ => return Ok(()),
+                }
+                if let Some(owner) = self.storage.remove_channel(&msg.channel_name).await? {
+                    self.dirty = true;
+                    self.emit(RegisterEvent::ChannelDown {
+                        channel: msg.channel_name,
+                        node_id: owner.node_id,
+                        reason: DownReason::Deleted,
+                    })
+                    .await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    //a set acquires the lease, a heartbeat only refreshes the lease its origin holds,
+    //or brings back one the register lost. leases are fenced by node_id: heartbeats and
+    //deletes of another origin are refused, the epoch only counts the handovers
+    async fn set_channel(
+        &mut self,
+        channel_name: String,
+        lease: Lease<'_>,
+        timestamp: u64,
+    ) -> Result<()> {
+        let old = self.storage.get_channel(&channel_name).await?;
+        let epoch = match &old {
+            None => 1,
+            Some(old) if old.node_id == lease.node_id => old.epoch,
+            Some(old) => {
+                let alive =
+                    old.confirmed && timestamp.saturating_sub(old.timestamp) < self.channel_ttl;
+                //the holder keeps it until it expires, even unconfirmed after a restore
+                if !lease.acquire {
+                    if alive {
+                        self.add_conflict(
+                            &channel_name,
+                            old,
+                            lease.node_id,
+                            ConflictKind::StaleHeartbeat,
+                            timestamp,
+                        );
+                        //the first revocation may have been lost
+                        if let Some(notify) = lease.notify {
+                            self.revoke(&channel_name, &old.node_id, notify);
+                        }
+                    }
+                    return Ok(());
+                }
+                if alive {
+                    self.add_conflict(
+                        &channel_name,
+                        old,
+                        lease.node_id,
+                        ConflictKind::Takeover,
+                        timestamp,
+                    );
                 }
+                if let Ok(notify) = old.notify.parse() {
+                    self.revoke(&channel_name, lease.node_id, notify);
+                }
+                old.epoch + 1
+            }
+        };
+        let notify = match (lease.notify, &old) {
+            (Some(notify), _) => notify.to_string(),
+            (None, Some(old)) if old.node_id == lease.node_id => old.notify.clone(),
+            (None, _) => "".to_owned(),
+        };
+        let owner = Owner {
+            node_id: lease.node_id.to_owned(),
+            addr: lease.addr.to_owned(),
+            timestamp,
+            confirmed: true,
+            epoch,
+            notify,
+        };
+        self.storage.set_channel(&channel_name, &owner).await?;
+        let refreshed = matches!(&old, Some(old) if old.node_id == owner.node_id
+            && old.addr == owner.addr
+            && old.confirmed
+            && old.epoch == owner.epoch
+            && old.notify == owner.notify);
+        if !refreshed {
+            self.dirty = true;
+        }
+        //a new channel, or one that moved to another origin
+        if !matches!(old, Some(old) if old.node_id == lease.node_id) {
+            self.emit(RegisterEvent::ChannelUp {
+                channel: channel_name,
+                node_id: lease.node_id.to_owned(),
+                addr: lease.addr.to_owned(),
+            })
+            .await;
+        }
+        Ok(())
+    }
+
+    //a published channel stays on its origin, otherwise the policy picks among the live origins
+    async fn pick_origin(
+        &mut self,
+        channel_name: &str,
+        policy: PickPolicy,
+        timestamp: u64,
+    ) -> Result<Option<(String, String)>> {
+        if let Some(owner) = self.storage.get_channel(channel_name).await? {
+            if timestamp.saturating_sub(owner.timestamp) < self.channel_ttl {
+                return Ok(Some((owner.node_id, owner.addr)));
+            }
+        }
+        let server_ttl = self.server_ttl;
+        let live = self
+            .origins
+            .iter_mut()
+            .filter(|(_, origin)| timestamp.saturating_sub(origin.updated) < server_ttl);
+        let picked = match policy {
+            PickPolicy::LeastLoaded => live.min_by_key(|(addr, origin)| {
+                (
+                    origin.load.channels,
+                    origin.load.ingress_bps,
+                    addr.to_owned(),
+                )
+            }),
+            //rendezvous hashing, only the channels of a leaving origin move
+            PickPolicy::ConsistentHash => live.max_by_key(|(_, origin)| {
+                hash(format!("{}/{}", origin.node_id, channel_name).as_bytes())
+            }),
+        };
+        Ok(picked.map(|(addr, origin)| {
+            //publishers picked before the next heartbeat spread over the origins
+            origin.load.channels += 1;
+            (origin.node_id.clone(), addr.clone())
+        }))
+    }
+
+    //the owner is told to release it, or its heartbeats would bring the channel back
+    async fn evict_channel(&mut self, channel_name: &str) -> Result<bool> {
+        let owner = match self.storage.remove_channel(channel_name).await? {
+            Some(owner) => owner,
+            None => return Ok(false),
+        };
+        self.dirty = true;
+        if let Ok(notify) = owner.notify.parse() {
+            self.revoke(channel_name, "", notify);
+        }
+        self.emit(RegisterEvent::ChannelDown {
+            channel: channel_name.to_owned(),
+            node_id: owner.node_id,
+            reason: DownReason::Evicted,
+        })
+        .await;
+        Ok(true)
+    }
+
+    fn add_conflict(
+        &mut self,
+        channel_name: &str,
+        owner: &Owner,
+        node_id: &str,
+        kind: ConflictKind,
+        timestamp: u64,
+    ) {
+        log::warn!(
+            "channel {} {:?} by {}, owner {} epoch {}",
+            channel_name,
+            kind,
+            node_id,
+            owner.node_id,
+            owner.epoch
+        );
+        let conflict = self
+            .conflicts
+            .entry((channel_name.to_owned(), node_id.to_owned()))
+            .or_insert_with(|| Conflict {
+                channel: channel_name.to_owned(),
+                owner: owner.node_id.clone(),
+                epoch: owner.epoch,
+                node_id: node_id.to_owned(),
+                kind,
+                first_seen: timestamp,
+                last_seen: timestamp,
+                count: 0,
+            });
+        conflict.owner = owner.node_id.clone();
+        conflict.epoch = owner.epoch;
+        conflict.kind = kind;
+        conflict.last_seen = timestamp;
+        conflict.count += 1;
+    }
+
+    fn revoke(&self, channel_name: &str, new_owner: &str, notify: SocketAddr) {
+        let resp = RegisterResp {
+            id: 0,
+            kind: RegisterRespKind::REVOKED,
+            payload: channel_name.to_owned(),
+            node_id: new_owner.to_owned(),
+        };
+        if let Some(outgoing) = &self.outgoing {
+            _ = outgoing.send((resp.try_into().unwrap(), notify));
+        }
+    }
+
+    async fn sweep(&mut self) -> Result<()> {
+        let timestamp = now();
+        let mut expired = vec![];
+        let (mut channels, mut servers) = (0, 0);
+        for (channel_name, owner) in self.storage.channels().await? {
+            if timestamp.saturating_sub(owner.timestamp) < self.channel_ttl {
+                channels += 1;
+                continue;
+            }
+            //with a shared storage only the register that removes it reports it
+            if self.storage.remove_channel(&channel_name).await?.is_some() {
+                expired.push(RegisterEvent::ChannelDown {
+                    channel: channel_name,
+                    node_id: owner.node_id,
+                    reason: DownReason::Expired,
+                });
             }
         }
+        for (addr, last_seen) in self.storage.servers().await? {
+            if timestamp.saturating_sub(last_seen) < self.server_ttl {
+                servers += 1;
+                continue;
+            }
+            if self.storage.remove_server(&addr).await? {
+                expired.push(RegisterEvent::ServerDown { addr });
+            }
+        }
+        METRICS.channels.set(channels);
+        METRICS.servers.set(servers);
+        let server_ttl = self.server_ttl;
+        self.origins
+            .retain(|_, origin| timestamp.saturating_sub(origin.updated) < server_ttl);
+        self.nodes
+            .retain(|_, node| timestamp.saturating_sub(node.updated) < server_ttl);
+        self.conflicts
+            .retain(|_, conflict| timestamp.saturating_sub(conflict.last_seen) < CONFLICT_TTL);
+        if !expired.is_empty() {
+            self.dirty = true;
+        }
+        for event in expired {
+            self.emit(event).await;
+        }
+        Ok(())
+    }
+
+    //written aside and renamed, so a crash never leaves a torn snapshot
+    async fn save(&mut self) -> Result<()> {
+        let path = match &self.snapshot_path {
+            Some(path) if self.dirty || self.saved.elapsed() >= SNAPSHOT_REFRESH => path.clone(),
+            _ => return Ok(()),
+        };
+        let snapshot = Snapshot {
+            channels: self.storage.channels().await?,
+            servers: self.storage.servers().await?,
+        };
+        let tmp = path.with_extension("tmp");
+        //on the blocking pool, not on a runtime thread
+        tokio::fs::write(&tmp, serde_json::to_vec(&snapshot)?).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        self.dirty = false;
+        self.saved = Instant::now();
+        Ok(())
+    }
+
+    async fn emit(&mut self, event: RegisterEvent) {
+        log::info!("{:?}", event);
+        if let Err(e) = self.storage.publish(&event).await {
+            log::error!("publish register event err {}", e);
+        }
+        let record = EventRecord {
+            id: self.next_event_id,
+            event,
+        };
+        self.next_event_id += 1;
+        if self.history.len() == EVENT_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(record.clone());
+        _ = self.events.send(record);
     }
 }
+
+//nodes that listen on an unspecified ip are reached at the source of their messages
+fn with_source_ip(advertised: &str, source: SocketAddr) -> String {
+    match advertised.parse::<SocketAddr>() {
+        Ok(advertised) if advertised.ip().is_unspecified() => {
+            SocketAddr::new(source.ip(), advertised.port()).to_string()
+        }
+        _ => advertised.to_owned(),
+    }
+}
+
+fn kind_label(kind: RegisterKind) -> &'static str {
+    match kind {
+        RegisterKind::Set => "set",
+        RegisterKind::Get => "get",
+        RegisterKind::Delete => "delete",
+        RegisterKind::Heartbeat => "heartbeat",
+        RegisterKind::PickOrigin => "pick_origin",
+    }
+}
+
+fn result_label(resp: &RegisterResp) -> &'static str {
+    match resp.kind {
+        RegisterRespKind::OK => "found",
+        RegisterRespKind::NOFOUND | RegisterRespKind::REVOKED => "not_found",
+    }
+}
+
+//stable across registers and restarts, unlike the std hasher
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+    //fnv alone barely mixes the high bits of keys sharing a suffix
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
@@ -0,0 +1,202 @@
+use crate::register::{Owner, RegisterEvent};
+use anyhow::Result;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+
+//where the channel leases and the known servers are kept
+pub trait Storage: Send + 'static {
+    fn get_channel(
+        &mut self,
+        channel_name: &str,
+    ) -> impl Future<Output = Result<Option<Owner>>> + Send;
+    //returns the owner it replaced
+    fn set_channel(
+        &mut self,
+        channel_name: &str,
+        owner: &Owner,
+    ) -> impl Future<Output = Result<Option<Owner>>> + Send;
+    //returns the removed owner, `None` when another register removed it first
+    fn remove_channel(
+        &mut self,
+        channel_name: &str,
+    ) -> impl Future<Output = Result<Option<Owner>>> + Send;
+    fn channels(&mut self) -> impl Future<Output = Result<HashMap<String, Owner>>> + Send;
+    //returns true for a server not seen before
+    fn touch_server(
+        &mut self,
+        addr: &str,
+        timestamp: u64,
+    ) -> impl Future<Output = Result<bool>> + Send;
+    fn remove_server(&mut self, addr: &str) -> impl Future<Output = Result<bool>> + Send;
+    fn servers(&mut self) -> impl Future<Output = Result<HashMap<String, u64>>> + Send;
+    //make a change visible to other services
+    fn publish(&mut self, _event: &RegisterEvent) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    channel_map: HashMap<String, Owner>,
+    servers: HashMap<String, u64>,
+}
+
+impl Storage for MemoryStorage {
+    async fn get_channel(&mut self, channel_name: &str) -> Result<Option<Owner>> {
+        Ok(self.channel_map.get(channel_name).cloned())
+    }
+
+    async fn set_channel(&mut self, channel_name: &str, owner: &Owner) -> Result<Option<Owner>> {
+        Ok(self
+            .channel_map
+            .insert(channel_name.to_owned(), owner.clone()))
+    }
+
+    async fn remove_channel(&mut self, channel_name: &str) -> Result<Option<Owner>> {
+        Ok(self.channel_map.remove(channel_name))
+    }
+
+    async fn channels(&mut self) -> Result<HashMap<String, Owner>> {
+        Ok(self.channel_map.clone())
+    }
+
+    async fn touch_server(&mut self, addr: &str, timestamp: u64) -> Result<bool> {
+        Ok(self.servers.insert(addr.to_owned(), timestamp).is_none())
+    }
+
+    async fn remove_server(&mut self, addr: &str) -> Result<bool> {
+        Ok(self.servers.remove(addr).is_some())
+    }
+
+    async fn servers(&mut self) -> Result<HashMap<String, u64>> {
+        Ok(self.servers.clone())
+    }
+}
+
+//leases as json under `<prefix>:channel:<name>`, servers in the `<prefix>:servers` hash,
+//changes published on `<prefix>:events`
+pub struct RedisStorage {
+    conn: ConnectionManager,
+    prefix: String,
+    //keys outlive the register ttl, they only expire by themselves when no register sweeps
+    key_ttl: usize,
+}
+
+impl RedisStorage {
+    pub async fn connect(url: &str, prefix: &str, key_ttl: Duration) -> Result<Self> {
+        let client = redis::Client::open(url)?;
+        let conn = ConnectionManager::new(client).await?;
+        Ok(Self {
+            conn,
+            prefix: prefix.to_owned(),
+            key_ttl: key_ttl.as_secs().max(1) as usize,
+        })
+    }
+
+    fn channel_key(&self, channel_name: &str) -> String {
+        format!("{}:channel:{}", self.prefix, channel_name)
+    }
+
+    fn servers_key(&self) -> String {
+        format!("{}:servers", self.prefix)
+    }
+}
+
+fn parse_owner(value: Option<String>) -> Result<Option<Owner>> {
+    match value {
+        Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+        None => Ok(None),
+    }
+}
+
+impl Storage for RedisStorage {
+    async fn get_channel(&mut self, channel_name: &str) -> Result<Option<Owner>> {
+        let key = self.channel_key(channel_name);
+        let value: Option<String> = self.conn.get(key).await?;
+        parse_owner(value)
+    }
+
+    async fn set_channel(&mut self, channel_name: &str, owner: &Owner) -> Result<Option<Owner>> {
+        let key = self.channel_key(channel_name);
+        let (old, ()): (Option<String>, ()) = redis::pipe()
+            .atomic()
+            .get(&key)
+            .set_ex(&key, serde_json::to_string(owner)?, self.key_ttl)
+            .query_async(&mut self.conn)
+            .await?;
+        parse_owner(old)
+    }
+
+    async fn remove_channel(&mut self, channel_name: &str) -> Result<Option<Owner>> {
+        let key = self.channel_key(channel_name);
+        let (old, removed): (Option<String>, u32) = redis::pipe()
+            .atomic()
+            .get(&key)
+            .del(&key)
+            .query_async(&mut self.conn)
+            .await?;
+        if removed == 0 {
+            return Ok(None);
+        }
+        parse_owner(old)
+    }
+
+    async fn channels(&mut self) -> Result<HashMap<String, Owner>> {
+        let pattern = self.channel_key("*");
+        let keys: Vec<String> = {
+            let mut iter = self.conn.scan_match::<_, String>(pattern).await?;
+            let mut keys = vec![];
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+            keys
+        };
+        if keys.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let values: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(&keys)
+            .query_async(&mut self.conn)
+            .await?;
+        let prefix_len = self.channel_key("").len();
+        let mut channels = HashMap::new();
+        for (key, value) in keys.into_iter().zip(values) {
+            //expired between the scan and the get
+            if let Some(owner) = parse_owner(value)? {
+                channels.insert(key[prefix_len..].to_owned(), owner);
+            }
+        }
+        Ok(channels)
+    }
+
+    async fn touch_server(&mut self, addr: &str, timestamp: u64) -> Result<bool> {
+        let key = self.servers_key();
+        let (added, ()): (u32, ()) = redis::pipe()
+            .hset(&key, addr, timestamp)
+            .expire(&key, self.key_ttl)
+            .query_async(&mut self.conn)
+            .await?;
+        Ok(added == 1)
+    }
+
+    async fn remove_server(&mut self, addr: &str) -> Result<bool> {
+        let removed: u32 = self.conn.hdel(self.servers_key(), addr).await?;
+        Ok(removed == 1)
+    }
+
+    async fn servers(&mut self) -> Result<HashMap<String, u64>> {
+        Ok(self.conn.hgetall(self.servers_key()).await?)
+    }
+
+    async fn publish(&mut self, event: &RegisterEvent) -> Result<()> {
+        let channel = format!("{}:events", self.prefix);
+        let () = self
+            .conn
+            .publish(channel, serde_json::to_string(event)?)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time;
+use xcore::register_client::{RegisterClient, RegisterClientConfig};
+use xlive_register::Config;
+
+const HTTP_ADDR: &str = "127.0.0.1:29496";
+
+//status and json body
+async fn http(method: &str, path: &str, token: Option<&str>) -> (u16, serde_json::Value) {
+    let mut stream = TcpStream::connect(HTTP_ADDR).await.unwrap();
+    let auth = token
+        .map(|token| format!("Authorization: Bearer {}\r\n", token))
+        .unwrap_or_default();
+    let request = format!(
+        "{} {} HTTP/1.0\r\nHost: {}\r\n{}\r\n",
+        method, path, HTTP_ADDR, auth
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split("\r\n\r\n").nth(1).unwrap_or("");
+    (status, serde_json::from_str(body).unwrap_or_default())
+}
+
+async fn origin(node_id: &str, advertise: &str) -> RegisterClient {
+    let mut config = RegisterClientConfig::new("127.0.0.1:29396");
+    config.node_id = node_id.to_owned();
+    config.advertise = advertise.to_owned();
+    RegisterClient::connect(config).await.unwrap()
+}
+
+#[tokio::test]
+async fn admin_api() {
+    let mut config = Config::new("127.0.0.1:29396", HTTP_ADDR.parse().unwrap());
+    config.admin_token = Some("secret".to_owned());
+    tokio::spawn(xlive_register::run(config));
+    time::sleep(Duration::from_millis(200)).await;
+
+    let first = origin("origin-a", "10.0.0.1:9878").await;
+    let second = origin("origin-b", "10.0.0.2:9878").await;
+    for i in 0..5 {
+        first.set(&format!("live/{}", i));
+    }
+    first.set("other/x");
+    second.set("live/b");
+    time::sleep(Duration::from_millis(300)).await;
+
+    let (code, body) = http("GET", "/channels?prefix=live%2F&offset=1&limit=2", None).await;
+    assert_eq!(code, 200);
+    assert_eq!(body["total"], 6);
+    let channels = body["channels"].as_array().unwrap();
+    assert_eq!(channels.len(), 2);
+    assert_eq!(channels[0]["channel"], "live/1");
+    assert_eq!(channels[0]["epoch"], 1);
+
+    let (code, body) = http("GET", "/channels/live/b", None).await;
+    assert_eq!(code, 200);
+    assert_eq!(body["node_id"], "origin-b");
+    assert!(body["age"].as_u64().unwrap() < 2);
+    let (code, _) = http("GET", "/channels/live/missing", None).await;
+    assert_eq!(code, 404);
+
+    //eviction needs the token
+    let (code, _) = http("DELETE", "/channels/live/b", None).await;
+    assert_eq!(code, 401);
+    let (code, _) = http("DELETE", "/channels/live/b", Some("wrong")).await;
+    assert_eq!(code, 401);
+
+    let mut revoked = second.revoked();
+    let (code, body) = http("DELETE", "/channels/live/b", Some("secret")).await;
+    assert_eq!(code, 200);
+    assert_eq!(body["evicted"][0], "live/b");
+    let channel_name = time::timeout(Duration::from_secs(2), revoked.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(channel_name, "live/b");
+    let (code, _) = http("GET", "/channels/live/b", None).await;
+    assert_eq!(code, 404);
+
+    //a server goes with all its channels
+    let (code, body) = http("DELETE", "/servers/10.0.0.1:9878", Some("secret")).await;
+    assert_eq!(code, 200);
+    assert_eq!(body["evicted"].as_array().unwrap().len(), 6);
+    let (_, body) = http("GET", "/channels", None).await;
+    assert_eq!(body["total"], 0);
+    let (code, _) = http("DELETE", "/servers/10.0.0.9:9878", Some("secret")).await;
+    assert_eq!(code, 404);
+}
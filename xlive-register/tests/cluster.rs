@@ -0,0 +1,74 @@
+use std::time::Duration;
+use tokio::time;
+use xcore::register_client::{Origin, RegisterClient, RegisterClientConfig};
+use xlive_register::Config;
+
+//three registers on localhost, each one a peer of the others
+async fn start_cluster(base_port: u16) -> Vec<String> {
+    let addrs: Vec<String> = (0..3)
+        .map(|i| format!("127.0.0.1:{}", base_port + i))
+        .collect();
+    for (i, addr) in addrs.iter().enumerate() {
+        let http_addr = format!("127.0.0.1:{}", base_port + 100 + i as u16);
+        let mut config = Config::new(addr, http_addr.parse().unwrap());
+        config.peers = addrs.iter().filter(|peer| *peer != addr).cloned().collect();
+        tokio::spawn(xlive_register::run(config));
+    }
+    time::sleep(Duration::from_millis(200)).await;
+    addrs
+}
+
+async fn client(addrs: &[String], node_id: &str) -> RegisterClient {
+    let mut config = RegisterClientConfig::new(&addrs.join(","));
+    config.node_id = node_id.to_owned();
+    if !node_id.is_empty() {
+        config.advertise = format!("10.0.0.1:{}", 9878);
+    }
+    config.timeout = Duration::from_millis(200);
+    config.found_ttl = Duration::ZERO;
+    config.not_found_ttl = Duration::ZERO;
+    RegisterClient::connect(config).await.unwrap()
+}
+
+//polls the register until the lookup gives the expected owner
+async fn wait_for(client: &RegisterClient, channel_name: &str, node_id: Option<&str>) -> bool {
+    for _ in 0..20 {
+        let found = client.lookup(channel_name).await.unwrap();
+        if found
+            .as_ref()
+            .map(|origin: &Origin| origin.node_id.as_str())
+            == node_id
+        {
+            return true;
+        }
+        time::sleep(Duration::from_millis(100)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn leases_are_replicated() {
+    let registers = start_cluster(29336).await;
+    let origin = client(&registers[..1], "origin-1").await;
+    let player = client(&registers[2..], "").await;
+
+    origin.set("live/room");
+    assert!(wait_for(&player, "live/room", Some("origin-1")).await);
+    let found = player.lookup("live/room").await.unwrap().unwrap();
+    assert_eq!(found.addr, "10.0.0.1:9878");
+
+    origin.delete("live/room");
+    assert!(wait_for(&player, "live/room", None).await);
+}
+
+#[tokio::test]
+async fn lookups_fail_over() {
+    let registers = start_cluster(29346).await;
+    let origin = client(&registers[..1], "origin-2").await;
+    origin.set("live/other");
+
+    //nothing listens on the first address
+    let endpoints = vec!["127.0.0.1:29359".to_owned(), registers[1].clone()];
+    let player = client(&endpoints, "").await;
+    assert!(wait_for(&player, "live/other", Some("origin-2")).await);
+}
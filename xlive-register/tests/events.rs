@@ -0,0 +1,114 @@
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time;
+use xcore::register_client::{RegisterClient, RegisterClientConfig};
+use xlive_register::Config;
+
+const HTTP_ADDR: &str = "127.0.0.1:29506";
+
+//the (id, event, data) of the events on the stream
+async fn events(
+    http_addr: &str,
+    last_event_id: Option<u64>,
+    count: usize,
+) -> Vec<(u64, String, serde_json::Value)> {
+    let stream = TcpStream::connect(http_addr).await.unwrap();
+    let mut stream = BufReader::new(stream);
+    let resume = last_event_id
+        .map(|id| format!("Last-Event-ID: {}\r\n", id))
+        .unwrap_or_default();
+    let request = format!(
+        "GET /events HTTP/1.1\r\nHost: {}\r\n{}\r\n",
+        http_addr, resume
+    );
+    stream
+        .get_mut()
+        .write_all(request.as_bytes())
+        .await
+        .unwrap();
+
+    let mut events = vec![];
+    let (mut id, mut event) = (0, String::new());
+    let mut line = String::new();
+    while events.len() < count {
+        line.clear();
+        time::timeout(Duration::from_secs(3), stream.read_line(&mut line))
+            .await
+            .unwrap()
+            .unwrap();
+        //chunked framing lines are skipped with the rest
+        if let Some(value) = line.trim_end().strip_prefix("id: ") {
+            id = value.parse().unwrap();
+        } else if let Some(value) = line.trim_end().strip_prefix("event: ") {
+            event = value.to_owned();
+        } else if let Some(value) = line.trim_end().strip_prefix("data: ") {
+            events.push((id, event.clone(), serde_json::from_str(value).unwrap()));
+        }
+    }
+    events
+}
+
+#[tokio::test]
+async fn channel_events_are_streamed_and_resumable() {
+    let config = Config::new("127.0.0.1:29406", HTTP_ADDR.parse().unwrap());
+    tokio::spawn(xlive_register::run(config));
+    time::sleep(Duration::from_millis(200)).await;
+
+    let mut origin = RegisterClientConfig::new("127.0.0.1:29406");
+    origin.node_id = "origin-a".to_owned();
+    origin.advertise = "10.0.0.1:9878".to_owned();
+    let origin = RegisterClient::connect(origin).await.unwrap();
+
+    let live = tokio::spawn(events(HTTP_ADDR, None, 3));
+    time::sleep(Duration::from_millis(200)).await;
+    origin.set("live/room");
+    time::sleep(Duration::from_millis(100)).await;
+    origin.delete("live/room");
+
+    let live = live.await.unwrap();
+    assert_eq!(live[0].1, "server_up");
+    assert_eq!(live[1].1, "channel_up");
+    assert_eq!(live[1].2["channel"], "live/room");
+    assert_eq!(live[2].1, "channel_down");
+    assert_eq!(live[2].2["reason"], "deleted");
+    assert!(live[0].0 < live[1].0 && live[1].0 < live[2].0);
+
+    //a client that saw the first event gets the two others again
+    let resumed = events(HTTP_ADDR, Some(live[0].0), 2).await;
+    assert_eq!(resumed[0].0, live[1].0);
+    assert_eq!(resumed[1].0, live[2].0);
+}
+
+#[tokio::test]
+async fn silent_origins_are_expired() {
+    let mut config = Config::new("127.0.0.1:29407", "127.0.0.1:29507".parse().unwrap());
+    config.channel_ttl = Duration::from_secs(1);
+    config.server_ttl = Duration::from_secs(1);
+    tokio::spawn(xlive_register::run(config));
+    time::sleep(Duration::from_millis(200)).await;
+
+    let live = tokio::spawn(events("127.0.0.1:29507", None, 4));
+    time::sleep(Duration::from_millis(200)).await;
+    let mut origin = RegisterClientConfig::new("127.0.0.1:29407");
+    origin.node_id = "origin-a".to_owned();
+    origin.advertise = "10.0.0.1:9878".to_owned();
+    let origin = RegisterClient::connect(origin).await.unwrap();
+    origin.set("live/room");
+    time::sleep(Duration::from_millis(100)).await;
+    //no more heartbeats
+    drop(origin);
+
+    let live = time::timeout(Duration::from_secs(5), live)
+        .await
+        .unwrap()
+        .unwrap();
+    let names: Vec<&str> = live.iter().map(|(_, name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        ["server_up", "channel_up", "channel_down", "server_down"]
+    );
+    assert_eq!(live[2].2["channel"], "live/room");
+    assert_eq!(live[2].2["reason"], "expired");
+    assert_eq!(live[3].2["addr"], "10.0.0.1:9878");
+}
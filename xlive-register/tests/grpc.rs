@@ -0,0 +1,121 @@
+use std::time::Duration;
+use tokio::time;
+use tonic::transport::Channel;
+use xlive_register::grpc::{
+    register_service_client::RegisterServiceClient, EventKind, HeartbeatRequest, LeaseRequest,
+    ListServersRequest, LookupRequest, WatchRequest,
+};
+use xlive_register::Config;
+
+const GRPC_ADDR: &str = "127.0.0.1:29516";
+
+fn lease(channel: &str, node_id: &str, addr: &str) -> LeaseRequest {
+    LeaseRequest {
+        channel: channel.to_owned(),
+        node_id: node_id.to_owned(),
+        addr: addr.to_owned(),
+    }
+}
+
+async fn lookup(client: &mut RegisterServiceClient<Channel>, channel: &str) -> (bool, String) {
+    let reply = client
+        .lookup(LookupRequest {
+            channel: channel.to_owned(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    (reply.found, reply.node_id)
+}
+
+#[tokio::test]
+async fn leases_lookups_and_events_over_grpc() {
+    let mut config = Config::new("127.0.0.1:29416", "127.0.0.1:29526".parse().unwrap());
+    config.grpc_addr = Some(GRPC_ADDR.parse().unwrap());
+    tokio::spawn(xlive_register::run(config));
+    time::sleep(Duration::from_millis(200)).await;
+
+    let mut client = RegisterServiceClient::connect(format!("http://{}", GRPC_ADDR))
+        .await
+        .unwrap();
+    let mut events = client
+        .watch(WatchRequest { last_event_id: 0 })
+        .await
+        .unwrap()
+        .into_inner();
+
+    let first = client
+        .acquire(lease("live/room", "origin-a", "10.0.0.1:9878"))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(first.owner, "origin-a");
+    assert_eq!(
+        lookup(&mut client, "live/room").await,
+        (true, "origin-a".to_owned())
+    );
+    assert!(!lookup(&mut client, "live/other").await.0);
+
+    //a takeover raises the epoch, the old owner learns it from its heartbeat
+    let second = client
+        .acquire(lease("live/room", "origin-b", "10.0.0.2:9878"))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(second.owner, "origin-b");
+    assert_eq!(second.epoch, first.epoch + 1);
+    let heartbeat = client
+        .heartbeat(HeartbeatRequest {
+            node_id: "origin-a".to_owned(),
+            addr: "10.0.0.1:9878".to_owned(),
+            channels: vec!["live/room".to_owned()],
+            load: None,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(heartbeat.revoked, vec!["live/room".to_owned()]);
+
+    //only the owner releases it
+    client
+        .release(lease("live/room", "origin-a", "10.0.0.1:9878"))
+        .await
+        .unwrap();
+    assert!(lookup(&mut client, "live/room").await.0);
+    client
+        .release(lease("live/room", "origin-b", "10.0.0.2:9878"))
+        .await
+        .unwrap();
+    assert!(!lookup(&mut client, "live/room").await.0);
+
+    let servers = client
+        .list_servers(ListServersRequest {
+            prefix: "10.0.0.".to_owned(),
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .servers;
+    let addrs: Vec<&str> = servers.iter().map(|server| server.addr.as_str()).collect();
+    assert_eq!(addrs, vec!["10.0.0.1:9878", "10.0.0.2:9878"]);
+
+    let mut channel_events = vec![];
+    while channel_events.len() < 3 {
+        let event = time::timeout(Duration::from_secs(3), events.message())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        if event.channel == "live/room" {
+            channel_events.push((event.kind(), event.node_id));
+        }
+    }
+    assert_eq!(
+        channel_events,
+        vec![
+            (EventKind::ChannelUp, "origin-a".to_owned()),
+            (EventKind::ChannelUp, "origin-b".to_owned()),
+            (EventKind::ChannelDown, "origin-b".to_owned()),
+        ]
+    );
+}
@@ -0,0 +1,158 @@
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time;
+use xcore::register_client::{RegisterClient, RegisterClientConfig};
+use xlive_register::Config;
+
+async fn client(addr: &str, node_id: &str, advertise: &str) -> RegisterClient {
+    let mut config = RegisterClientConfig::new(addr);
+    config.node_id = node_id.to_owned();
+    config.advertise = advertise.to_owned();
+    config.timeout = Duration::from_millis(200);
+    config.found_ttl = Duration::ZERO;
+    config.not_found_ttl = Duration::ZERO;
+    config.heartbeat_interval = Duration::from_millis(100);
+    RegisterClient::connect(config).await.unwrap()
+}
+
+async fn http_get(addr: &str, path: &str) -> serde_json::Value {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, addr);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    serde_json::from_str(body).unwrap()
+}
+
+//a raw heartbeat of `channel_name`, from the returned socket
+async fn heartbeat(addr: &str, node_id: &str, channel_name: &str) -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let heartbeat = xcore::register::Register {
+        id: 0,
+        kind: xcore::register::RegisterKind::Heartbeat,
+        channel_name: "".to_owned(),
+        channels: vec![channel_name.to_owned()],
+        node_id: node_id.to_owned(),
+        addr: "10.0.0.2:9878".to_owned(),
+        relayed: false,
+        load: Default::default(),
+        policy: Default::default(),
+        role: None,
+        monitor_addr: "".to_owned(),
+    };
+    let buf: bytes::Bytes = heartbeat.try_into().unwrap();
+    socket.send_to(&buf, addr).await.unwrap();
+    socket
+}
+
+#[tokio::test]
+async fn a_new_owner_revokes_the_old_one() {
+    let config = Config::new("127.0.0.1:29376", "127.0.0.1:29476".parse().unwrap());
+    tokio::spawn(xlive_register::run(config));
+    time::sleep(Duration::from_millis(200)).await;
+
+    let first = client("127.0.0.1:29376", "origin-a", "10.0.0.1:9878").await;
+    let second = client("127.0.0.1:29376", "origin-b", "10.0.0.2:9878").await;
+    let mut revoked = first.revoked();
+
+    first.set("live/room");
+    time::sleep(Duration::from_millis(200)).await;
+    second.set("live/room");
+
+    let channel_name = time::timeout(Duration::from_secs(2), revoked.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(channel_name, "live/room");
+
+    //the old owner deleting the channel does not drop the new lease
+    first.delete("live/room");
+    time::sleep(Duration::from_millis(300)).await;
+    let owner = second.lookup("live/room").await.unwrap().unwrap();
+    assert_eq!(owner.node_id, "origin-b");
+
+    let channels = http_get("127.0.0.1:29476", "/channels_info").await;
+    assert_eq!(channels["channels"]["live/room"]["epoch"], 2);
+    let conflicts = http_get("127.0.0.1:29476", "/conflicts").await;
+    let conflicts = conflicts["conflicts"].as_array().unwrap();
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0]["kind"], "takeover");
+    assert_eq!(conflicts[0]["owner"], "origin-a");
+    assert_eq!(conflicts[0]["node_id"], "origin-b");
+}
+
+#[tokio::test]
+async fn stale_heartbeats_are_rejected() {
+    let config = Config::new("127.0.0.1:29377", "127.0.0.1:29477".parse().unwrap());
+    tokio::spawn(xlive_register::run(config));
+    time::sleep(Duration::from_millis(200)).await;
+
+    let owner = client("127.0.0.1:29377", "origin-a", "10.0.0.1:9878").await;
+    owner.set("live/room");
+    time::sleep(Duration::from_millis(200)).await;
+
+    //a raw heartbeat from an origin that never acquired the lease
+    let stale = heartbeat("127.0.0.1:29377", "origin-b", "live/room").await;
+
+    //it is told to release
+    let mut resp = vec![0u8; 1500];
+    let (n, _) = time::timeout(Duration::from_secs(2), stale.recv_from(&mut resp))
+        .await
+        .unwrap()
+        .unwrap();
+    let resp = xcore::register::RegisterResp::try_from(&resp[..n]).unwrap();
+    assert!(matches!(
+        resp.kind,
+        xcore::register::RegisterRespKind::REVOKED
+    ));
+    assert_eq!(resp.payload, "live/room");
+    assert_eq!(resp.node_id, "origin-a");
+
+    let found = owner.lookup("live/room").await.unwrap().unwrap();
+    assert_eq!(found.node_id, "origin-a");
+    let conflicts = http_get("127.0.0.1:29477", "/conflicts").await;
+    assert_eq!(conflicts["conflicts"][0]["kind"], "stale_heartbeat");
+}
+
+#[tokio::test]
+async fn heartbeats_do_not_take_an_unconfirmed_lease() {
+    //origin-a held the lease before the restart, it has not sent a heartbeat since
+    let path = std::env::temp_dir().join("xlive-register-lease-29378.json");
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let snapshot = serde_json::json!({
+        "channels": { "live/room": {
+            "node_id": "origin-a",
+            "addr": "10.0.0.1:9878",
+            "timestamp": timestamp,
+            "confirmed": true,
+            "epoch": 3,
+        }},
+        "servers": {},
+    });
+    std::fs::write(&path, snapshot.to_string()).unwrap();
+    let mut config = Config::new("127.0.0.1:29378", "127.0.0.1:29478".parse().unwrap());
+    config.snapshot = Some(path.clone());
+    tokio::spawn(xlive_register::run(config));
+    time::sleep(Duration::from_millis(200)).await;
+
+    let other = heartbeat("127.0.0.1:29378", "origin-b", "live/room").await;
+    time::sleep(Duration::from_millis(200)).await;
+    let channels = http_get("127.0.0.1:29478", "/channels_info").await;
+    let owner = &channels["channels"]["live/room"];
+    assert_eq!(owner["node_id"], "origin-a");
+    assert_eq!(owner["confirmed"], false);
+    assert_eq!(owner["epoch"], 3);
+    //not a stale heartbeat either, the holder may be gone
+    let mut resp = vec![0u8; 1500];
+    assert!(
+        time::timeout(Duration::from_millis(300), other.recv_from(&mut resp))
+            .await
+            .is_err()
+    );
+    _ = std::fs::remove_file(path);
+}
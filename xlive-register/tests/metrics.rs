@@ -0,0 +1,51 @@
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time;
+use xcore::register_client::{RegisterClient, RegisterClientConfig};
+use xlive_register::Config;
+
+const HTTP_ADDR: &str = "127.0.0.1:29566";
+
+async fn metrics() -> String {
+    let mut stream = TcpStream::connect(HTTP_ADDR).await.unwrap();
+    let request = format!("GET /metrics HTTP/1.0\r\nHost: {}\r\n\r\n", HTTP_ADDR);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.0 200"));
+    response
+}
+
+#[tokio::test]
+async fn metrics_count_messages_and_lookups() {
+    let config = Config::new("127.0.0.1:29556", HTTP_ADDR.parse().unwrap());
+    tokio::spawn(xlive_register::run(config));
+    time::sleep(Duration::from_millis(200)).await;
+
+    let mut config = RegisterClientConfig::new("127.0.0.1:29556");
+    config.node_id = "origin-a".to_owned();
+    config.advertise = "10.0.0.1:9878".to_owned();
+    let client = RegisterClient::connect(config).await.unwrap();
+    client.set("live/room");
+    time::sleep(Duration::from_millis(100)).await;
+    assert!(client.lookup("live/room").await.unwrap().is_some());
+    assert!(client.lookup("live/other").await.unwrap().is_none());
+    //the gauges are set by the sweeper
+    time::sleep(Duration::from_millis(1200)).await;
+
+    let text = metrics().await;
+    for line in [
+        "xlive_register_messages_total{kind=\"set\"} 1",
+        "xlive_register_messages_total{kind=\"get\"} 2",
+        "xlive_register_lookups_total{kind=\"get\",result=\"found\"} 1",
+        "xlive_register_lookups_total{kind=\"get\",result=\"not_found\"} 1",
+        "xlive_register_channels 1",
+        //the origin and the source of the lookups
+        "xlive_register_servers 2",
+        //the client side of the same lookups
+        "xlive_register_requests_total{kind=\"lookup\",result=\"found\"} 1",
+    ] {
+        assert!(text.contains(line), "{} missing from\n{}", line, text);
+    }
+}
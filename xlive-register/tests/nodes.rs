@@ -0,0 +1,48 @@
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time;
+use xcore::register::{PickPolicy, Role};
+use xcore::register_client::{RegisterClient, RegisterClientConfig};
+use xlive_register::Config;
+
+const HTTP_ADDR: &str = "127.0.0.1:29536";
+
+async fn servers() -> Vec<serde_json::Value> {
+    let mut stream = TcpStream::connect(HTTP_ADDR).await.unwrap();
+    let request = format!("GET /servers_info HTTP/1.0\r\nHost: {}\r\n\r\n", HTTP_ADDR);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    let value: serde_json::Value = serde_json::from_str(body).unwrap();
+    value["servers"].as_array().unwrap().clone()
+}
+
+#[tokio::test]
+async fn nodes_announce_their_role_and_monitor() {
+    let config = Config::new("127.0.0.1:29436", HTTP_ADDR.parse().unwrap());
+    tokio::spawn(xlive_register::run(config));
+    time::sleep(Duration::from_millis(200)).await;
+
+    let mut config = RegisterClientConfig::new("127.0.0.1:29436");
+    config.role = Some(Role::Edge);
+    config.monitor_addr = "[::]:3032".to_owned();
+    config.heartbeat_interval = Duration::from_millis(100);
+    let edge = RegisterClient::connect(config).await.unwrap();
+    time::sleep(Duration::from_millis(300)).await;
+
+    let servers = servers().await;
+    assert_eq!(servers.len(), 1);
+    assert_eq!(servers[0]["role"], "edge");
+    //the unspecified ip is replaced with the source of the heartbeats
+    assert_eq!(servers[0]["monitor_addr"], "127.0.0.1:3032");
+    assert!(servers[0].get("load").is_none());
+
+    //an edge is not an origin new publishers can go to
+    let picked = edge
+        .pick_origin("live/new", PickPolicy::LeastLoaded)
+        .await
+        .unwrap();
+    assert!(picked.is_none());
+}
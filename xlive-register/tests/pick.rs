@@ -0,0 +1,84 @@
+use std::time::Duration;
+use tokio::time;
+use xcore::register::PickPolicy;
+use xcore::register_client::{RegisterClient, RegisterClientConfig};
+use xlive_register::Config;
+
+async fn origin(addr: &str, node_id: &str, advertise: &str) -> RegisterClient {
+    let mut config = RegisterClientConfig::new(addr);
+    config.node_id = node_id.to_owned();
+    config.advertise = advertise.to_owned();
+    config.report_load = true;
+    config.heartbeat_interval = Duration::from_millis(100);
+    RegisterClient::connect(config).await.unwrap()
+}
+
+#[tokio::test]
+async fn new_publishers_go_to_the_least_loaded_origin() {
+    let config = Config::new("127.0.0.1:29386", "127.0.0.1:29486".parse().unwrap());
+    tokio::spawn(xlive_register::run(config));
+    time::sleep(Duration::from_millis(200)).await;
+
+    let busy = origin("127.0.0.1:29386", "origin-a", "10.0.0.1:9878").await;
+    let _idle = origin("127.0.0.1:29386", "origin-b", "10.0.0.2:9878").await;
+    busy.set("live/one");
+    busy.set("live/two");
+    time::sleep(Duration::from_millis(300)).await;
+
+    let edge = RegisterClient::connect(RegisterClientConfig::new("127.0.0.1:29386"))
+        .await
+        .unwrap();
+    let picked = edge
+        .pick_origin("live/new", PickPolicy::LeastLoaded)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(picked.node_id, "origin-b");
+    assert_eq!(picked.addr, "10.0.0.2:9878");
+
+    //a published channel stays where it is
+    let picked = edge
+        .pick_origin("live/one", PickPolicy::LeastLoaded)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(picked.node_id, "origin-a");
+}
+
+#[tokio::test]
+async fn consistent_hash_is_stable() {
+    let config = Config::new("127.0.0.1:29387", "127.0.0.1:29487".parse().unwrap());
+    tokio::spawn(xlive_register::run(config));
+    time::sleep(Duration::from_millis(200)).await;
+
+    let mut origins = vec![];
+    for i in 0..3 {
+        let node_id = format!("origin-{}", i);
+        let advertise = format!("10.0.0.{}:9878", i);
+        origins.push(origin("127.0.0.1:29387", &node_id, &advertise).await);
+    }
+    time::sleep(Duration::from_millis(300)).await;
+
+    let edge = RegisterClient::connect(RegisterClientConfig::new("127.0.0.1:29387"))
+        .await
+        .unwrap();
+    let mut picked = vec![];
+    for i in 0..20 {
+        let channel_name = format!("live/{}", i);
+        let first = edge
+            .pick_origin(&channel_name, PickPolicy::ConsistentHash)
+            .await
+            .unwrap()
+            .unwrap();
+        let again = edge
+            .pick_origin(&channel_name, PickPolicy::ConsistentHash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.node_id, again.node_id);
+        picked.push(first.node_id);
+    }
+    picked.sort();
+    picked.dedup();
+    assert!(picked.len() > 1);
+}
@@ -0,0 +1,117 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time;
+use xcore::register_client::{RegisterClient, RegisterClientConfig};
+use xlive_register::Config;
+
+fn snapshot_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("xlive-register-{}.json", name));
+    _ = std::fs::remove_file(&path);
+    path
+}
+
+async fn register(addr: &str, http_addr: &str, snapshot: &Path, channel_ttl: u64) {
+    let mut config = Config::new(addr, http_addr.parse().unwrap());
+    config.snapshot = Some(snapshot.to_owned());
+    config.channel_ttl = Duration::from_secs(channel_ttl);
+    tokio::spawn(xlive_register::run(config));
+    time::sleep(Duration::from_millis(200)).await;
+}
+
+async fn http_get(addr: &str, path: &str) -> serde_json::Value {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, addr);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    serde_json::from_str(body).unwrap()
+}
+
+//a raw heartbeat of origin-a, which does not set the channel first
+async fn heartbeat(addr: &str, channel_name: &str) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let heartbeat = xcore::register::Register {
+        id: 0,
+        kind: xcore::register::RegisterKind::Heartbeat,
+        channel_name: "".to_owned(),
+        channels: vec![channel_name.to_owned()],
+        node_id: "origin-a".to_owned(),
+        addr: "10.0.0.1:9878".to_owned(),
+        relayed: false,
+        load: Default::default(),
+        policy: Default::default(),
+        role: None,
+        monitor_addr: "".to_owned(),
+    };
+    let buf: bytes::Bytes = heartbeat.try_into().unwrap();
+    socket.send_to(&buf, addr).await.unwrap();
+}
+
+//the first register saves origin-a holding live/room, it keeps sending heartbeats
+async fn saved(addr: &str, http_addr: &str, snapshot: &Path) -> RegisterClient {
+    register(addr, http_addr, snapshot, 10).await;
+    let mut config = RegisterClientConfig::new(addr);
+    config.node_id = "origin-a".to_owned();
+    config.advertise = "10.0.0.1:9878".to_owned();
+    let client = RegisterClient::connect(config).await.unwrap();
+    client.set("live/room");
+    //saved on the next sweep
+    for _ in 0..30 {
+        time::sleep(Duration::from_millis(100)).await;
+        if snapshot.exists() {
+            return client;
+        }
+    }
+    panic!("no snapshot at {}", snapshot.display());
+}
+
+#[tokio::test]
+async fn restored_channels_wait_for_their_origin() {
+    let snapshot = snapshot_path("29396");
+    let _client = saved("127.0.0.1:29397", "127.0.0.1:29497", &snapshot).await;
+
+    //a restart, on other ports as the first one keeps running
+    register("127.0.0.1:29398", "127.0.0.1:29498", &snapshot, 10).await;
+    let channels = http_get("127.0.0.1:29498", "/channels_info").await;
+    let owner = &channels["channels"]["live/room"];
+    assert_eq!(owner["node_id"], "origin-a");
+    assert_eq!(owner["addr"], "10.0.0.1:9878");
+    assert_eq!(owner["confirmed"], false);
+
+    //the heartbeat of its owner confirms it
+    heartbeat("127.0.0.1:29398", "live/room").await;
+    time::sleep(Duration::from_millis(200)).await;
+    let channels = http_get("127.0.0.1:29498", "/channels_info").await;
+    assert_eq!(channels["channels"]["live/room"]["confirmed"], true);
+    _ = std::fs::remove_file(snapshot);
+}
+
+#[tokio::test]
+async fn unconfirmed_channels_expire() {
+    let snapshot = snapshot_path("29426");
+    let _client = saved("127.0.0.1:29427", "127.0.0.1:29527", &snapshot).await;
+
+    register("127.0.0.1:29428", "127.0.0.1:29528", &snapshot, 1).await;
+    let channels = http_get("127.0.0.1:29528", "/channels_info").await;
+    assert_eq!(channels["channels"]["live/room"]["confirmed"], false);
+    //no heartbeat arrives within the ttl
+    time::sleep(Duration::from_millis(2500)).await;
+    let channels = http_get("127.0.0.1:29528", "/channels_info").await;
+    assert!(channels["channels"].get("live/room").is_none());
+    _ = std::fs::remove_file(snapshot);
+}
+
+#[tokio::test]
+async fn heartbeats_do_not_rewrite_the_snapshot() {
+    let snapshot = snapshot_path("29436");
+    let _client = saved("127.0.0.1:29437", "127.0.0.1:29537", &snapshot).await;
+    let modified = || std::fs::metadata(&snapshot).unwrap().modified().unwrap();
+    let written = modified();
+    //a heartbeat every second
+    time::sleep(Duration::from_millis(2500)).await;
+    assert_eq!(modified(), written);
+    _ = std::fs::remove_file(&snapshot);
+}
@@ -0,0 +1,145 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time;
+use xcore::register_client::{RegisterClient, RegisterClientConfig};
+use xlive_register::register::Owner;
+use xlive_register::storage::{MemoryStorage, RedisStorage, Storage};
+use xlive_register::Config;
+
+//the redis tests need a local redis-server: cargo test -- --ignored
+fn redis_url() -> String {
+    std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_owned())
+}
+
+//each test works under its own keys
+fn prefix(name: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    format!("xlive-test-{}-{}", name, nanos)
+}
+
+fn owner(node_id: &str) -> Owner {
+    Owner {
+        node_id: node_id.to_owned(),
+        addr: "10.0.0.1:9878".to_owned(),
+        timestamp: 1,
+        confirmed: true,
+        epoch: 1,
+        notify: "".to_owned(),
+    }
+}
+
+async fn check_storage<S: Storage>(mut storage: S) {
+    assert!(storage.get_channel("live/a").await.unwrap().is_none());
+    assert!(storage
+        .set_channel("live/a", &owner("n1"))
+        .await
+        .unwrap()
+        .is_none());
+    let old = storage.set_channel("live/a", &owner("n2")).await.unwrap();
+    assert_eq!(old.unwrap().node_id, "n1");
+    assert_eq!(
+        storage
+            .get_channel("live/a")
+            .await
+            .unwrap()
+            .unwrap()
+            .node_id,
+        "n2"
+    );
+    let channels = storage.channels().await.unwrap();
+    assert_eq!(channels.len(), 1);
+    assert!(channels.contains_key("live/a"));
+
+    assert_eq!(
+        storage
+            .remove_channel("live/a")
+            .await
+            .unwrap()
+            .unwrap()
+            .node_id,
+        "n2"
+    );
+    //removed once only, by whoever gets there first
+    assert!(storage.remove_channel("live/a").await.unwrap().is_none());
+    assert!(storage.channels().await.unwrap().is_empty());
+
+    assert!(storage.touch_server("10.0.0.1:9878", 1).await.unwrap());
+    assert!(!storage.touch_server("10.0.0.1:9878", 2).await.unwrap());
+    assert_eq!(storage.servers().await.unwrap()["10.0.0.1:9878"], 2);
+    assert!(storage.remove_server("10.0.0.1:9878").await.unwrap());
+    assert!(!storage.remove_server("10.0.0.1:9878").await.unwrap());
+}
+
+#[tokio::test]
+async fn memory_storage() {
+    check_storage(MemoryStorage::default()).await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn redis_storage() {
+    let storage = RedisStorage::connect(&redis_url(), &prefix("storage"), Duration::from_secs(30))
+        .await
+        .unwrap();
+    check_storage(storage).await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn redis_register_publishes_changes() {
+    let prefix = prefix("register");
+    let client = redis::Client::open(redis_url()).unwrap();
+    let mut pubsub = client.get_async_connection().await.unwrap().into_pubsub();
+    pubsub
+        .subscribe(format!("{}:events", prefix))
+        .await
+        .unwrap();
+
+    let mut config = Config::new("127.0.0.1:29366", "127.0.0.1:29466".parse().unwrap());
+    config.redis_url = Some(redis_url());
+    config.redis_prefix = prefix.clone();
+    tokio::spawn(xlive_register::run(config));
+    time::sleep(Duration::from_millis(200)).await;
+
+    let mut origin = RegisterClientConfig::new("127.0.0.1:29366");
+    origin.node_id = "origin-1".to_owned();
+    origin.advertise = "10.0.0.1:9878".to_owned();
+    let origin = RegisterClient::connect(origin).await.unwrap();
+    origin.set("live/room");
+
+    //the server comes up first, then the channel
+    let mut messages = pubsub.on_message();
+    let mut events = vec![];
+    for _ in 0..2 {
+        let message = time::timeout(
+            Duration::from_secs(2),
+            futures::StreamExt::next(&mut messages),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let payload: String = message.get_payload().unwrap();
+        events.push(serde_json::from_str::<serde_json::Value>(&payload).unwrap());
+    }
+    assert_eq!(events[1]["event"], "channel_up");
+    assert_eq!(events[1]["channel"], "live/room");
+
+    //other services read the leases, they expire when no register refreshes them
+    let mut conn = client.get_async_connection().await.unwrap();
+    let key = format!("{}:channel:live/room", prefix);
+    let ttl: i64 = redis::cmd("TTL")
+        .arg(&key)
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    assert!(ttl > 0);
+    let value: String = redis::cmd("GET")
+        .arg(&key)
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    let owner: Owner = serde_json::from_str(&value).unwrap();
+    assert_eq!(owner.node_id, "origin-1");
+}